@@ -0,0 +1,39 @@
+use std::{env, fs};
+
+/// Dependencies whose versions get reported by `bpm --version --detailed`, since they decide how assets get read and
+/// written
+const REPORTED_DEPENDENCIES: &[(&str, &str)] = &[
+    ("bevy", "BPM_BEVY_VERSION"),
+    ("gltf_kun", "BPM_GLTF_KUN_VERSION"),
+    ("image", "BPM_IMAGE_VERSION"),
+    ("libktx-rs", "BPM_LIBKTX_VERSION"),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    for (name, var) in REPORTED_DEPENDENCIES {
+        let version = get_locked_version(&lock, name).unwrap_or_else(|| "unknown".into());
+        println!("cargo:rustc-env={}={}", var, version);
+    }
+    for var in ["TARGET", "PROFILE"] {
+        println!(
+            "cargo:rustc-env=BPM_BUILD_{}={}",
+            var,
+            env::var(var).unwrap_or_else(|_| "unknown".into())
+        );
+    }
+}
+
+/// Reads the resolved version from the `[[package]]` entry that follows `name = "..."`
+fn get_locked_version(lock: &str, name: &str) -> Option<String> {
+    let mut lines = lock.lines();
+    let package = format!("name = \"{}\"", name);
+    lines.find(|line| line.trim() == package)?;
+    lines
+        .next()?
+        .trim()
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+        .map(str::to_string)
+}
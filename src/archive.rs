@@ -0,0 +1,117 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use bevy::{prelude::*, tasks::IoTaskPool};
+use zip::ZipArchive;
+
+use crate::{
+    config::Config,
+    manifest::Manifest,
+    processing::{
+        FileProcessed, FileQueuedForProcessing, ProcessingKind, ProcessingTask, ProcessingType,
+        TEMP_FILE_SUFFIX,
+    },
+    profiling::{ProcessingStage, StageTimings},
+};
+
+#[derive(Component)]
+pub struct FileArchive;
+
+/// Extracts archives in the source tree into `archives.cache_dir` (`kenney_pack.zip` => `<cache_dir>/kenney_pack/`).
+/// Scans walk the cache alongside `source_dir`, so the extracted files get processed like any other source files and
+/// their outputs end up under `kenney_pack/` in the output directory
+pub struct ProcessingArchive;
+
+impl ProcessingType for ProcessingArchive {
+    type Comp = FileArchive;
+
+    fn get_component() -> Self::Comp {
+        FileArchive
+    }
+
+    fn matches(ext: &String, config: &Config) -> bool {
+        ext == "zip" || config.is_mapped_extension(ProcessingKind::Archive, ext)
+    }
+
+    fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf> {
+        // unlike other processing types, this destination is a directory outside the output directory
+        config.get_archive_extract_dir(source)
+    }
+
+    fn is_enabled(config: &Config) -> bool {
+        config.archives.enabled
+    }
+
+    fn system(
+        query: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
+        _: Res<Config>,
+        mut commands: Commands,
+        _: EventWriter<FileProcessed>,
+        _: ResMut<StageTimings>,
+        _: Query<(&FileQueuedForProcessing, &ProcessingTask)>,
+    ) {
+        for (e, entry) in query.iter() {
+            let source = entry.source.clone();
+            let dest = entry.dest.clone();
+            let task = IoTaskPool::get().spawn(async move {
+                let mut timings = StageTimings::default();
+                let result =
+                    timings.time(ProcessingStage::Import, || extract_archive(&source, &dest));
+                (result, timings)
+            });
+            commands
+                .entity(e)
+                .remove::<FileArchive>()
+                .insert(ProcessingTask::new(ProcessingKind::Archive, task));
+        }
+    }
+}
+
+/// Extracts next to the previous extraction and swaps it in, so files dropped from the archive don't linger and a
+/// broken archive leaves the previous contents in place
+fn extract_archive(source: &PathBuf, dest: &PathBuf) -> Result<(), String> {
+    let file = fs::File::open(source).map_err(|err| format!("Failed to open archive: {}", err))?;
+    let mut archive =
+        ZipArchive::new(file).map_err(|err| format!("Failed to read archive: {}", err))?;
+    let mut temp_dir = dest.clone().into_os_string();
+    temp_dir.push(TEMP_FILE_SUFFIX);
+    let temp_dir = PathBuf::from(temp_dir);
+    remove_dir(&temp_dir)?;
+    fs::create_dir_all(&temp_dir)
+        .map_err(|err| format!("Failed to create extraction directory: {}", err))?;
+    // `extract` rejects entries that would escape the destination directory
+    if let Err(err) = archive.extract(&temp_dir) {
+        let _ = fs::remove_dir_all(&temp_dir);
+        return Err(format!("Failed to extract archive: {}", err));
+    }
+    remove_dir(dest)?;
+    fs::rename(&temp_dir, dest).map_err(|err| format!("Failed to move extracted archive: {}", err))
+}
+
+fn remove_dir(path: &Path) -> Result<(), String> {
+    match fs::remove_dir_all(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(format!(
+            "Failed to remove {}: {}",
+            path.display(),
+            err
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Removes extracted archives whose archive is no longer in the source tree, so their contents stop being processed
+/// and their outputs become orphans. Returns the removed directories
+pub fn remove_orphaned_extractions(manifest: &Manifest, config: &Config) -> Vec<PathBuf> {
+    manifest
+        .entries
+        .iter()
+        .filter(|(source, entry)| {
+            !source.exists() && entry.dest.starts_with(&config.archives.cache_dir)
+        })
+        .filter(|(_, entry)| fs::remove_dir_all(&entry.dest).is_ok())
+        .map(|(_, entry)| entry.dest.clone())
+        .collect()
+}
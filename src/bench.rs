@@ -0,0 +1,68 @@
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
+};
+
+use crate::{config::Config, processing::get_human_duration};
+
+/// A synthetic processing pass started by `bpm bench`, run against generated files in a scratch directory
+pub struct BenchRun {
+    root: PathBuf,
+    files: usize,
+    bytes: usize,
+    start: Instant,
+}
+
+impl BenchRun {
+    /// Writes `files` raw files of `bytes` each into a scratch directory and points the config at it.
+    /// The run is timed from here, so startup is included the same way it is for a real oneshot run
+    pub fn generate(config: &mut Config, files: usize, bytes: usize) -> Result<Self, String> {
+        let ext = config
+            .extensions
+            .raw
+            .first()
+            .cloned()
+            .ok_or("bench generates raw files, but no raw extensions are configured")?;
+        let root = env::temp_dir().join(format!("bpm-bench-{}", process::id()));
+        let source_dir = root.join("source");
+        fs::create_dir_all(&source_dir)
+            .map_err(|err| format!("Failed to create bench directory: {}", err))?;
+        let contents = (0..bytes).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        for i in 0..files {
+            // spread files over subdirectories like a real asset tree
+            let path = source_dir.join(format!("{:03}/file_{:06}.{}", i % 100, i, ext));
+            let _ = fs::create_dir_all(path.parent().unwrap_or(&source_dir));
+            fs::write(&path, &contents)
+                .map_err(|err| format!("Failed to write bench file: {}", err))?;
+        }
+        config.source_dir = source_dir;
+        config.output_dir = root.join("output");
+        config.file_watching_rate_seconds = 0.01;
+        Ok(Self {
+            root,
+            files,
+            bytes,
+            start: Instant::now(),
+        })
+    }
+
+    /// Prints throughput for the pass and removes the scratch directory
+    pub fn finish(self) {
+        let elapsed = self.start.elapsed().max(Duration::from_millis(1));
+        let seconds = elapsed.as_secs_f64();
+        println!(
+            "Processed {} files ({} bytes each) in {}",
+            self.files,
+            self.bytes,
+            get_human_duration(elapsed)
+        );
+        println!(
+            "{:.1} files/sec, {:.1} MiB/sec",
+            self.files as f64 / seconds,
+            (self.files * self.bytes) as f64 / seconds / (1024.0 * 1024.0)
+        );
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
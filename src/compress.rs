@@ -0,0 +1,63 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use bevy::prelude::*;
+use flate2::write::GzEncoder;
+
+use crate::{
+    config::{Compression, Config},
+    processing::{write_output, FileProcessed},
+};
+
+/// Writes a compressed copy next to each successfully processed output (`foo.png` => `foo.png.br`), for web servers
+/// that serve precompressed assets
+pub fn precompress_outputs(mut events: EventReader<FileProcessed>, config: Res<Config>) {
+    let Some(compression) = &config.precompress else {
+        events.clear();
+        return;
+    };
+    for event in events.read() {
+        if event.result.is_err() || !event.dest.is_file() {
+            continue;
+        }
+        if let Err(err) = write_compressed(&event.dest, compression) {
+            error!("Failed to precompress {}: {}", event.dest.display(), err);
+        }
+    }
+}
+
+fn write_compressed(dest: &PathBuf, compression: &Compression) -> io::Result<()> {
+    let bytes = fs::read(dest)?;
+    let compressed = match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(&bytes)?;
+            encoder.finish()?
+        }
+        Compression::Brotli => {
+            let mut compressed = Vec::new();
+            {
+                // quality 11 and a 4MiB window are brotli's maximums. Assets are compressed once and served many times
+                let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+                encoder.write_all(&bytes)?;
+            }
+            compressed
+        }
+    };
+    write_output(&get_compressed_path(dest, compression), compressed)
+}
+
+/// `foo.png` => `foo.png.br`
+pub fn get_compressed_path(dest: &PathBuf, compression: &Compression) -> PathBuf {
+    let ext = match compression {
+        Compression::Gzip => "gz",
+        Compression::Brotli => "br",
+    };
+    let mut path = dest.clone().into_os_string();
+    path.push(".");
+    path.push(ext);
+    PathBuf::from(path)
+}
@@ -1,12 +1,197 @@
-use bevy::prelude::Resource;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Component, Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use bevy::{log::error, prelude::Resource};
 use serde::{Deserialize, Serialize};
 
+use crate::processing::ProcessingKind;
+
 #[derive(Serialize, Deserialize, Resource, Debug, Clone)]
 pub struct Config {
+    /// The staging directory holding the unprocessed assets. Supports `${VAR}` interpolation. Can also be an
+    /// `s3://bucket/prefix` or `http(s)://` location, which gets mirrored into `remote_cache_dir`
+    #[serde(default = "default_source_dir")]
+    pub source_dir: PathBuf,
+    /// Where a remote `source_dir` is mirrored to before processing
+    #[serde(default = "default_remote_cache_dir")]
+    pub remote_cache_dir: PathBuf,
+    /// How often a remote `source_dir` is synced again while watching
+    #[serde(default = "default_remote_sync_seconds")]
+    pub remote_sync_seconds: f64,
+    /// The directory processed assets are written to. Supports `${VAR}` interpolation
+    #[serde(default = "default_output_dir")]
+    pub output_dir: PathBuf,
     pub file_watching_rate_seconds: f64,
+    /// Caps how many new files each scan can queue, so huge first runs get processed in waves
+    #[serde(default)]
+    pub max_queue_per_scan: Option<usize>,
+    /// Warns when the number of files waiting to be processed grows on this many scans in a row. `0` disables it
+    #[serde(default = "default_backlog_warning_scans")]
+    pub backlog_warning_scans: u32,
+    /// How the manifest is stored. `Lines` writes one sorted entry per line and marks it for git's union merge driver,
+    /// so branches that each process different assets merge without conflicts
+    #[serde(default)]
+    pub manifest_format: ManifestFormat,
+    /// Reloads each processed output after writing it, failing the file if the output can't be read back
+    #[serde(default)]
+    pub verify_outputs: bool,
+    /// What to do with a file when its source or destination metadata can't be read
+    #[serde(default)]
+    pub on_metadata_error: MetadataErrorPolicy,
+    /// Writes a Bevy `.meta` sidecar next to each processed texture and mesh encoding its loader settings
+    #[serde(default = "default_enabled")]
+    pub generate_meta: bool,
     pub extensions: Extensions,
+    /// Routes extra extensions to an existing processor, e.g. `[[processors]]` with
+    /// `extensions = ["dds"]` and `kind = "texture"`. Checked alongside the built-in `extensions` lists
+    #[serde(default)]
+    pub processors: Vec<ProcessorMapping>,
+    /// Files reprocessed once every run regardless of timestamps. Patterns containing a `/` match the path relative to
+    /// `source_dir`, others match the file name. `*` matches any run of characters, e.g. `["CHANGELOG.md", "data/*.json"]`
+    #[serde(default)]
+    pub always_process: Vec<String>,
+    /// Files matching a pattern are queued and processed ahead of lower priority files, using the highest matching
+    /// priority. Unmatched files have priority 0. Patterns work like `always_process`, e.g. `"ui/*" = 10`
+    #[serde(default)]
+    pub priorities: BTreeMap<String, i32>,
+    /// Also writes a compressed copy of each output (`.gz` or `.br`) for web builds
+    #[serde(default)]
+    pub precompress: Option<Compression>,
+    /// Appends a row of timings to this CSV at the end of every run, e.g. `"build-metrics.csv"`, for plotting build
+    /// time trends. Supports `${VAR}` interpolation
+    #[serde(default)]
+    pub metrics_csv: Option<PathBuf>,
+    /// Warns about source files sharing a file name across directories, once per file
+    #[serde(default = "default_enabled")]
+    pub report_duplicate_basenames: bool,
+    /// How many directories deep scans descend below `source_dir`, guarding against deep or looping symlinked trees.
+    /// Unlimited when unset
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// What happens when two sources map to outputs that only differ by case
+    #[serde(default)]
+    pub case_collision_policy: CaseCollisionPolicy,
+    /// When existing outputs get replaced. `Never` protects hand-edited outputs, only writing ones that are missing
+    #[serde(default)]
+    pub overwrite_policy: OverwritePolicy,
+    /// What happens to empty or truncated image, mesh and audio files
+    #[serde(default)]
+    pub invalid_media_policy: InvalidMediaPolicy,
+    /// Whether a placeholder 2D camera is spawned. `Auto` only spawns it when watching with a render device
+    #[serde(default)]
+    pub dummy_camera: DummyCamera,
+    /// What happens to source files no processor handles
+    #[serde(default)]
+    pub unknown_extension_policy: UnknownExtensionPolicy,
+    /// Overrides the extension each processor writes its outputs with. The encoder follows the extension
+    #[serde(default)]
+    pub output_extensions: OutputExtensions,
+    /// Replaces mirroring the source path with a template per processor, relative to `output_dir`. Placeholders are
+    /// `{dir}` (the source's directory relative to `source_dir`), `{name}`, `{ext}`, and `{hash}` (of the source's
+    /// contents). Textures also get `{w}` and `{h}`, their processed size. e.g. `texture = "{dir}/{name}_{w}x{h}.{ext}"`
+    #[serde(default)]
+    pub output_templates: OutputTemplates,
+    #[serde(default)]
+    pub raw: RawConfigs,
+    #[serde(default)]
+    pub archives: ArchiveConfigs,
+    #[serde(default)]
+    pub shaders: ShaderConfigs,
+    #[serde(default)]
+    pub environments: EnvironmentConfigs,
+    #[serde(default)]
+    pub fonts: FontConfigs,
+    #[serde(default)]
+    pub telemetry: TelemetryConfigs,
+    #[serde(default)]
+    pub preload: PreloadConfigs,
+    #[serde(default)]
+    pub task_pools: TaskPoolConfigs,
     pub meshes: MeshConfigs,
     pub textures: TextureConfigs,
+    /// Named sets of settings layered over the rest of the config, e.g. `[profiles.mobile]` with its own `output_dir`
+    /// and `[profiles.mobile.textures]`
+    #[serde(default)]
+    pub profiles: BTreeMap<String, toml::Table>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub enum MetadataErrorPolicy {
+    /// Treat the file as stale and process it again
+    #[default]
+    Reprocess,
+    /// Leave the file alone until its metadata can be read
+    Skip,
+    /// Quietly leave the file for the next scan to read again, for flaky network shares
+    Retry,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Compression {
+    Gzip,
+    Brotli,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ManifestFormat {
+    /// A single pretty printed `.bpm-manifest.json`
+    #[default]
+    Json,
+    /// `.bpm-manifest.jsonl`, one source per line sorted by path
+    Lines,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum OverwritePolicy {
+    /// Regenerate every output once per run, stale or not
+    Always,
+    /// Regenerate outputs that are out of date
+    #[default]
+    IfStale,
+    /// Only write outputs that don't exist yet, warning about stale ones that were left alone
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub enum InvalidMediaPolicy {
+    /// Leave the file unprocessed with a warning, checking it again on every scan. Covers files that are still
+    /// being written when a scan sees them
+    #[default]
+    Skip,
+    /// Queue it anyway, recording a failure with the reason
+    Fail,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub enum DummyCamera {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub enum CaseCollisionPolicy {
+    /// Log a warning and process both, so the later source wins on case-insensitive filesystems
+    #[default]
+    Warn,
+    /// Log an error and skip the later source
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub enum UnknownExtensionPolicy {
+    /// Leave the file out of the output
+    #[default]
+    Ignore,
+    /// Copy the file verbatim through the raw processor, so nothing in the source is lost
+    Copy,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -15,23 +200,839 @@ pub struct Extensions {
     pub texture: Vec<String>,
     pub mesh: Vec<String>,
     pub audio: Vec<String>,
+    #[serde(default = "default_shader_extensions")]
+    pub shader: Vec<String>,
+    #[serde(default = "default_environment_extensions")]
+    pub environment: Vec<String>,
+    #[serde(default = "default_font_extensions")]
+    pub font: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessorMapping {
+    pub extensions: Vec<String>,
+    pub kind: ProcessingKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawConfigs {
+    /// Whether the raw processor is registered at all
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Copies the source modification time onto raw outputs
+    #[serde(default)]
+    pub preserve_mtime: bool,
+    /// Copies the source permission bits (like `+x`) onto raw outputs. Only has an effect on Unix
+    #[serde(default)]
+    pub preserve_mode: bool,
+    /// Files larger than this are skipped with a warning instead of copied
+    #[serde(default)]
+    pub max_size_bytes: Option<u64>,
+}
+
+impl Default for RawConfigs {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            preserve_mtime: false,
+            preserve_mode: false,
+            max_size_bytes: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchiveConfigs {
+    /// Extracts `.zip` files in the source tree so their contents get processed
+    #[serde(default)]
+    pub enabled: bool,
+    /// Where archives are extracted to, mirroring their location under `source_dir`. Scanned alongside `source_dir`,
+    /// so `packs/kenney_pack.zip` gets its contents written to `packs/kenney_pack/` in the output. Supports `${VAR}`
+    /// interpolation
+    #[serde(default = "default_archive_cache_dir")]
+    pub cache_dir: PathBuf,
+}
+
+impl Default for ArchiveConfigs {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cache_dir: default_archive_cache_dir(),
+        }
+    }
+}
+
+fn default_archive_cache_dir() -> PathBuf {
+    PathBuf::from(".bpm-cache/archives")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShaderConfigs {
+    /// Validates wgsl shaders with naga and only copies them to the output when they pass. `#ifdef`s and `#import`s of
+    /// modules defined in the source tree are resolved with naga_oil first, the way Bevy loads them
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EnvironmentConfigs {
+    /// Converts equirectangular HDR panoramas into cubemap `.ktx2` files
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Width and height of each cube face in pixels
+    #[serde(default = "default_face_size")]
+    pub face_size: u32,
+}
+
+impl Default for EnvironmentConfigs {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            face_size: default_face_size(),
+        }
+    }
+}
+
+fn default_face_size() -> u32 {
+    512
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FontConfigs {
+    /// Validates TrueType and OpenType fonts and only copies them to the output when they parse
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Also renders a glyph atlas next to each font, for text drawn from sprites or custom shaders
+    #[serde(default)]
+    pub atlas: Option<FontAtlasConfigs>,
+}
+
+impl Default for FontConfigs {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            atlas: None,
+        }
+    }
+}
+
+/// A glyph atlas is written as `<name>.atlas.png`, with each glyph's rect and metrics in `<name>.atlas.json`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FontAtlasConfigs {
+    /// Every character rendered into the atlas. Printable ASCII by default
+    #[serde(default = "default_atlas_characters")]
+    pub characters: String,
+    /// Glyph size in pixels
+    #[serde(default = "default_atlas_font_size")]
+    pub size: f32,
+    /// Atlas width in pixels. The height grows to fit every glyph
+    #[serde(default = "default_atlas_width")]
+    pub width: u32,
+    /// Empty pixels around each glyph, so neighbours don't bleed in when sampling. Should cover `sdf.spread`
+    #[serde(default = "default_atlas_padding")]
+    pub padding: u32,
+    /// Stores the atlas as a signed distance field instead of coverage, for text that stays sharp at any scale
+    #[serde(default)]
+    pub sdf: Option<SdfConfigs>,
+}
+
+impl Default for FontAtlasConfigs {
+    fn default() -> Self {
+        Self {
+            characters: default_atlas_characters(),
+            size: default_atlas_font_size(),
+            width: default_atlas_width(),
+            padding: default_atlas_padding(),
+            sdf: None,
+        }
+    }
+}
+
+impl FontAtlasConfigs {
+    fn validate(&self) -> Result<(), String> {
+        if !(self.size > 0.0) {
+            return Err(format!(
+                "Invalid fonts.atlas.size {}. Expected > 0",
+                self.size
+            ));
+        }
+        if self.width == 0 {
+            return Err("fonts.atlas.width must be at least 1".into());
+        }
+        if let Some(sdf) = &self.sdf {
+            sdf.validate()?;
+        }
+        Ok(())
+    }
+}
+
+fn default_atlas_characters() -> String {
+    (' '..='~').collect()
+}
+
+fn default_atlas_font_size() -> f32 {
+    32.0
+}
+
+fn default_atlas_width() -> u32 {
+    512
+}
+
+fn default_atlas_padding() -> u32 {
+    2
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OutputExtensions {
+    /// Renames copied files. Keeps the source extension when unset
+    #[serde(default)]
+    pub raw: Option<String>,
+    /// Any format the image crate can write, or `ktx2` alongside `textures.ktx2`. Keeps the source extension when unset.
+    /// Setting it normalizes mixed sources, e.g. `"png"` converts jpg sources so every texture output is a png
+    #[serde(default)]
+    pub texture: Option<String>,
+    /// `glb` or `gltf`. Follows the first `meshes.storage` format when unset
+    #[serde(default)]
+    pub mesh: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OutputTemplates {
+    #[serde(default)]
+    pub raw: Option<String>,
+    #[serde(default)]
+    pub texture: Option<String>,
+    #[serde(default)]
+    pub mesh: Option<String>,
+    #[serde(default)]
+    pub shader: Option<String>,
+    #[serde(default)]
+    pub environment: Option<String>,
+    #[serde(default)]
+    pub font: Option<String>,
+}
+
+impl OutputTemplates {
+    pub fn get(&self, kind: ProcessingKind) -> Option<&String> {
+        match kind {
+            ProcessingKind::Raw => self.raw.as_ref(),
+            ProcessingKind::Texture => self.texture.as_ref(),
+            ProcessingKind::Mesh => self.mesh.as_ref(),
+            ProcessingKind::Shader => self.shader.as_ref(),
+            ProcessingKind::Environment => self.environment.as_ref(),
+            ProcessingKind::Font => self.font.as_ref(),
+            ProcessingKind::Audio | ProcessingKind::Archive => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        [
+            &self.raw,
+            &self.texture,
+            &self.mesh,
+            &self.shader,
+            &self.environment,
+            &self.font,
+        ]
+        .iter()
+        .all(|template| template.is_none())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for kind in [
+            ProcessingKind::Raw,
+            ProcessingKind::Texture,
+            ProcessingKind::Mesh,
+            ProcessingKind::Shader,
+            ProcessingKind::Environment,
+            ProcessingKind::Font,
+        ] {
+            let Some(template) = self.get(kind) else {
+                continue;
+            };
+            let placeholders = render_template(template, |name| Some(name.to_string()))
+                .ok_or_else(|| format!("Unclosed placeholder in output template '{}'", template))?;
+            if placeholders.split('/').any(|part| part == "..") {
+                return Err(format!(
+                    "Output template '{}' can't leave the output directory",
+                    template
+                ));
+            }
+            let mut names = vec!["dir", "name", "ext", "hash"];
+            if kind == ProcessingKind::Texture {
+                names.extend(["w", "h"]);
+            }
+            let mut unknown = None;
+            render_template(template, |name| {
+                if !names.contains(&name) {
+                    unknown = Some(name.to_string());
+                }
+                Some(String::new())
+            });
+            if let Some(name) = unknown {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in output template '{}'. Expected one of {:?}",
+                    name, template, names
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replaces each `{placeholder}` with what `get_value` returns for it. `None` when a placeholder is unclosed or has
+/// no value
+fn render_template(
+    template: &str,
+    mut get_value: impl FnMut(&str) -> Option<String>,
+) -> Option<String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..].find('}')? + start;
+        rendered.push_str(&get_value(&rest[start + 1..end])?);
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    Some(rendered)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TelemetryConfigs {
+    /// Exports processing spans over OTLP/HTTP to this collector, e.g. `"http://localhost:4318/v1/traces"`. The
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable takes precedence
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// Sizes Bevy's task pools. Each share is a fraction of the available threads, and unset shares keep Bevy's defaults.
+/// IO and async compute are assigned first, then compute gets the rest
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TaskPoolConfigs {
+    /// Total threads to use across all pools. Defaults to every logical core
+    #[serde(default)]
+    pub max_threads: Option<usize>,
+    #[serde(default)]
+    pub io_share: Option<f32>,
+    /// Texture and mesh workers run on the async compute pool, so batch conversions want most threads here
+    #[serde(default)]
+    pub async_compute_share: Option<f32>,
+    #[serde(default)]
+    pub compute_share: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PreloadConfigs {
+    /// Writes `preload.json` to the output directory, listing every produced asset path for a loading state to fetch
+    #[serde(default)]
+    pub enabled: bool,
+    /// Only lists outputs with these extensions, e.g. `["png", "glb"]`. Lists everything when empty
+    #[serde(default)]
+    pub extensions: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MeshConfigs {
+    /// Whether the mesh processor is registered at all. Disabled mesh files fall through to other processors
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// How many meshes are processed at once on the async compute pool, which needs at least this many threads
+    /// through `task_pools.async_compute_share`. Only read from the main config
+    #[serde(default = "default_workers")]
+    pub workers: usize,
     pub use_meshlets: bool,
-    pub storage: MeshStorage,
+    /// Cluster sizes for meshlet generation when `use_meshlets` is enabled
+    #[serde(default)]
+    pub meshlets: MeshletConfigs,
+    /// One format like `"Glb"`, or several like `["Glb", "Gltf"]` to write each of them from the same source.
+    /// The first is the primary output
+    pub storage: MeshStorageTargets,
+    /// Decimation ratios for generated LODs, e.g. `[0.5, 0.25]` writes `foo_lod1.glb` and `foo_lod2.glb` next to `foo.glb`
+    #[serde(default)]
+    pub lod_levels: Vec<f32>,
+    /// Collapses materials with identical properties and textures into one shared material before export
+    #[serde(default)]
+    pub merge_duplicate_materials: bool,
+    /// Generates index buffers for unindexed primitives, merging duplicate vertices
+    #[serde(default)]
+    pub weld_vertices: bool,
+    /// How close vertex positions must be to weld together. Zero only welds exact duplicates
+    #[serde(default)]
+    pub weld_tolerance: f32,
+    /// Mirrors texture coordinates vertically (`v = 1 - v`), for sources exported with the V axis pointing up
+    #[serde(default)]
+    pub flip_uv_v: bool,
+    /// Mirrors geometry along Z and reverses triangle winding, converting left-handed sources to Bevy's right-handed space
+    #[serde(default)]
+    pub convert_handedness: bool,
+    /// Also writes a Bevy scene (`foo.scn.ron`) of each mesh's node hierarchy next to its output
+    #[serde(default)]
+    pub scene: bool,
+    /// Which gltf extensions are kept in exported meshes
+    #[serde(default)]
+    pub gltf_extensions: GltfExtensionConfigs,
+    /// Which extensions gltf_kun reads and writes at all. `Minimal` processes core gltf only, dropping extension data
+    /// like lights and vendor physics before any other step sees it
+    #[serde(default)]
+    pub extension_set: GltfExtensionSet,
+    /// Rewrites node, mesh and material names into labels that are safe to use in asset paths
+    #[serde(default)]
+    pub sanitize_names: Option<NameSanitizeConfigs>,
+    /// Removes cameras and lights from exported meshes, e.g. `["Camera", "Light"]`. Nodes left empty are removed too
+    #[serde(default)]
+    pub strip_nodes: Vec<NodeKind>,
+    /// Keeps the `extras` custom properties DCC tools export, like gameplay metadata. Turning it off strips them from
+    /// every object for smaller outputs
+    #[serde(default = "default_enabled")]
+    pub keep_extras: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum NodeKind {
+    Camera,
+    /// Punctual lights from `KHR_lights_punctual`
+    Light,
 }
 
+/// The most triangles Bevy's meshlet rasterizer handles in a single cluster
+const MAX_MESHLET_TRIANGLES: u32 = 64;
+/// meshoptimizer stores per-meshlet vertex indices as bytes
+const MAX_MESHLET_VERTICES: u32 = 255;
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MeshletConfigs {
+    /// Most vertices referenced by one meshlet, 3 to 255
+    #[serde(default = "default_meshlet_size")]
+    pub max_vertices: u32,
+    /// Most triangles in one meshlet, a multiple of 4 up to 64
+    #[serde(default = "default_meshlet_size")]
+    pub max_triangles: u32,
+    /// How much clustering favours tight normal cones for culling over compact clusters, 0.0 to 1.0
+    #[serde(default)]
+    pub cone_weight: f32,
+}
+
+impl Default for MeshletConfigs {
+    fn default() -> Self {
+        Self {
+            max_vertices: default_meshlet_size(),
+            max_triangles: default_meshlet_size(),
+            cone_weight: 0.0,
+        }
+    }
+}
+
+impl MeshletConfigs {
+    fn validate(&self) -> Result<(), String> {
+        if !(3..=MAX_MESHLET_VERTICES).contains(&self.max_vertices) {
+            return Err(format!(
+                "Invalid meshlets.max_vertices {}. Expected 3..={}",
+                self.max_vertices, MAX_MESHLET_VERTICES
+            ));
+        }
+        if self.max_triangles == 0
+            || self.max_triangles > MAX_MESHLET_TRIANGLES
+            || self.max_triangles % 4 != 0
+        {
+            return Err(format!(
+                "Invalid meshlets.max_triangles {}. Expected a multiple of 4 up to {}",
+                self.max_triangles, MAX_MESHLET_TRIANGLES
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.cone_weight) {
+            return Err(format!(
+                "Invalid meshlets.cone_weight {}. Expected 0.0..=1.0",
+                self.cone_weight
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn default_meshlet_size() -> u32 {
+    64
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct NameSanitizeConfigs {
+    /// Replaces each run of characters other than ASCII letters, digits, `_` and `-`
+    #[serde(default = "default_name_replacement")]
+    pub replacement: String,
+    /// Lowercases names after replacing characters
+    #[serde(default)]
+    pub lowercase: bool,
+}
+
+fn default_name_replacement() -> String {
+    "_".into()
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum GltfExtensionSet {
+    /// Every extension gltf_kun supports, including vendor extensions like `OMI_physics_body`
+    #[default]
+    Default,
+    /// No extensions
+    Minimal,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct GltfExtensionConfigs {
+    /// When set, every extension not listed is stripped
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    /// Extensions removed from exported meshes, e.g. `["KHR_materials_emissive_strength"]`
+    #[serde(default)]
+    pub strip: Vec<String>,
+    /// Extensions that fail processing when a source uses them
+    #[serde(default)]
+    pub reject: Vec<String>,
+}
+
+impl MeshConfigs {
+    /// Formats written alongside the primary output, which has the extension `primary`
+    pub fn get_extra_extensions(&self, primary: &str) -> Vec<&'static str> {
+        let mut extensions = self
+            .storage
+            .targets()
+            .iter()
+            .map(|storage| storage.extension())
+            .filter(|ext| *ext != primary)
+            .collect::<Vec<_>>();
+        extensions.dedup();
+        extensions
+    }
+}
+
+impl GltfExtensionConfigs {
+    pub fn is_stripped(&self, name: &str) -> bool {
+        self.strip.iter().any(|stripped| stripped == name)
+            || self
+                .allow
+                .as_ref()
+                .is_some_and(|allow| !allow.iter().any(|allowed| allowed == name))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub enum MeshStorage {
     Glb,
     Gltf,
 }
 
+impl MeshStorage {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            MeshStorage::Glb => "glb",
+            MeshStorage::Gltf => "gltf",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MeshStorageTargets {
+    Single(MeshStorage),
+    Multiple(Vec<MeshStorage>),
+}
+
+impl MeshStorageTargets {
+    pub fn targets(&self) -> &[MeshStorage] {
+        match self {
+            MeshStorageTargets::Single(storage) => std::slice::from_ref(storage),
+            MeshStorageTargets::Multiple(storages) => storages,
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TextureConfigs {
+    /// Whether the texture processor is registered at all. Disabled texture files fall through to other processors
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// How many textures are encoded at once on the async compute pool, which needs at least this many threads
+    /// through `task_pools.async_compute_share`. Only read from the main config
+    #[serde(default = "default_workers")]
+    pub workers: usize,
     pub filter: TextureFilter,
+    /// Overrides `filter` for textures with the given source extension, e.g. `png = "Nearest"`
+    #[serde(default)]
+    pub filter_extensions: BTreeMap<String, TextureFilter>,
+    /// Overrides `filter` for textures whose file stem ends with the given suffix, e.g. `"_pixel" = "Nearest"`.
+    /// Takes precedence over `filter_extensions`
+    #[serde(default)]
+    pub filter_suffixes: BTreeMap<String, TextureFilter>,
+    /// Vertically mirrors processed images, for sources authored with the opposite V convention
+    #[serde(default)]
+    pub flip_vertical: bool,
+    /// Combines numbered image sequences (`walk_00.png`..`walk_07.png`) into a single image (`walk.png`), laid out by `sequence_layout`
+    #[serde(default)]
+    pub combine_sequences: bool,
+    /// How combined sequence frames are arranged. `Grid` packs them into a near square flipbook atlas
+    #[serde(default)]
+    pub sequence_layout: SequenceLayout,
+    /// Playback rate recorded in the `.sequence.json` of combined sequences
+    #[serde(default)]
+    pub sequence_fps: Option<f32>,
+    /// The sampler address mode written to each texture's `.meta`
+    #[serde(default)]
+    pub wrap_mode: TextureWrapMode,
+    /// Overrides `wrap_mode` for textures whose file stem ends with the given suffix, e.g. `"_tile" = "Repeat"`
+    #[serde(default)]
+    pub wrap_mode_suffixes: BTreeMap<String, TextureWrapMode>,
+    /// Reorders channels, e.g. `"bgra"` swaps red and blue. Each character picks the source channel for that output channel
+    #[serde(default)]
+    pub swizzle: Option<String>,
+    /// Overrides `swizzle` for textures whose file stem ends with the given suffix, e.g. `"_orm" = "rrrg"`
+    #[serde(default)]
+    pub swizzle_suffixes: BTreeMap<String, String>,
+    /// Downscales textures so neither side is larger than this, keeping the aspect ratio
+    #[serde(default)]
+    pub max_size: Option<u32>,
+    /// Treats textures as repeating when resizing, so seamless tiles stay seamless
+    #[serde(default)]
+    pub tiling: bool,
+    /// Resizes non power of two textures for GPU paths that need them
+    #[serde(default)]
+    pub force_pow2: Option<PowerOfTwoMode>,
+    /// RGBA fill for the area added by `force_pow2 = "Pad"`
+    #[serde(default)]
+    pub pow2_pad_color: [u8; 4],
+    /// Rejects source images with more pixels than this before decoding them, to avoid running out of memory
+    #[serde(default)]
+    pub max_source_pixels: Option<u64>,
+    /// Writes textures as Basis Universal compressed `.ktx2` instead of keeping the source format
+    #[serde(default)]
+    pub ktx2: Option<Ktx2Encoding>,
+    /// UASTC effort level, 0 (fastest) to 4 (slowest, best quality)
+    #[serde(default = "default_uastc_quality")]
+    pub uastc_quality: u32,
+    /// ETC1S quality level, 1 (smallest) to 255 (best quality)
+    #[serde(default = "default_etc1s_quality")]
+    pub etc1s_quality: u32,
+    /// Classifies textures whose file stem ends with the given suffix, e.g. `"_basecolor" = "Srgb"`, `"_normal" = "Linear"`.
+    /// Unclassified textures are treated as sRGB
+    #[serde(default)]
+    pub color_space_suffixes: BTreeMap<String, TextureColorSpace>,
+    /// Converts textures classified as `Srgb` to linear values and stores them as linear, so no conversion happens at runtime
+    #[serde(default)]
+    pub bake_linear: bool,
+    /// Appended to the file stem of every texture output, e.g. `"_compressed"` writes `foo.png` as `foo_compressed.png`
+    #[serde(default)]
+    pub output_suffix: Option<String>,
+    /// Fills fully transparent pixels with the colors of their visible neighbours to avoid dark fringes when filtered
+    #[serde(default)]
+    pub alpha_bleed: bool,
+    /// Extrudes the edge pixels outward by this many pixels on every side, so sprites packed into an atlas don't pick
+    /// up their neighbours when filtered. Applied after `max_size`, so the output grows by twice this
+    #[serde(default)]
+    pub border: u32,
+    /// Turns every texture into a signed distance field of its mask, for crisp UI shapes and decals at any scale
+    #[serde(default)]
+    pub sdf: Option<SdfConfigs>,
+    /// Turns textures whose file stem ends with the given suffix into signed distance fields, e.g. `"_sdf" = {}`
+    #[serde(default)]
+    pub sdf_suffixes: BTreeMap<String, SdfConfigs>,
+}
+
+/// Settings for generating a signed distance field. The output stores the distance to the mask's edge in every color
+/// channel, 0.5 on the edge and higher inside
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct SdfConfigs {
+    /// How far from the edge, in source pixels, distances are stored before clamping to 0 or 1
+    #[serde(default = "default_sdf_spread")]
+    pub spread: f32,
+    /// Output size relative to the source. Distances are computed at full resolution first, so a high resolution mask
+    /// stored at e.g. `0.25` keeps smooth edges
+    #[serde(default = "default_sdf_scale")]
+    pub scale: f32,
+    /// Which channel holds the mask
+    #[serde(default)]
+    pub channel: SdfChannel,
+    /// Mask values at or above this are inside the shape
+    #[serde(default = "default_sdf_threshold")]
+    pub threshold: f32,
+}
+
+impl Default for SdfConfigs {
+    fn default() -> Self {
+        Self {
+            spread: default_sdf_spread(),
+            scale: default_sdf_scale(),
+            channel: SdfChannel::default(),
+            threshold: default_sdf_threshold(),
+        }
+    }
+}
+
+impl SdfConfigs {
+    fn validate(&self) -> Result<(), String> {
+        if !(self.spread > 0.0) {
+            return Err(format!("Invalid sdf spread {}. Expected > 0", self.spread));
+        }
+        if !(self.scale > 0.0 && self.scale <= 1.0) {
+            return Err(format!(
+                "Invalid sdf scale {}. Expected > 0 and <= 1",
+                self.scale
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum SdfChannel {
+    /// Alpha for images with transparency, luminance for opaque black and white masks
+    #[default]
+    Auto,
+    Alpha,
+    Luminance,
+}
+
+fn default_sdf_spread() -> f32 {
+    8.0
+}
+
+fn default_sdf_scale() -> f32 {
+    1.0
+}
+
+fn default_sdf_threshold() -> f32 {
+    0.5
+}
+
+impl TextureConfigs {
+    pub fn get_wrap_mode(&self, path: &Path) -> TextureWrapMode {
+        get_suffix_override(&self.wrap_mode_suffixes, path, self.output_suffix.as_ref())
+            .map_or(self.wrap_mode.clone(), |mode| mode.clone())
+    }
+
+    /// The filter for a texture, resolved from its source path
+    pub fn get_filter(&self, source: &Path) -> TextureFilter {
+        get_suffix_override(&self.filter_suffixes, source, None)
+            .or_else(|| {
+                let ext = source.extension()?.to_str()?;
+                self.filter_extensions
+                    .iter()
+                    .find(|(mapped, _)| mapped.eq_ignore_ascii_case(ext))
+                    .map(|(_, filter)| filter)
+            })
+            .unwrap_or(&self.filter)
+            .clone()
+    }
+
+    pub fn get_swizzle(&self, path: &Path) -> Option<&String> {
+        get_suffix_override(&self.swizzle_suffixes, path, self.output_suffix.as_ref())
+            .or(self.swizzle.as_ref())
+    }
+
+    /// Whether the texture's pixel data gets converted from sRGB to linear. Only ever true for maps classified as sRGB,
+    /// so data that is already linear is never converted twice
+    pub fn should_bake_linear(&self, path: &Path) -> bool {
+        self.bake_linear
+            && matches!(
+                get_suffix_override(
+                    &self.color_space_suffixes,
+                    path,
+                    self.output_suffix.as_ref()
+                ),
+                Some(TextureColorSpace::Srgb)
+            )
+    }
+
+    pub fn get_sdf(&self, path: &Path) -> Option<&SdfConfigs> {
+        get_suffix_override(&self.sdf_suffixes, path, self.output_suffix.as_ref())
+            .or(self.sdf.as_ref())
+    }
+
+    /// Whether the output is stored as sRGB, and so needs decoding when sampled. Distance fields are data, never sRGB
+    pub fn is_srgb_output(&self, path: &Path) -> bool {
+        if self.get_sdf(path).is_some() {
+            return false;
+        }
+        match get_suffix_override(
+            &self.color_space_suffixes,
+            path,
+            self.output_suffix.as_ref(),
+        ) {
+            Some(TextureColorSpace::Linear) => false,
+            Some(TextureColorSpace::Srgb) => !self.bake_linear,
+            None => true,
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for swizzle in self.swizzle.iter().chain(self.swizzle_suffixes.values()) {
+            if swizzle.len() != 4 || !swizzle.chars().all(|c| "rgba".contains(c)) {
+                return Err(format!(
+                    "Invalid swizzle '{}'. Expected four of 'r', 'g', 'b', 'a'",
+                    swizzle
+                ));
+            }
+        }
+        if self.uastc_quality > 4 {
+            return Err(format!(
+                "Invalid uastc_quality {}. Expected 0..=4",
+                self.uastc_quality
+            ));
+        }
+        if !(1..=255).contains(&self.etc1s_quality) {
+            return Err(format!(
+                "Invalid etc1s_quality {}. Expected 1..=255",
+                self.etc1s_quality
+            ));
+        }
+        for sdf in self.sdf.iter().chain(self.sdf_suffixes.values()) {
+            sdf.validate()?;
+        }
+        if let Some(fps) = self.sequence_fps {
+            if !(fps > 0.0) {
+                return Err(format!("Invalid sequence_fps {}. Expected > 0", fps));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of characters
+fn matches_wildcard(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts = parts.collect::<Vec<_>>();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty(); // no wildcard, so the prefix had to be the whole text
+    };
+    for part in middle {
+        let Some(index) = rest.find(part) else {
+            return false;
+        };
+        rest = &rest[index + part.len()..];
+    }
+    rest.ends_with(last)
+}
+
+/// Finds the first entry whose key is a suffix of the path's file stem. `output_suffix` is ignored, so outputs
+/// resolve the same overrides as their sources
+fn get_suffix_override<'a, T>(
+    overrides: &'a BTreeMap<String, T>,
+    path: &Path,
+    output_suffix: Option<&String>,
+) -> Option<&'a T> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or_default();
+    let stem = output_suffix
+        .and_then(|suffix| stem.strip_suffix(suffix.as_str()))
+        .unwrap_or(stem);
+    overrides
+        .iter()
+        .find(|(suffix, _)| stem.ends_with(suffix.as_str()))
+        .map(|(_, value)| value)
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -39,39 +1040,596 @@ pub enum TextureFilter {
     Nearest,
     Linear,
 }
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum TextureColorSpace {
+    /// Color data like base color and emissive maps
+    Srgb,
+    /// Non-color data like normal, roughness, and occlusion maps
+    Linear,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum Ktx2Encoding {
+    /// High quality, larger files. Tuned by `uastc_quality`
+    Uastc,
+    /// Small files at lower quality. Tuned by `etc1s_quality`
+    Etc1s,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub enum PowerOfTwoMode {
+    /// Extends the canvas up to the next power of two, keeping the image in the top left corner
+    Pad,
+    /// Resamples each dimension to its nearest power of two
+    Scale,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum SequenceLayout {
+    /// Stacks frames vertically, one per row, so the output can be used as an array texture
+    #[default]
+    Strip,
+    /// Packs frames left to right, top to bottom into a grid atlas, matching `TextureAtlasLayout::from_grid`
+    Grid,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub enum TextureWrapMode {
+    Repeat,
+    #[default]
+    ClampToEdge,
+    MirrorRepeat,
+}
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct AudioConfigs {}
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            source_dir: default_source_dir(),
+            remote_cache_dir: default_remote_cache_dir(),
+            remote_sync_seconds: default_remote_sync_seconds(),
+            output_dir: default_output_dir(),
             file_watching_rate_seconds: 0.3,
+            max_queue_per_scan: None,
+            backlog_warning_scans: default_backlog_warning_scans(),
+            manifest_format: ManifestFormat::default(),
+            verify_outputs: false,
+            on_metadata_error: MetadataErrorPolicy::Reprocess,
+            generate_meta: true,
             extensions: Extensions {
                 raw: vec![],
                 texture: vec!["jpg".into(), "png".into()],
                 mesh: vec!["glb".into(), "gltf".into()],
                 audio: vec!["ogg".into(), "wav".into()],
+                shader: default_shader_extensions(),
+                environment: default_environment_extensions(),
+                font: default_font_extensions(),
             },
+            processors: vec![],
             // replicate_ext: vec![".*^[jpg|png|glb|gltf|wav|mp3]".into()],
             // ext_mesh: vec!["glb".into(), "gltf".into()],
             // regex_texture: vec![],
             // regex_audio: vec![],
+            always_process: vec![],
+            priorities: BTreeMap::new(),
+            precompress: None,
+            metrics_csv: None,
+            report_duplicate_basenames: true,
+            max_depth: None,
+            case_collision_policy: CaseCollisionPolicy::default(),
+            overwrite_policy: OverwritePolicy::default(),
+            invalid_media_policy: InvalidMediaPolicy::default(),
+            dummy_camera: DummyCamera::default(),
+            unknown_extension_policy: UnknownExtensionPolicy::default(),
+            output_extensions: OutputExtensions::default(),
+            output_templates: OutputTemplates::default(),
+            raw: RawConfigs::default(),
+            archives: ArchiveConfigs::default(),
+            shaders: ShaderConfigs::default(),
+            environments: EnvironmentConfigs::default(),
+            fonts: FontConfigs::default(),
+            telemetry: TelemetryConfigs::default(),
+            preload: PreloadConfigs::default(),
+            task_pools: TaskPoolConfigs::default(),
             meshes: MeshConfigs {
+                enabled: true,
+                workers: default_workers(),
                 use_meshlets: false,
-                storage: MeshStorage::Glb,
+                storage: MeshStorageTargets::Single(MeshStorage::Glb),
+                lod_levels: vec![],
+                merge_duplicate_materials: false,
+                weld_vertices: false,
+                weld_tolerance: 0.0,
+                flip_uv_v: false,
+                convert_handedness: false,
+                scene: false,
+                gltf_extensions: GltfExtensionConfigs::default(),
+                extension_set: GltfExtensionSet::default(),
+                sanitize_names: None,
+                meshlets: MeshletConfigs::default(),
+                strip_nodes: vec![],
+                keep_extras: true,
             },
+            profiles: BTreeMap::new(),
             textures: TextureConfigs {
+                enabled: true,
+                workers: default_workers(),
                 filter: TextureFilter::Linear,
+                filter_extensions: BTreeMap::new(),
+                filter_suffixes: BTreeMap::new(),
+                flip_vertical: false,
+                combine_sequences: false,
+                sequence_layout: SequenceLayout::Strip,
+                sequence_fps: None,
+                wrap_mode: TextureWrapMode::ClampToEdge,
+                wrap_mode_suffixes: BTreeMap::new(),
+                swizzle: None,
+                swizzle_suffixes: BTreeMap::new(),
+                max_size: None,
+                tiling: false,
+                force_pow2: None,
+                pow2_pad_color: [0, 0, 0, 0],
+                max_source_pixels: None,
+                ktx2: None,
+                uastc_quality: default_uastc_quality(),
+                etc1s_quality: default_etc1s_quality(),
+                color_space_suffixes: BTreeMap::new(),
+                bake_linear: false,
+                alpha_bleed: false,
+                border: 0,
+                sdf: None,
+                sdf_suffixes: BTreeMap::new(),
+                output_suffix: None,
             },
         }
     }
 }
 
+impl Config {
+    /// Whether `[[processors]]` routes the extension to the given processor
+    pub fn is_mapped_extension(&self, kind: ProcessingKind, ext: &str) -> bool {
+        self.processors.iter().any(|mapping| {
+            mapping.kind == kind
+                && mapping
+                    .extensions
+                    .iter()
+                    .any(|mapped| mapped.eq_ignore_ascii_case(ext))
+        })
+    }
+
+    /// The path of a source file relative to `source_dir`, or to the archive cache for extracted archive contents
+    pub fn get_source_relative_path(&self, source: &Path) -> Option<PathBuf> {
+        self.get_source_roots()
+            .into_iter()
+            .find_map(|root| get_relative_path(root, source))
+    }
+
+    /// The directories scanned for sources: `source_dir`, and the extracted archives when they're enabled. Files in
+    /// either are processed as if they were at the same relative path under `source_dir`
+    pub fn get_source_roots(&self) -> Vec<&Path> {
+        let mut roots = vec![self.source_dir.as_path()];
+        if self.archives.enabled {
+            roots.push(&self.archives.cache_dir);
+        }
+        roots
+    }
+
+    /// Where an archive in the source tree is extracted to, `packs/kenney_pack.zip` => `<cache_dir>/packs/kenney_pack`
+    pub fn get_archive_extract_dir(&self, archive: &Path) -> Option<PathBuf> {
+        let relative = get_relative_path(&self.source_dir, archive)?;
+        Some(self.archives.cache_dir.join(relative).with_extension(""))
+    }
+
+    pub fn is_always_processed(&self, source: &Path) -> bool {
+        self.always_process
+            .iter()
+            .any(|pattern| self.matches_source_pattern(pattern, source))
+    }
+
+    /// The highest priority whose pattern matches the source, see `priorities`
+    pub fn get_priority(&self, source: &Path) -> i32 {
+        self.priorities
+            .iter()
+            .filter(|(pattern, _)| self.matches_source_pattern(pattern, source))
+            .map(|(_, priority)| *priority)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Patterns containing a `/` match the path relative to `source_dir`, others match the file name
+    fn matches_source_pattern(&self, pattern: &str, source: &Path) -> bool {
+        if pattern.contains('/') {
+            let Some(relative) = self.get_source_relative_path(source) else {
+                return false;
+            };
+            matches_wildcard(pattern, &relative.to_string_lossy().replace('\\', "/"))
+        } else {
+            let name = source
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            matches_wildcard(pattern, &name)
+        }
+    }
+
+    /// Layers the named profile over this config, picked with `--profile <name>`
+    pub fn with_profile(&self, name: &str) -> Result<Config, String> {
+        let Some(profile) = self.profiles.get(name) else {
+            let names = self.profiles.keys().cloned().collect::<Vec<_>>();
+            return Err(format!(
+                "No profile named '{}'. Available profiles: [{}]",
+                name,
+                names.join(", ")
+            ));
+        };
+        merge_config(self, toml::Value::Table(profile.clone()))
+    }
+
+    /// Where a source file's output goes, mirroring its location under `source_dir`
+    pub fn get_output_path(&self, source: &Path) -> Option<PathBuf> {
+        Some(self.output_dir.join(self.get_source_relative_path(source)?))
+    }
+
+    /// Moves a processor's mirrored destination to where its `output_templates` entry puts it, if it has one.
+    /// `get_value` fills in processor specific placeholders. `None` if a placeholder can't be filled in
+    pub fn apply_output_template(
+        &self,
+        kind: ProcessingKind,
+        source: &Path,
+        dest: PathBuf,
+        get_value: impl Fn(&str) -> Option<String>,
+    ) -> Option<PathBuf> {
+        let Some(template) = self.output_templates.get(kind) else {
+            return Some(dest);
+        };
+        let rendered = render_template(template, |name| match name {
+            "dir" => Some(
+                self.get_source_relative_path(source)?
+                    .parent()
+                    .map(|dir| dir.to_string_lossy().replace('\\', "/"))
+                    .unwrap_or_default(),
+            ),
+            "name" => Some(dest.file_stem()?.to_string_lossy().to_string()),
+            "ext" => Some(
+                dest.extension()
+                    .map(|ext| ext.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            ),
+            "hash" => Some(format!("{:016x}", get_content_hash(source)?)),
+            _ => get_value(name),
+        })?;
+        // an empty `{dir}` at the root leaves a stray separator behind
+        let mut path = self.output_dir.clone();
+        for part in rendered
+            .split('/')
+            .filter(|part| !part.is_empty() && *part != ".")
+        {
+            path.push(part);
+        }
+        Some(path)
+    }
+
+    /// Checks values that deserialize fine but can't be used
+    fn validate(&self) -> Result<(), String> {
+        self.textures.validate()?;
+        self.meshes.meshlets.validate()?;
+        self.output_templates.validate()?;
+        if self.environments.face_size == 0 {
+            return Err("environments.face_size must be at least 1".into());
+        }
+        if let Some(atlas) = &self.fonts.atlas {
+            atlas.validate()?;
+        }
+        if self.textures.workers == 0 || self.meshes.workers == 0 {
+            return Err("textures.workers and meshes.workers must be at least 1".into());
+        }
+        if self.remote_sync_seconds <= 0.0 {
+            return Err("remote_sync_seconds must be greater than 0".into());
+        }
+        if self.max_depth == Some(0) {
+            return Err("max_depth must be at least 1".into());
+        }
+        if let Some(mapping) = self
+            .processors
+            .iter()
+            .find(|mapping| mapping.kind == ProcessingKind::Audio)
+        {
+            return Err(format!(
+                "Cannot map {:?} to the audio processor, which doesn't process files yet",
+                mapping.extensions
+            ));
+        }
+        let pools = &self.task_pools;
+        for share in [
+            pools.io_share,
+            pools.async_compute_share,
+            pools.compute_share,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if !(0.0..=1.0).contains(&share) {
+                return Err(format!(
+                    "Invalid task pool share {}. Expected 0.0..=1.0",
+                    share
+                ));
+            }
+        }
+        if self.meshes.storage.targets().is_empty() {
+            return Err("meshes.storage needs at least one format".into());
+        }
+        if let Some(ext) = &self.output_extensions.mesh {
+            if ext != "glb" && ext != "gltf" {
+                return Err(format!(
+                    "Invalid mesh output extension '{}'. Expected 'glb' or 'gltf'",
+                    ext
+                ));
+            }
+        }
+        match (&self.output_extensions.texture, &self.textures.ktx2) {
+            (Some(ext), None) if ext == "ktx2" => {
+                Err("Writing ktx2 textures needs textures.ktx2 to pick an encoding".into())
+            }
+            (Some(ext), Some(_)) if ext != "ktx2" => Err(format!(
+                "Texture output extension '{}' conflicts with textures.ktx2",
+                ext
+            )),
+            (Some(ext), None) if image::ImageFormat::from_extension(ext).is_none() => {
+                Err(format!("Unsupported texture output extension '{}'", ext))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Hashes the settings that affect a processor's outputs, so changing them invalidates outputs it already wrote
+    pub fn get_cache_key(&self, kind: ProcessingKind) -> u64 {
+        let mut settings = match kind {
+            ProcessingKind::Raw => serde_json::to_value(&self.raw),
+            ProcessingKind::Texture => serde_json::to_value(&self.textures),
+            ProcessingKind::Mesh => serde_json::to_value(&self.meshes),
+            ProcessingKind::Shader => serde_json::to_value(&self.shaders),
+            ProcessingKind::Environment => serde_json::to_value(&self.environments),
+            ProcessingKind::Font => serde_json::to_value(&self.fonts),
+            ProcessingKind::Audio => serde_json::to_value(&self.extensions.audio),
+            ProcessingKind::Archive => serde_json::to_value(&self.archives),
+        }
+        .unwrap_or_default();
+        if let Some(settings) = settings.as_object_mut() {
+            settings.retain(|key, _| !NON_OUTPUT_SETTINGS.contains(&key.as_str()));
+        }
+        let mut hasher = DefaultHasher::new();
+        settings.to_string().hash(&mut hasher);
+        self.get_output_extension(kind).hash(&mut hasher);
+        self.generate_meta.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The extension a processor's outputs are written with, or None to keep the source extension
+    pub fn get_output_extension(&self, kind: ProcessingKind) -> Option<String> {
+        match kind {
+            ProcessingKind::Raw => self.output_extensions.raw.clone(),
+            ProcessingKind::Texture => self
+                .output_extensions
+                .texture
+                .clone()
+                .or_else(|| self.textures.ktx2.as_ref().map(|_| "ktx2".into())),
+            ProcessingKind::Mesh => self.output_extensions.mesh.clone().or_else(|| {
+                let primary = self.meshes.storage.targets().first()?;
+                Some(primary.extension().into())
+            }),
+            ProcessingKind::Environment => Some("ktx2".into()),
+            ProcessingKind::Audio
+            | ProcessingKind::Shader
+            | ProcessingKind::Archive
+            | ProcessingKind::Font => None,
+        }
+    }
+}
+
+/// Settings in a processor's section that change how or whether it runs, but not what it writes. Left out of
+/// [`Config::get_cache_key`] so tuning them doesn't reprocess everything
+const NON_OUTPUT_SETTINGS: &[&str] = &["enabled", "workers", "max_size_bytes"];
+
+fn default_backlog_warning_scans() -> u32 {
+    5
+}
+
+fn default_source_dir() -> PathBuf {
+    PathBuf::from("assets-dev")
+}
+
+fn default_remote_cache_dir() -> PathBuf {
+    PathBuf::from(".bpm-remote-source")
+}
+
+fn default_remote_sync_seconds() -> f64 {
+    60.0
+}
+
+fn default_output_dir() -> PathBuf {
+    PathBuf::from("assets")
+}
+
+fn default_shader_extensions() -> Vec<String> {
+    vec!["wgsl".into()]
+}
+
+fn default_environment_extensions() -> Vec<String> {
+    vec!["hdr".into(), "exr".into()]
+}
+
+fn default_font_extensions() -> Vec<String> {
+    vec!["ttf".into(), "otf".into()]
+}
+
+fn default_uastc_quality() -> u32 {
+    2
+}
+
+fn default_etc1s_quality() -> u32 {
+    128
+}
+
+fn default_workers() -> usize {
+    1
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
 pub fn load_config(text: &str) -> Option<Config> {
-    if let Ok(config) = toml::from_str(text) {
-        return Some(config);
+    let Ok(mut config) = toml::from_str::<Config>(text) else {
+        return None;
+    };
+    if let Err(err) = interpolate_paths(&mut config, None) {
+        error!("Failed to interpolate config path: {}", err);
+        return None;
     }
-    None
+    if let Err(err) = config.validate() {
+        error!("Invalid configuration: {}", err);
+        return None;
+    }
+    Some(config)
+}
+
+/// Layers a partial config (such as a per-file sidecar) on top of the base config. Tables merge recursively, anything else is replaced
+pub fn apply_overrides(base: &Config, text: &str) -> Result<Config, String> {
+    let overrides = toml::from_str::<toml::Value>(text).map_err(|err| err.to_string())?;
+    merge_config(base, overrides)
+}
+
+fn merge_config(base: &Config, overrides: toml::Value) -> Result<Config, String> {
+    let mut value = toml::Value::try_from(base).map_err(|err| err.to_string())?;
+    merge_toml(&mut value, overrides);
+    let mut config = value.try_into::<Config>().map_err(|err| err.to_string())?;
+    interpolate_paths(&mut config, Some(base))?;
+    config.validate()?;
+    Ok(config)
+}
+
+fn merge_toml(base: &mut toml::Value, overrides: toml::Value) {
+    match (base, overrides) {
+        (toml::Value::Table(base), toml::Value::Table(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_toml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// A file's modification time and size when its contents were hashed
+type ContentStamp = (SystemTime, u64);
+
+/// Hashes of source contents for `{hash}` templates, which get rendered for every source on every scan
+static CONTENT_HASHES: OnceLock<Mutex<HashMap<PathBuf, (ContentStamp, u64)>>> = OnceLock::new();
+
+/// Hashes the file's contents, only reading it again when its modification time or size changed since last time
+fn get_content_hash(path: &Path) -> Option<u64> {
+    let meta = fs::metadata(path).ok()?;
+    let stamp = (meta.modified().ok()?, meta.len());
+    let mut hashes = CONTENT_HASHES
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if let Some((cached, hash)) = hashes.get(path) {
+        if *cached == stamp {
+            return Some(*hash);
+        }
+    }
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&fs::read(path).ok()?);
+    let hash = hasher.finish();
+    hashes.insert(path.to_path_buf(), (stamp, hash));
+    Some(hash)
+}
+
+/// `path` relative to `root`. Falls back to comparing canonical paths, so absolute and relative spellings of the same
+/// location still line up no matter the working directory
+fn get_relative_path(root: &Path, path: &Path) -> Option<PathBuf> {
+    if let Ok(relative) = path.strip_prefix(root) {
+        if !relative
+            .components()
+            .any(|part| part == Component::ParentDir)
+        {
+            return Some(relative.to_path_buf());
+        }
+    }
+    let root = root.canonicalize().ok()?;
+    let canonical = path.canonicalize().ok()?;
+    canonical.strip_prefix(root).ok().map(Path::to_path_buf)
+}
+
+/// Expands any `${VAR}` references in the path-typed config fields using the current environment. Runs after
+/// overrides are merged so profiles and sidecars can use them too. Paths still the same as in `base` were already
+/// expanded when it was loaded, and expanding them again would undo their `$$` escapes
+fn interpolate_paths(config: &mut Config, base: Option<&Config>) -> Result<(), String> {
+    let interpolate = |path: &Path, get_base: fn(&Config) -> Option<&PathBuf>| {
+        if base.and_then(get_base).is_some_and(|base| base == path) {
+            return Ok(path.to_path_buf());
+        }
+        interpolate_path(path)
+    };
+    config.source_dir = interpolate(&config.source_dir, |base| Some(&base.source_dir))?;
+    config.output_dir = interpolate(&config.output_dir, |base| Some(&base.output_dir))?;
+    config.remote_cache_dir =
+        interpolate(&config.remote_cache_dir, |base| Some(&base.remote_cache_dir))?;
+    config.archives.cache_dir =
+        interpolate(&config.archives.cache_dir, |base| Some(&base.archives.cache_dir))?;
+    if let Some(path) = &config.metrics_csv {
+        config.metrics_csv = Some(interpolate(path, |base| base.metrics_csv.as_ref())?);
+    }
+    Ok(())
+}
+
+fn interpolate_path(path: &Path) -> Result<PathBuf, String> {
+    let Some(text) = path.to_str() else {
+        return Err(format!("Config path is not valid UTF-8: {}", path.display()));
+    };
+    interpolate_env_vars(text).map(PathBuf::from)
+}
+
+/// Replaces each `${VAR}` with the variable's value. `$$` is a literal `$`, for paths that really contain `${`
+fn interpolate_env_vars(text: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            result.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(reference) = rest.strip_prefix("${") else {
+            // a lone `$` isn't a reference, so it's kept as-is
+            result.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let Some(len) = reference.find('}') else {
+            return Err(format!("Unterminated variable reference in '{}'", text));
+        };
+        let name = &reference[..len];
+        let Ok(value) = env::var(name) else {
+            return Err(format!(
+                "Undefined environment variable '{}' referenced in '{}'",
+                name, text
+            ));
+        };
+        result.push_str(&value);
+        rest = &reference[len + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
 }
 
 pub fn get_default_configuration_text() -> Option<String> {
@@ -80,3 +1638,269 @@ pub fn get_default_configuration_text() -> Option<String> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_templated_config(template: &str) -> Config {
+        let mut config = Config::default();
+        config.source_dir = PathBuf::from("src-assets");
+        config.output_dir = PathBuf::from("out");
+        config.output_templates.texture = Some(template.to_string());
+        config
+    }
+
+    fn render(config: &Config, source: &str) -> Option<PathBuf> {
+        let source = config.source_dir.join(source);
+        let dest = config.get_output_path(&source)?;
+        config.apply_output_template(ProcessingKind::Texture, &source, dest, |name| match name {
+            "w" | "h" => Some("64".into()),
+            _ => None,
+        })
+    }
+
+    /// A fresh directory per test, so tests running in parallel don't share files
+    fn get_test_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bpm-config-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        assert_eq!(
+            render_template("{dir}/{name}.{ext}", |name| Some(name.to_uppercase())),
+            Some("DIR/NAME.EXT".into())
+        );
+        assert_eq!(render_template("{dir}/{name", |_| Some(String::new())), None);
+        assert_eq!(render_template("{name}", |_| None), None);
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        let templates = OutputTemplates {
+            texture: Some("{dir}/{name}_{w}x{h}.{ext}".into()),
+            ..Default::default()
+        };
+        assert!(templates.validate().is_ok());
+        // `{w}` and `{h}` are only known to textures
+        let templates = OutputTemplates {
+            mesh: Some("{name}_{w}.{ext}".into()),
+            ..Default::default()
+        };
+        assert!(templates.validate().unwrap_err().contains("{w}"));
+        let templates = OutputTemplates {
+            raw: Some("../{name}.{ext}".into()),
+            ..Default::default()
+        };
+        assert!(templates.validate().is_err());
+        let templates = OutputTemplates {
+            raw: Some("{name.{ext}".into()),
+            ..Default::default()
+        };
+        assert!(templates.validate().is_err());
+    }
+
+    #[test]
+    fn drops_empty_dir_at_the_root() {
+        let config = get_templated_config("{dir}/{name}_{w}x{h}.{ext}");
+        assert_eq!(
+            render(&config, "logo.png"),
+            Some(PathBuf::from("out/logo_64x64.png"))
+        );
+        assert_eq!(
+            render(&config, "ui/logo.png"),
+            Some(PathBuf::from("out/ui/logo_64x64.png"))
+        );
+    }
+
+    #[test]
+    fn renders_same_path_for_sources_in_different_dirs() {
+        let config = get_templated_config("{name}.{ext}");
+        let first = render(&config, "a/logo.png");
+        assert!(first.is_some());
+        assert_eq!(first, render(&config, "b/logo.png"));
+
+        let config = get_templated_config("{dir}/{name}.{ext}");
+        assert_ne!(render(&config, "a/logo.png"), render(&config, "b/logo.png"));
+    }
+
+    #[test]
+    fn hashes_contents_again_once_changed() {
+        let dir = get_test_dir("hash");
+        let mut config = get_templated_config("{hash}.{ext}");
+        config.source_dir = dir.clone();
+        fs::write(dir.join("a.png"), "first").unwrap();
+        fs::write(dir.join("b.png"), "second").unwrap();
+        let first = render(&config, "a.png").unwrap();
+        assert_eq!(render(&config, "a.png").unwrap(), first);
+        assert_ne!(render(&config, "b.png").unwrap(), first);
+
+        // a different length changes the stamp even when the modification time doesn't
+        fs::write(dir.join("a.png"), "changed").unwrap();
+        assert_ne!(render(&config, "a.png").unwrap(), first);
+    }
+
+    #[test]
+    fn interpolates_env_vars() {
+        env::set_var("BPM_TEST_INTERPOLATE_ROOT", "/games/demo");
+        assert_eq!(
+            interpolate_env_vars("${BPM_TEST_INTERPOLATE_ROOT}/assets"),
+            Ok("/games/demo/assets".into())
+        );
+        assert_eq!(
+            interpolate_env_vars("a/${BPM_TEST_INTERPOLATE_ROOT}${BPM_TEST_INTERPOLATE_ROOT}"),
+            Ok("a//games/demo/games/demo".into())
+        );
+        assert_eq!(interpolate_env_vars("plain/path"), Ok("plain/path".into()));
+    }
+
+    #[test]
+    fn rejects_unset_and_unterminated_env_vars() {
+        env::remove_var("BPM_TEST_UNSET");
+        let err = interpolate_env_vars("${BPM_TEST_UNSET}/assets").unwrap_err();
+        assert!(err.contains("BPM_TEST_UNSET"));
+        let err = interpolate_env_vars("assets/${BPM_TEST_UNSET").unwrap_err();
+        assert!(err.contains("Unterminated"));
+    }
+
+    #[test]
+    fn keeps_escaped_dollars() {
+        env::set_var("BPM_TEST_ESCAPED", "value");
+        assert_eq!(
+            interpolate_env_vars("$${BPM_TEST_ESCAPED}"),
+            Ok("${BPM_TEST_ESCAPED}".into())
+        );
+        assert_eq!(
+            interpolate_env_vars("$$${BPM_TEST_ESCAPED}"),
+            Ok("$value".into())
+        );
+        assert_eq!(interpolate_env_vars("price$$5"), Ok("price$5".into()));
+        // a `$` that doesn't start a reference is left alone
+        assert_eq!(interpolate_env_vars("cost$5/$"), Ok("cost$5/$".into()));
+    }
+
+    #[test]
+    fn interpolates_paths_once_through_overrides() {
+        env::set_var("BPM_TEST_PATHS_ROOT", "/games/demo");
+        let mut config = Config::default();
+        config.source_dir = PathBuf::from("${BPM_TEST_PATHS_ROOT}/src");
+        config.output_dir = PathBuf::from("out/$${literal}");
+        interpolate_paths(&mut config, None).unwrap();
+        assert_eq!(config.source_dir, PathBuf::from("/games/demo/src"));
+        assert_eq!(config.output_dir, PathBuf::from("out/${literal}"));
+
+        // the escaped output_dir was already expanded, so layering a sidecar mustn't expand it again
+        let merged =
+            apply_overrides(&config, "metrics_csv = \"${BPM_TEST_PATHS_ROOT}/metrics.csv\"")
+                .unwrap();
+        assert_eq!(merged.output_dir, config.output_dir);
+        assert_eq!(
+            merged.metrics_csv,
+            Some(PathBuf::from("/games/demo/metrics.csv"))
+        );
+
+        env::remove_var("BPM_TEST_PATHS_MISSING");
+        assert!(apply_overrides(&config, "output_dir = \"${BPM_TEST_PATHS_MISSING}\"").is_err());
+    }
+
+    #[test]
+    fn merges_tables_recursively() {
+        let mut base = toml::from_str::<toml::Value>(
+            "a = 1\nlist = [1, 2]\n[textures]\nfilter = \"Linear\"\nmax_size = 512\n",
+        )
+        .unwrap();
+        let overrides = toml::from_str::<toml::Value>(
+            "list = [3]\nadded = true\n[textures]\nmax_size = 256\n",
+        )
+        .unwrap();
+        merge_toml(&mut base, overrides);
+        // arrays are replaced rather than appended to
+        let expected = toml::from_str::<toml::Value>(
+            "a = 1\nlist = [3]\nadded = true\n[textures]\nfilter = \"Linear\"\nmax_size = 256\n",
+        )
+        .unwrap();
+        assert_eq!(base, expected);
+    }
+
+    #[test]
+    fn layers_profiles_over_the_config() {
+        let mut config = Config::default();
+        config.profiles.insert(
+            "mobile".into(),
+            toml::from_str("output_dir = \"out-mobile\"\n[textures]\nmax_size = 512\n").unwrap(),
+        );
+        let mobile = config.with_profile("mobile").unwrap();
+        assert_eq!(mobile.output_dir, PathBuf::from("out-mobile"));
+        assert_eq!(mobile.textures.max_size, Some(512));
+        assert_eq!(mobile.source_dir, config.source_dir);
+        assert_eq!(mobile.textures.workers, config.textures.workers);
+
+        let err = config.with_profile("console").unwrap_err();
+        assert!(err.contains("console") && err.contains("mobile"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches_wildcard("*.png", "logo.png"));
+        assert!(!matches_wildcard("*.png", "logo.png.bak"));
+        assert!(matches_wildcard("ui/*/icon_*.png", "ui/menu/icon_play.png"));
+        assert!(!matches_wildcard("ui/*/icon_*.png", "hud/menu/icon_play.png"));
+        assert!(matches_wildcard("logo.png", "logo.png"));
+        assert!(!matches_wildcard("logo.png", "logo.png2"));
+        assert!(matches_wildcard("*", ""));
+        assert!(matches_wildcard("a*b*b", "abb"));
+        // the prefix and suffix can't share characters
+        assert!(!matches_wildcard("a*a", "a"));
+        assert!(!matches_wildcard("a*ab", "ab"));
+    }
+
+    #[test]
+    fn finds_suffix_overrides() {
+        let overrides = BTreeMap::from([("_n".to_string(), 1), ("_orm".to_string(), 2)]);
+        assert_eq!(
+            get_suffix_override(&overrides, Path::new("rock_n.png"), None),
+            Some(&1)
+        );
+        assert_eq!(
+            get_suffix_override(&overrides, Path::new("ui/rock_orm.png"), None),
+            Some(&2)
+        );
+        assert_eq!(get_suffix_override(&overrides, Path::new("rock.png"), None), None);
+        // only the stem counts, not the extension
+        assert_eq!(get_suffix_override(&overrides, Path::new("rock.png_n"), None), None);
+
+        // outputs carry `output_suffix` but resolve the same override as their source
+        let suffix = "_processed".to_string();
+        assert_eq!(
+            get_suffix_override(&overrides, Path::new("rock_n_processed.ktx2"), Some(&suffix)),
+            Some(&1)
+        );
+        assert_eq!(
+            get_suffix_override(&overrides, Path::new("rock_n.png"), Some(&suffix)),
+            Some(&1)
+        );
+    }
+
+    #[test]
+    fn ignores_non_output_settings_in_cache_key() {
+        let config = Config::default();
+        let texture_key = config.get_cache_key(ProcessingKind::Texture);
+        let raw_key = config.get_cache_key(ProcessingKind::Raw);
+
+        let mut tuned = config.clone();
+        tuned.textures.workers += 3;
+        tuned.raw.enabled = !tuned.raw.enabled;
+        tuned.raw.max_size_bytes = Some(1024);
+        assert_eq!(tuned.get_cache_key(ProcessingKind::Texture), texture_key);
+        assert_eq!(tuned.get_cache_key(ProcessingKind::Raw), raw_key);
+
+        let mut changed = config.clone();
+        changed.textures.max_size = Some(256);
+        changed.raw.preserve_mode = !changed.raw.preserve_mode;
+        assert_ne!(changed.get_cache_key(ProcessingKind::Texture), texture_key);
+        assert_ne!(changed.get_cache_key(ProcessingKind::Raw), raw_key);
+    }
+}
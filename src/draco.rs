@@ -0,0 +1,990 @@
+use std::collections::HashSet;
+
+use serde_json::{json, Value};
+
+use crate::mesh::read_glb_json;
+
+/// Geometry compressed with this extension lives in a draco bitstream instead of accessors
+pub const DRACO_EXTENSION: &str = "KHR_draco_mesh_compression";
+
+/// Number of primitives in a gltf document that store their geometry with draco
+pub fn count_compressed_primitives(json: &Value) -> usize {
+    json["meshes"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|mesh| mesh["primitives"].as_array().into_iter().flatten())
+        .filter(|primitive| primitive["extensions"].get(DRACO_EXTENSION).is_some())
+        .count()
+}
+
+/// Decodes every draco compressed primitive of a glb into plain accessors appended to its binary chunk, and removes
+/// the extension. The compressed buffer views are left in place with nothing referencing them
+pub fn decode_glb(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let (mut json, json_end) = read_glb_json(bytes)?;
+    let mut bin = read_glb_bin(bytes, json_end)?.to_vec();
+    if !json["buffers"][0].is_object() || json["buffers"][0].get("uri").is_some() {
+        return Err("glb's first buffer isn't its binary chunk".into());
+    }
+
+    let mut decoded_accessors = HashSet::new();
+    let mesh_count = json["meshes"].as_array().map_or(0, |meshes| meshes.len());
+    for mesh in 0..mesh_count {
+        let primitive_count = json["meshes"][mesh]["primitives"]
+            .as_array()
+            .map_or(0, |primitives| primitives.len());
+        for index in 0..primitive_count {
+            let Some(primitive) = json["meshes"][mesh]["primitives"][index]
+                .as_object_mut()
+                .filter(|primitive| primitive.get("extensions").is_some_and(Value::is_object))
+            else {
+                continue;
+            };
+            let extensions = primitive["extensions"]
+                .as_object_mut()
+                .expect("was checked to be an object");
+            let Some(extension) = extensions.remove(DRACO_EXTENSION) else {
+                continue;
+            };
+            if extensions.is_empty() {
+                primitive.remove("extensions");
+            }
+            let primitive = Value::Object(primitive.clone());
+
+            let draco = get_buffer_view(&json, &bin, &extension["bufferView"])?;
+            let decoded = decode_mesh(draco).map_err(|err| {
+                format!(
+                    "Failed to decode draco primitive {} of mesh {}: {}",
+                    index, mesh, err
+                )
+            })?;
+            write_attributes(
+                &mut json,
+                &mut bin,
+                &primitive,
+                &extension,
+                &decoded,
+                &mut decoded_accessors,
+            )?;
+            match primitive["indices"].as_u64() {
+                Some(accessor) => {
+                    if decoded_accessors.insert(accessor) {
+                        let indices = decoded
+                            .indices
+                            .iter()
+                            .map(|&index| index as f64)
+                            .collect::<Vec<_>>();
+                        write_accessor(&mut json, &mut bin, accessor, &indices, false)?;
+                    }
+                }
+                None => {
+                    let accessor = push_index_accessor(&mut json, &mut bin, &decoded.indices);
+                    json["meshes"][mesh]["primitives"][index]["indices"] = accessor.into();
+                }
+            }
+        }
+    }
+
+    for key in ["extensionsUsed", "extensionsRequired"] {
+        if let Some(Value::Array(names)) = json.get_mut(key) {
+            names.retain(|name| name != DRACO_EXTENSION);
+            if names.is_empty() {
+                if let Some(root) = json.as_object_mut() {
+                    root.remove(key);
+                }
+            }
+        }
+    }
+    json["buffers"][0]["byteLength"] = bin.len().into();
+    write_glb(&json, &bin)
+}
+
+/// Points the primitive's attribute accessors at the decoded values. Accessors are only written once, in case
+/// primitives share them
+fn write_attributes(
+    json: &mut Value,
+    bin: &mut Vec<u8>,
+    primitive: &Value,
+    extension: &Value,
+    decoded: &DracoMesh,
+    decoded_accessors: &mut HashSet<u64>,
+) -> Result<(), String> {
+    let attributes = extension["attributes"]
+        .as_object()
+        .ok_or_else(|| "draco extension has no attributes".to_string())?;
+    for (name, unique_id) in attributes {
+        let Some(accessor) = primitive["attributes"][name].as_u64() else {
+            continue;
+        };
+        let attribute = decoded
+            .attributes
+            .iter()
+            .find(|attribute| Some(attribute.unique_id as u64) == unique_id.as_u64())
+            .ok_or_else(|| format!("draco data has no attribute for {}", name))?;
+        if decoded_accessors.insert(accessor) {
+            write_accessor(json, bin, accessor, &attribute.values, true)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes values in the accessor's component type to a new buffer view. Vertex attribute elements are padded to 4
+/// bytes as the gltf spec requires
+fn write_accessor(
+    json: &mut Value,
+    bin: &mut Vec<u8>,
+    accessor: u64,
+    values: &[f64],
+    is_attribute: bool,
+) -> Result<(), String> {
+    let entry = &json["accessors"][accessor as usize];
+    let component_type = entry["componentType"].as_u64().unwrap_or_default();
+    let component_size = match component_type {
+        5120 | 5121 => 1,
+        5122 | 5123 => 2,
+        5125 | 5126 => 4,
+        _ => {
+            return Err(format!(
+                "Accessor {} has an invalid component type",
+                accessor
+            ))
+        }
+    };
+    let components = match entry["type"].as_str().unwrap_or_default() {
+        "SCALAR" => 1,
+        "VEC2" => 2,
+        "VEC3" => 3,
+        "VEC4" | "MAT2" => 4,
+        "MAT3" => 9,
+        "MAT4" => 16,
+        other => {
+            return Err(format!(
+                "Accessor {} has an invalid type {}",
+                accessor, other
+            ))
+        }
+    };
+    if values.len() % components != 0 {
+        return Err(format!(
+            "Decoded draco data doesn't fit accessor {}'s type",
+            accessor
+        ));
+    }
+    let element_size = component_size * components;
+    let stride = if is_attribute {
+        element_size.next_multiple_of(4)
+    } else {
+        element_size
+    };
+
+    align_bin(bin);
+    let offset = bin.len();
+    for element in values.chunks(components) {
+        for &value in element {
+            match component_type {
+                5120 => bin.push(value as i8 as u8),
+                5121 => bin.push(value as u8),
+                5122 => bin.extend((value as i16).to_le_bytes()),
+                5123 => bin.extend((value as u16).to_le_bytes()),
+                5125 => bin.extend((value as u32).to_le_bytes()),
+                _ => bin.extend((value as f32).to_le_bytes()),
+            }
+        }
+        bin.resize(bin.len() + stride - element_size, 0);
+    }
+    let mut view = json!({ "buffer": 0, "byteOffset": offset, "byteLength": bin.len() - offset });
+    if stride != element_size {
+        view["byteStride"] = stride.into();
+    }
+    let view_index = push_item(json, "bufferViews", view);
+
+    let entry = &mut json["accessors"][accessor as usize];
+    entry["bufferView"] = view_index.into();
+    entry["count"] = (values.len() / components).into();
+    if let Some(entry) = entry.as_object_mut() {
+        entry.remove("byteOffset");
+    }
+    Ok(())
+}
+
+/// Adds a new `u32` index accessor, returning its index
+fn push_index_accessor(json: &mut Value, bin: &mut Vec<u8>, indices: &[u32]) -> usize {
+    align_bin(bin);
+    let offset = bin.len();
+    for index in indices {
+        bin.extend(index.to_le_bytes());
+    }
+    let view = push_item(
+        json,
+        "bufferViews",
+        json!({ "buffer": 0, "byteOffset": offset, "byteLength": bin.len() - offset }),
+    );
+    push_item(
+        json,
+        "accessors",
+        json!({ "bufferView": view, "componentType": 5125, "count": indices.len(), "type": "SCALAR" }),
+    )
+}
+
+fn push_item(json: &mut Value, key: &str, item: Value) -> usize {
+    if !json[key].is_array() {
+        json[key] = json!([]);
+    }
+    let items = json[key].as_array_mut().expect("was just made an array");
+    items.push(item);
+    items.len() - 1
+}
+
+fn align_bin(bin: &mut Vec<u8>) {
+    bin.resize(bin.len().next_multiple_of(4), 0);
+}
+
+fn get_buffer_view<'a>(json: &Value, bin: &'a [u8], index: &Value) -> Result<&'a [u8], String> {
+    let view = index
+        .as_u64()
+        .map(|index| &json["bufferViews"][index as usize])
+        .filter(|view| view.is_object())
+        .ok_or_else(|| "draco extension points at a missing buffer view".to_string())?;
+    if view["buffer"].as_u64() != Some(0) {
+        return Err("draco data isn't in the glb's binary chunk".into());
+    }
+    let offset = view["byteOffset"].as_u64().unwrap_or_default() as usize;
+    let length = view["byteLength"].as_u64().unwrap_or_default() as usize;
+    bin.get(offset..offset + length)
+        .ok_or_else(|| "draco buffer view is out of the binary chunk's bounds".into())
+}
+
+/// The binary chunk, which follows the JSON chunk
+fn read_glb_bin(bytes: &[u8], json_end: usize) -> Result<&[u8], String> {
+    let header = bytes
+        .get(json_end..json_end + 8)
+        .ok_or_else(|| "glb has no binary chunk".to_string())?;
+    if &header[4..8] != b"BIN\0" {
+        return Err("glb has no binary chunk".into());
+    }
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    bytes
+        .get(json_end + 8..json_end + 8 + len)
+        .ok_or_else(|| "glb has a truncated binary chunk".into())
+}
+
+fn write_glb(json: &Value, bin: &[u8]) -> Result<Vec<u8>, String> {
+    let mut json_bytes = serde_json::to_vec(json)
+        .map_err(|err| format!("Failed to write glb JSON chunk: {}", err))?;
+    while json_bytes.len() % 4 != 0 {
+        json_bytes.push(b' ');
+    }
+    let bin_len = bin.len().next_multiple_of(4);
+    let total_len = 12 + 8 + json_bytes.len() + 8 + bin_len;
+    let mut bytes = Vec::with_capacity(total_len);
+    bytes.extend(b"glTF");
+    bytes.extend(2u32.to_le_bytes());
+    bytes.extend((total_len as u32).to_le_bytes());
+    bytes.extend((json_bytes.len() as u32).to_le_bytes());
+    bytes.extend(b"JSON");
+    bytes.extend(json_bytes);
+    bytes.extend((bin_len as u32).to_le_bytes());
+    bytes.extend(b"BIN\0");
+    bytes.extend(bin);
+    bytes.resize(total_len, 0);
+    Ok(bytes)
+}
+
+/// A decoded draco mesh. Every attribute has one value per point, with its components flattened
+#[derive(Debug)]
+struct DracoMesh {
+    indices: Vec<u32>,
+    attributes: Vec<DracoAttribute>,
+}
+
+#[derive(Debug)]
+struct DracoAttribute {
+    /// What the gltf extension's `attributes` map refers to
+    unique_id: u32,
+    values: Vec<f64>,
+}
+
+/// How an attribute's values were encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AttributeEncoding {
+    Generic,
+    Integer,
+    Quantized,
+    Normals,
+}
+
+#[derive(Debug)]
+struct AttributeInfo {
+    data_type: u8,
+    components: usize,
+    unique_id: u32,
+    encoding: AttributeEncoding,
+}
+
+/// Decodes a draco bitstream (version 2.2, as written by draco 1.4 and later) encoded with the sequential method.
+/// Edgebreaker encoded meshes aren't supported
+fn decode_mesh(data: &[u8]) -> Result<DracoMesh, String> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(5)? != b"DRACO" {
+        return Err("Not a draco bitstream".into());
+    }
+    let (major, minor) = (reader.u8()?, reader.u8()?);
+    if (major, minor) != (2, 2) {
+        return Err(format!(
+            "Draco bitstream version {}.{} isn't supported",
+            major, minor
+        ));
+    }
+    if reader.u8()? != 1 {
+        return Err("Draco data isn't a triangle mesh".into());
+    }
+    match reader.u8()? {
+        0 => {}
+        1 => {
+            return Err(
+                "Edgebreaker encoded draco isn't supported. Re-encode the source with the sequential method, e.g. \
+                 `gltf-transform draco --method sequential`"
+                    .into(),
+            )
+        }
+        method => return Err(format!("Unknown draco encoding method {}", method)),
+    }
+    if reader.u16()? & 0x8000 != 0 {
+        return Err("Draco metadata isn't supported".into());
+    }
+
+    let face_count = reader.varint()? as usize;
+    let point_count = reader.varint()? as usize;
+    let indices = decode_connectivity(&mut reader, face_count * 3, point_count)?;
+    if indices.iter().any(|&index| index as usize >= point_count) {
+        return Err("Draco faces reference points that don't exist".into());
+    }
+
+    // every decoder's attribute descriptions come before any of their values
+    let decoder_count = reader.u8()?;
+    let mut decoders = vec![];
+    for _ in 0..decoder_count {
+        let mut infos = vec![];
+        for _ in 0..reader.varint()? {
+            let _attribute_type = reader.u8()?;
+            let data_type = reader.u8()?;
+            let components = reader.u8()? as usize;
+            let _normalized = reader.u8()?;
+            let unique_id = reader.varint()? as u32;
+            if components == 0 {
+                return Err("Draco attribute has no components".into());
+            }
+            infos.push(AttributeInfo {
+                data_type,
+                components,
+                unique_id,
+                encoding: AttributeEncoding::Generic,
+            });
+        }
+        for info in &mut infos {
+            info.encoding = match reader.u8()? {
+                0 => AttributeEncoding::Generic,
+                1 => AttributeEncoding::Integer,
+                2 => AttributeEncoding::Quantized,
+                3 => AttributeEncoding::Normals,
+                other => return Err(format!("Unknown draco attribute encoding {}", other)),
+            };
+        }
+        decoders.push(infos);
+    }
+
+    let mut attributes = vec![];
+    for infos in decoders {
+        attributes.extend(decode_attributes(&mut reader, &infos, point_count)?);
+    }
+    Ok(DracoMesh {
+        indices,
+        attributes,
+    })
+}
+
+fn decode_connectivity(
+    reader: &mut Reader,
+    index_count: usize,
+    point_count: usize,
+) -> Result<Vec<u32>, String> {
+    if reader.u8()? != 0 {
+        // stored uncompressed, in the smallest type that fits every index
+        return (0..index_count)
+            .map(|_| match point_count {
+                0..=0xff => reader.u8().map(u32::from),
+                0x100..=0xffff => reader.u16().map(u32::from),
+                0x10000..=0x1fffff => reader.varint().map(|index| index as u32),
+                _ => reader.u32(),
+            })
+            .collect();
+    }
+    // each index is stored as the signed difference from the previous one
+    let mut last = 0i64;
+    decode_symbols(reader, index_count, 1)?
+        .into_iter()
+        .map(|symbol| {
+            let difference = (symbol >> 1) as i64;
+            last += if symbol & 1 == 1 {
+                -difference
+            } else {
+                difference
+            };
+            u32::try_from(last).map_err(|_| "Draco face has a negative index".to_string())
+        })
+        .collect()
+}
+
+/// Decodes the values of one attribute decoder: every attribute's integers first, then the parameters that turn them
+/// back into their original values
+fn decode_attributes(
+    reader: &mut Reader,
+    infos: &[AttributeInfo],
+    point_count: usize,
+) -> Result<Vec<DracoAttribute>, String> {
+    let mut portable = vec![];
+    for info in infos {
+        portable.push(match info.encoding {
+            AttributeEncoding::Generic => {
+                decode_generic_values(reader, info.data_type, info.components * point_count)?
+            }
+            AttributeEncoding::Normals => decode_integer_values(reader, 2, point_count)?,
+            _ => decode_integer_values(reader, info.components, point_count)?,
+        });
+    }
+
+    let mut attributes = vec![];
+    for (info, values) in infos.iter().zip(portable) {
+        let values = match info.encoding {
+            AttributeEncoding::Generic | AttributeEncoding::Integer => values,
+            AttributeEncoding::Quantized => {
+                let min = (0..info.components)
+                    .map(|_| reader.f32())
+                    .collect::<Result<Vec<_>, _>>()?;
+                let range = reader.f32()?;
+                let bits = reader.u8()?;
+                if !(1..=30).contains(&bits) {
+                    return Err(format!("Invalid draco quantization bits {}", bits));
+                }
+                let delta = range / ((1u32 << bits) - 1) as f32;
+                values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &value)| (value as f32 * delta + min[i % info.components]) as f64)
+                    .collect()
+            }
+            AttributeEncoding::Normals => {
+                let bits = reader.u8()?;
+                if !(2..=30).contains(&bits) {
+                    return Err(format!("Invalid draco normal quantization bits {}", bits));
+                }
+                let scale = 2.0 / ((1u32 << bits) - 2) as f32;
+                values
+                    .chunks(2)
+                    .flat_map(|st| {
+                        octahedral_to_unit_vector(
+                            st[0] as f32 * scale - 1.0,
+                            st[1] as f32 * scale - 1.0,
+                        )
+                    })
+                    .map(f64::from)
+                    .collect()
+            }
+        };
+        attributes.push(DracoAttribute {
+            unique_id: info.unique_id,
+            values,
+        });
+    }
+    Ok(attributes)
+}
+
+fn decode_generic_values(
+    reader: &mut Reader,
+    data_type: u8,
+    count: usize,
+) -> Result<Vec<f64>, String> {
+    (0..count)
+        .map(|_| {
+            Ok(match data_type {
+                1 => reader.u8()? as i8 as f64,
+                2 => reader.u8()? as f64,
+                3 => reader.u16()? as i16 as f64,
+                4 => reader.u16()? as f64,
+                5 => reader.i32()? as f64,
+                6 => reader.u32()? as f64,
+                9 => reader.f32()? as f64,
+                other => return Err(format!("Unsupported draco data type {}", other)),
+            })
+        })
+        .collect()
+}
+
+/// Reads integer values, which draco stores as entropy coded corrections to a prediction
+fn decode_integer_values(
+    reader: &mut Reader,
+    components: usize,
+    point_count: usize,
+) -> Result<Vec<f64>, String> {
+    let prediction = reader.i8()?;
+    let transform = match prediction {
+        -2 => None,
+        0 => Some(reader.i8()?),
+        other => {
+            return Err(format!(
+                "Draco prediction scheme {} isn't supported by sequential decoding",
+                other
+            ))
+        }
+    };
+    let count = components * point_count;
+    let symbols = if reader.u8()? != 0 {
+        decode_symbols(reader, count, components)?
+    } else {
+        let size = reader.u8()? as usize;
+        if !(1..=4).contains(&size) {
+            return Err(format!("Invalid draco value size {}", size));
+        }
+        (0..count)
+            .map(|_| {
+                let bytes = reader.bytes(size)?;
+                Ok(bytes
+                    .iter()
+                    .rev()
+                    .fold(0u32, |value, &byte| (value << 8) | byte as u32))
+            })
+            .collect::<Result<_, String>>()?
+    };
+    // normal corrections are always positive, everything else zigzag encodes its sign
+    let mut values = symbols
+        .into_iter()
+        .map(|symbol| match transform {
+            Some(3) => symbol as i64,
+            _ => ((symbol >> 1) as i64) ^ -((symbol & 1) as i64),
+        })
+        .collect::<Vec<_>>();
+
+    match transform {
+        None => {}
+        Some(1) => {
+            let transform = WrapTransform::new(reader.i32()?, reader.i32()?)?;
+            apply_difference_prediction(&mut values, components, |predicted, correction| {
+                transform.original_value(predicted, correction)
+            });
+        }
+        Some(3) => {
+            if components != 2 {
+                return Err("Draco normal prediction needs 2 components".into());
+            }
+            let transform = OctahedronTransform::new(reader.i32()?)?;
+            for i in (0..values.len()).step_by(2) {
+                let predicted = match i {
+                    0 => [0, 0],
+                    _ => [values[i - 2], values[i - 1]],
+                };
+                let original = transform.original_value(predicted, [values[i], values[i + 1]]);
+                values[i..i + 2].copy_from_slice(&original);
+            }
+        }
+        Some(other) => {
+            return Err(format!(
+                "Draco prediction transform {} isn't supported",
+                other
+            ))
+        }
+    }
+    Ok(values.into_iter().map(|value| value as f64).collect())
+}
+
+/// Each value is predicted to equal the previous one, and the first to be zero
+fn apply_difference_prediction(
+    values: &mut [i64],
+    components: usize,
+    original_value: impl Fn(i64, i64) -> i64,
+) {
+    for i in 0..values.len() {
+        let predicted = if i < components {
+            0
+        } else {
+            values[i - components]
+        };
+        values[i] = original_value(predicted, values[i]);
+    }
+}
+
+/// Wraps corrections around the value range, so they never need more than half of it
+struct WrapTransform {
+    min: i64,
+    max: i64,
+}
+
+impl WrapTransform {
+    fn new(min: i32, max: i32) -> Result<Self, String> {
+        if min > max {
+            return Err("Invalid draco wrap transform bounds".into());
+        }
+        Ok(Self {
+            min: min as i64,
+            max: max as i64,
+        })
+    }
+
+    fn original_value(&self, predicted: i64, correction: i64) -> i64 {
+        let value = predicted.clamp(self.min, self.max) + correction;
+        let range = 1 + self.max - self.min;
+        if value > self.max {
+            value - range
+        } else if value < self.min {
+            value + range
+        } else {
+            value
+        }
+    }
+}
+
+/// Predicts octahedral normal coordinates after rotating them into the bottom left quadrant of the diamond
+struct OctahedronTransform {
+    max_quantized: i64,
+    center: i64,
+}
+
+impl OctahedronTransform {
+    fn new(max_quantized: i32) -> Result<Self, String> {
+        if max_quantized < 3 || max_quantized % 2 == 0 {
+            return Err("Invalid draco normal transform range".into());
+        }
+        let bits = 32 - max_quantized.leading_zeros();
+        let max_quantized = (1i64 << bits) - 1;
+        Ok(Self {
+            max_quantized,
+            center: (max_quantized - 1) / 2,
+        })
+    }
+
+    fn original_value(&self, predicted: [i64; 2], correction: [i64; 2]) -> [i64; 2] {
+        let mut predicted = [predicted[0] - self.center, predicted[1] - self.center];
+        let in_diamond = predicted[0].abs() + predicted[1].abs() <= self.center;
+        if !in_diamond {
+            predicted = self.invert_diamond(predicted);
+        }
+        let in_bottom_left = predicted == [0, 0] || (predicted[0] < 0 && predicted[1] <= 0);
+        let rotation = get_rotation_count(predicted);
+        if !in_bottom_left {
+            predicted = rotate(predicted, rotation);
+        }
+        let mut original = [
+            self.mod_max(predicted[0] + correction[0]),
+            self.mod_max(predicted[1] + correction[1]),
+        ];
+        if !in_bottom_left {
+            original = rotate(original, (4 - rotation) % 4);
+        }
+        if !in_diamond {
+            original = self.invert_diamond(original);
+        }
+        [original[0] + self.center, original[1] + self.center]
+    }
+
+    /// Mirrors a point across the diamond's edge, between the inner and outer halves of the octahedron
+    fn invert_diamond(&self, [s, t]: [i64; 2]) -> [i64; 2] {
+        let (sign_s, sign_t) = if s >= 0 && t >= 0 {
+            (1, 1)
+        } else if s <= 0 && t <= 0 {
+            (-1, -1)
+        } else {
+            (s.signum(), t.signum())
+        };
+        let (corner_s, corner_t) = (sign_s * self.center, sign_t * self.center);
+        let (s, t) = (2 * s - corner_s, 2 * t - corner_t);
+        let (s, t) = if sign_s * sign_t >= 0 {
+            (-t, -s)
+        } else {
+            (t, s)
+        };
+        [(s + corner_s) / 2, (t + corner_t) / 2]
+    }
+
+    fn mod_max(&self, value: i64) -> i64 {
+        if value > self.center {
+            value - self.max_quantized
+        } else if value < -self.center {
+            value + self.max_quantized
+        } else {
+            value
+        }
+    }
+}
+
+fn get_rotation_count([x, y]: [i64; 2]) -> usize {
+    match (x.signum(), y.signum()) {
+        (0, 0) => 0,
+        (0, 1) => 3,
+        (0, _) => 1,
+        (1, -1) => 1,
+        (1, _) => 2,
+        (_, 1) => 3,
+        _ => 0,
+    }
+}
+
+/// Rotates by 90 degrees clockwise `count` times
+fn rotate([x, y]: [i64; 2], count: usize) -> [i64; 2] {
+    match count {
+        1 => [y, -x],
+        2 => [-x, -y],
+        3 => [-y, x],
+        _ => [x, y],
+    }
+}
+
+fn octahedral_to_unit_vector(y: f32, z: f32) -> [f32; 3] {
+    let x = 1.0 - y.abs() - z.abs();
+    let offset = (-x).max(0.0);
+    let y = if y < 0.0 { y + offset } else { y - offset };
+    let z = if z < 0.0 { z + offset } else { z - offset };
+    let norm_squared = x * x + y * y + z * z;
+    if norm_squared < 1e-6 {
+        return [0.0; 3];
+    }
+    let d = 1.0 / norm_squared.sqrt();
+    [x * d, y * d, z * d]
+}
+
+/// Entropy coded unsigned values, either all with one rANS table or as a rANS coded bit length per group of
+/// `components` followed by the raw bits
+fn decode_symbols(
+    reader: &mut Reader,
+    count: usize,
+    components: usize,
+) -> Result<Vec<u32>, String> {
+    if count == 0 {
+        return Ok(vec![]);
+    }
+    match reader.u8()? {
+        0 => {
+            let tags = decode_rans_symbols(reader, 5, count.div_ceil(components))?;
+            let mut bits = BitReader::new(reader.remaining());
+            let mut values = vec![];
+            for tag in tags {
+                for _ in 0..components.min(count - values.len()) {
+                    values.push(bits.read(tag)?);
+                }
+            }
+            let consumed = bits.bytes_consumed();
+            reader.bytes(consumed)?;
+            Ok(values)
+        }
+        1 => {
+            let max_bit_length = reader.u8()? as u32;
+            if !(1..=18).contains(&max_bit_length) {
+                return Err(format!(
+                    "Invalid draco symbol bit length {}",
+                    max_bit_length
+                ));
+            }
+            decode_rans_symbols(reader, max_bit_length, count)
+        }
+        scheme => Err(format!("Unknown draco symbol coding {}", scheme)),
+    }
+}
+
+/// Reads a probability table and the rANS stream coded with it
+fn decode_rans_symbols(
+    reader: &mut Reader,
+    bit_length: u32,
+    count: usize,
+) -> Result<Vec<u32>, String> {
+    let precision_bits = (3 * bit_length / 2).clamp(12, 20);
+    let probabilities = decode_probability_table(reader)?;
+    let size = reader.varint()? as usize;
+    let data = reader.bytes(size)?;
+    let mut decoder = RansDecoder::new(precision_bits, &probabilities, data)?;
+    Ok((0..count).map(|_| decoder.read()).collect())
+}
+
+fn decode_probability_table(reader: &mut Reader) -> Result<Vec<u32>, String> {
+    let count = reader.varint()? as usize;
+    let mut probabilities = Vec::with_capacity(count.min(1 << 20));
+    while probabilities.len() < count {
+        let byte = reader.u8()?;
+        let token = byte & 3;
+        if token == 3 {
+            // a run of symbols that never occur
+            let run = (byte >> 2) as usize + 1;
+            if probabilities.len() + run > count {
+                return Err("Draco probability table overflows".into());
+            }
+            probabilities.resize(probabilities.len() + run, 0);
+            continue;
+        }
+        let mut probability = (byte >> 2) as u32;
+        for i in 0..token as u32 {
+            probability |= (reader.u8()? as u32) << (8 * (i + 1) - 2);
+        }
+        probabilities.push(probability);
+    }
+    Ok(probabilities)
+}
+
+struct RansDecoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+    state: u32,
+    precision: u32,
+    base: u32,
+    /// (probability, cumulative probability) per symbol
+    symbols: Vec<(u32, u32)>,
+    lookup: Vec<u32>,
+}
+
+impl<'a> RansDecoder<'a> {
+    fn new(precision_bits: u32, probabilities: &[u32], data: &'a [u8]) -> Result<Self, String> {
+        let precision = 1 << precision_bits;
+        let base = precision * 4;
+        let mut symbols = Vec::with_capacity(probabilities.len());
+        let mut lookup = Vec::with_capacity(precision as usize);
+        for (symbol, &probability) in probabilities.iter().enumerate() {
+            symbols.push((probability, lookup.len() as u32));
+            if lookup.len() + probability as usize > precision as usize {
+                return Err("Draco probabilities exceed the rANS precision".into());
+            }
+            lookup.resize(lookup.len() + probability as usize, symbol as u32);
+        }
+        if lookup.len() != precision as usize {
+            return Err("Draco probabilities don't add up to the rANS precision".into());
+        }
+
+        // the stream is read backwards, starting with the final state whose size is in its top 2 bits
+        let Some(&last) = data.last() else {
+            return Err("Draco rANS stream is empty".into());
+        };
+        let size = (last >> 6) as usize + 1;
+        if data.len() < size {
+            return Err("Draco rANS stream is truncated".into());
+        }
+        let offset = data.len() - size;
+        let state = data[offset..]
+            .iter()
+            .rev()
+            .fold(0u32, |state, &byte| (state << 8) | byte as u32)
+            & ((1 << (size * 8 - 2)) - 1);
+        let state = state + base;
+        if state >= base * 256 {
+            return Err("Invalid draco rANS state".into());
+        }
+        Ok(Self {
+            data,
+            offset,
+            state,
+            precision,
+            base,
+            symbols,
+            lookup,
+        })
+    }
+
+    fn read(&mut self) -> u32 {
+        while self.state < self.base && self.offset > 0 {
+            self.offset -= 1;
+            self.state = self.state * 256 + self.data[self.offset] as u32;
+        }
+        let (quotient, remainder) = (self.state / self.precision, self.state % self.precision);
+        let symbol = self.lookup[remainder as usize];
+        let (probability, cumulative) = self.symbols[symbol as usize];
+        self.state = quotient * probability + remainder - cumulative;
+        symbol
+    }
+}
+
+/// Reads values least significant bit first
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read(&mut self, bits: u32) -> Result<u32, String> {
+        if bits > 32 {
+            return Err("Invalid draco bit length".into());
+        }
+        let mut value = 0u32;
+        for bit in 0..bits {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or_else(|| "Draco bit stream is truncated".to_string())?;
+            value |= ((*byte as u32 >> (self.position % 8)) & 1) << bit;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn bytes_consumed(&self) -> usize {
+        self.position.div_ceil(8)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .ok_or_else(|| "Draco data is truncated".to_string())?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn remaining(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn i8(&mut self) -> Result<i8, String> {
+        Ok(self.u8()? as i8)
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    /// LEB128, as draco writes counts and sizes
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Draco varint is too long".into())
+    }
+}
@@ -0,0 +1,174 @@
+use std::{f32::consts::PI, io::Cursor, path::PathBuf};
+
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+use half::f16;
+use image::Rgba32FImage;
+use libktx_rs::{sinks::StreamSink, sources::Ktx2CreateInfo, Texture};
+
+use crate::{
+    config::{Config, EnvironmentConfigs},
+    media::check_media_file,
+    processing::{
+        write_output, FileProcessed, FileQueuedForProcessing, ProcessingKind,
+        ProcessingTask, ProcessingType,
+    },
+    profiling::{ProcessingStage, StageTimings},
+};
+
+/// Half float RGBA, which keeps the HDR range and is directly sampleable by Bevy
+const VK_FORMAT_R16G16B16A16_SFLOAT: u32 = 97;
+
+#[derive(Component)]
+pub struct FileEnvironment;
+
+/// Converts equirectangular `.hdr`/`.exr` panoramas into cubemap `.ktx2` files, for skyboxes and image based lighting
+pub struct ProcessingEnvironment;
+
+impl ProcessingType for ProcessingEnvironment {
+    type Comp = FileEnvironment;
+
+    fn get_component() -> Self::Comp {
+        FileEnvironment
+    }
+
+    fn matches(ext: &String, config: &Config) -> bool {
+        config.extensions.environment.contains(ext)
+            || config.is_mapped_extension(ProcessingKind::Environment, ext)
+    }
+
+    fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf> {
+        let mut dest_path = config.get_output_path(source)?;
+        dest_path.set_extension(config.get_output_extension(ProcessingKind::Environment)?);
+        config.apply_output_template(ProcessingKind::Environment, source, dest_path, |_| None)
+    }
+
+    fn is_enabled(config: &Config) -> bool {
+        config.environments.enabled
+    }
+
+    fn system(
+        query: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
+        config: Res<Config>,
+        mut commands: Commands,
+        _: EventWriter<FileProcessed>,
+        _: ResMut<StageTimings>,
+        _: Query<(&FileQueuedForProcessing, &ProcessingTask)>,
+    ) {
+        for (e, entry) in query.iter() {
+            let environment_config = entry.get_config(&config).environments.clone();
+            let source = entry.source.clone();
+            let dest = entry.dest.clone();
+            // resampling every face texel and encoding the KTX2 is far too slow to run inside the frame
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let mut timings = StageTimings::default();
+                let result =
+                    process_environment(&source, &dest, &environment_config, &mut timings);
+                (result, timings)
+            });
+            commands
+                .entity(e)
+                .remove::<FileEnvironment>()
+                .insert(ProcessingTask::new(ProcessingKind::Environment, task));
+        }
+    }
+}
+
+fn process_environment(
+    source: &PathBuf,
+    dest: &PathBuf,
+    config: &EnvironmentConfigs,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    let panorama = timings.time(ProcessingStage::Import, || {
+        check_media_file(source)?;
+        image::open(source)
+            .map(|image| image.to_rgba32f())
+            .map_err(|err| format!("Failed to decode environment map: {}", err))
+    })?;
+    let bytes = timings.time(ProcessingStage::Export, || {
+        encode_cubemap(&panorama, config.face_size)
+    })?;
+    timings.time(ProcessingStage::Write, || {
+        write_output(dest, bytes).map_err(|err| format!("Failed to write cubemap: {}", err))
+    })
+}
+
+/// Renders each cube face by looking up its texel directions in the panorama, in the +X, -X, +Y, -Y, +Z, -Z face order
+/// ktx2 expects
+fn encode_cubemap(panorama: &Rgba32FImage, face_size: u32) -> Result<Vec<u8>, String> {
+    let mut texture = Texture::new(Ktx2CreateInfo {
+        vk_format: VK_FORMAT_R16G16B16A16_SFLOAT,
+        base_width: face_size,
+        base_height: face_size,
+        base_depth: 1,
+        num_dimensions: 2,
+        num_levels: 1,
+        num_layers: 1,
+        num_faces: 6,
+        is_array: false,
+        generate_mipmaps: false,
+        ..Default::default()
+    })
+    .map_err(|err| format!("Failed to create ktx2 cubemap: {:?}", err))?;
+    for face in 0..6 {
+        let texels = (0..face_size * face_size)
+            .flat_map(|index| {
+                let direction =
+                    get_face_direction(face, index % face_size, index / face_size, face_size);
+                sample_equirect(panorama, direction)
+            })
+            .flat_map(|channel| f16::from_f32(channel).to_le_bytes())
+            .collect::<Vec<_>>();
+        let offset = texture
+            .get_image_offset(0, 0, face)
+            .map_err(|err| format!("Failed to create ktx2 cubemap: {:?}", err))?;
+        texture.data_mut()[offset..offset + texels.len()].copy_from_slice(&texels);
+    }
+    let mut bytes = Vec::new();
+    texture
+        .write_to(&mut StreamSink::new(&mut Cursor::new(&mut bytes)))
+        .map_err(|err| format!("Failed to encode ktx2 cubemap: {:?}", err))?;
+    Ok(bytes)
+}
+
+/// The direction through the center of a cube face texel, following the Vulkan cubemap face layout
+fn get_face_direction(face: u32, x: u32, y: u32, face_size: u32) -> Vec3 {
+    let s = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+    let t = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+    let direction = match face {
+        0 => Vec3::new(1.0, -t, -s),
+        1 => Vec3::new(-1.0, -t, s),
+        2 => Vec3::new(s, 1.0, t),
+        3 => Vec3::new(s, -1.0, -t),
+        4 => Vec3::new(s, -t, 1.0),
+        _ => Vec3::new(-s, -t, -1.0),
+    };
+    direction.normalize()
+}
+
+/// Bilinearly samples the panorama in a direction, wrapping around horizontally
+fn sample_equirect(panorama: &Rgba32FImage, direction: Vec3) -> [f32; 4] {
+    let (width, height) = panorama.dimensions();
+    let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+    let v = direction.y.clamp(-1.0, 1.0).acos() / PI;
+    let x = u * width as f32 - 0.5;
+    let y = (v * height as f32 - 0.5).clamp(0.0, height as f32 - 1.0);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let texel = |x: f32, y: f32| {
+        let x = (x as i64).rem_euclid(width as i64) as u32;
+        let y = (y as u32).min(height - 1);
+        panorama.get_pixel(x, y).0
+    };
+    let (a, b) = (texel(x0, y0), texel(x0 + 1.0, y0));
+    let (c, d) = (texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+    let mut sample = [0.0; 4];
+    for i in 0..3 {
+        let top = a[i] + (b[i] - a[i]) * fx;
+        let bottom = c[i] + (d[i] - c[i]) * fx;
+        sample[i] = top + (bottom - top) * fy;
+    }
+    // environment maps are opaque, whatever alpha the source format reported
+    sample[3] = 1.0;
+    sample
+}
@@ -0,0 +1,96 @@
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    processing::{write_output, FileProcessed},
+};
+
+/// Written to the output directory whenever files fail, so failures can be inspected after the process exits
+pub const ERRORS_FILE_NAME: &str = ".bpm-errors.json";
+
+/// Every source file that currently fails to process, keyed by source path
+#[derive(Resource, Serialize, Deserialize, Default, Debug)]
+pub struct ErrorSummary {
+    pub failures: BTreeMap<PathBuf, FailureEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FailureEntry {
+    /// The processor that failed, e.g. `Texture`
+    pub processor: String,
+    pub error: String,
+    /// How many times in a row processing this file has failed, across runs
+    pub attempts: u32,
+}
+
+impl ErrorSummary {
+    pub fn get_path(config: &Config) -> PathBuf {
+        config.output_dir.join(ERRORS_FILE_NAME)
+    }
+
+    /// Loads the previous summary so attempt counts carry over between runs
+    pub fn load(config: &Config) -> Self {
+        fs::read_to_string(Self::get_path(config))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the summary, or removes the file once nothing is failing anymore
+    pub fn save(&self, config: &Config) {
+        let path = Self::get_path(config);
+        if self.failures.is_empty() {
+            if let Err(err) = fs::remove_file(&path) {
+                if err.kind() != ErrorKind::NotFound {
+                    error!("Failed to remove error summary: {}", err);
+                }
+            }
+            return;
+        }
+        let text = match serde_json::to_string_pretty(self) {
+            Ok(text) => text,
+            Err(err) => {
+                error!("Failed to serialize error summary: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = write_output(&path, text) {
+            error!("Failed to write error summary: {}", err);
+        }
+    }
+}
+
+/// Adds failed files to the error summary, drops files that now succeed, and persists it when anything changed
+pub fn record_failures(
+    mut events: EventReader<FileProcessed>,
+    mut summary: ResMut<ErrorSummary>,
+    config: Res<Config>,
+) {
+    let mut changed = false;
+    for event in events.read() {
+        match &event.result {
+            Ok(_) => changed |= summary.failures.remove(&event.source).is_some(),
+            Err(err) => {
+                let attempts = summary
+                    .failures
+                    .get(&event.source)
+                    .map_or(0, |entry| entry.attempts);
+                summary.failures.insert(
+                    event.source.clone(),
+                    FailureEntry {
+                        processor: format!("{:?}", event.kind),
+                        error: err.clone(),
+                        attempts: attempts + 1,
+                    },
+                );
+                changed = true;
+            }
+        }
+    }
+    if changed {
+        summary.save(&config);
+    }
+}
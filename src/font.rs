@@ -0,0 +1,271 @@
+use std::{
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use ab_glyph::{point, Font, FontVec, GlyphId, PxScale, ScaleFont};
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use serde::Serialize;
+
+use crate::{
+    config::{Config, FontAtlasConfigs},
+    media::check_media_file,
+    processing::{
+        copy_output, write_output, FileProcessed,
+        FileQueuedForProcessing, ProcessingKind, ProcessingTask, ProcessingType,
+    },
+    profiling::{ProcessingStage, StageTimings},
+    texture::generate_sdf,
+};
+
+#[derive(Component)]
+pub struct FileFont;
+
+/// Copies `.ttf`/`.otf` fonts to the output once they parse, optionally rendering a glyph atlas next to them
+pub struct ProcessingFont;
+
+impl ProcessingType for ProcessingFont {
+    type Comp = FileFont;
+
+    fn get_component() -> Self::Comp {
+        FileFont
+    }
+
+    fn matches(ext: &String, config: &Config) -> bool {
+        config.extensions.font.contains(ext)
+            || config.is_mapped_extension(ProcessingKind::Font, ext)
+    }
+
+    fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf> {
+        let dest_path = config.get_output_path(source)?;
+        config.apply_output_template(ProcessingKind::Font, source, dest_path, |_| None)
+    }
+
+    fn is_enabled(config: &Config) -> bool {
+        config.fonts.enabled
+    }
+
+    fn get_outputs(source: &PathBuf, config: &Config) -> Vec<PathBuf> {
+        let Some(dest) = Self::get_destination(source, config) else {
+            return vec![];
+        };
+        let mut outputs = vec![dest.clone()];
+        if config.fonts.atlas.is_some() {
+            let (image_path, layout_path) = get_atlas_paths(&dest);
+            outputs.extend([image_path, layout_path]);
+        }
+        outputs
+    }
+
+    fn system(
+        query: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
+        config: Res<Config>,
+        mut commands: Commands,
+        _: EventWriter<FileProcessed>,
+        _: ResMut<StageTimings>,
+        _: Query<(&FileQueuedForProcessing, &ProcessingTask)>,
+    ) {
+        for (e, entry) in query.iter() {
+            let atlas_config = entry.get_config(&config).fonts.atlas.clone();
+            let source = entry.source.clone();
+            let dest = entry.dest.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let mut timings = StageTimings::default();
+                let result = process_font(&source, &dest, atlas_config.as_ref(), &mut timings);
+                (result, timings)
+            });
+            commands
+                .entity(e)
+                .remove::<FileFont>()
+                .insert(ProcessingTask::new(ProcessingKind::Font, task));
+        }
+    }
+}
+
+fn process_font(
+    source: &PathBuf,
+    dest: &PathBuf,
+    atlas: Option<&FontAtlasConfigs>,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    let font = timings.time(ProcessingStage::Import, || {
+        check_media_file(source)?;
+        let bytes = fs::read(source).map_err(|err| format!("Failed to read font: {}", err))?;
+        FontVec::try_from_vec(bytes).map_err(|err| format!("Failed to parse font: {}", err))
+    })?;
+    let atlas = match atlas {
+        Some(config) => Some(timings.time(ProcessingStage::Export, || {
+            let (image, layout) = render_atlas(&font, config)?;
+            let mut bytes = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+                .map_err(|err| format!("Failed to encode glyph atlas: {}", err))?;
+            let layout = serde_json::to_string_pretty(&layout)
+                .map_err(|err| format!("Failed to serialize glyph atlas layout: {}", err))?;
+            Ok::<_, String>((bytes, layout))
+        })?),
+        None => None,
+    };
+    timings.time(ProcessingStage::Write, || {
+        copy_output(source, dest).map_err(|err| format!("Failed to copy font: {}", err))?;
+        let Some((image, layout)) = atlas else {
+            return Ok(());
+        };
+        let (image_path, layout_path) = get_atlas_paths(dest);
+        write_output(&image_path, image)
+            .map_err(|err| format!("Failed to write glyph atlas: {}", err))?;
+        write_output(&layout_path, layout)
+            .map_err(|err| format!("Failed to write glyph atlas layout: {}", err))
+    })
+}
+
+/// `font.ttf` => (`font.atlas.png`, `font.atlas.json`)
+fn get_atlas_paths(dest: &Path) -> (PathBuf, PathBuf) {
+    (
+        dest.with_extension("atlas.png"),
+        dest.with_extension("atlas.json"),
+    )
+}
+
+/// Layout of a glyph atlas, written next to it. Metrics are in pixels at `size`
+#[derive(Serialize, Debug)]
+struct AtlasLayout {
+    size: f32,
+    ascent: f32,
+    descent: f32,
+    line_gap: f32,
+    width: u32,
+    height: u32,
+    glyphs: Vec<AtlasGlyph>,
+}
+
+#[derive(Serialize, Debug)]
+struct AtlasGlyph {
+    character: char,
+    /// The glyph's rect in the atlas image. Empty for glyphs with no outline, like spaces
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    /// Where the rect's top left corner sits relative to the pen position on the baseline, y pointing down
+    offset_x: f32,
+    offset_y: f32,
+    advance: f32,
+}
+
+/// Rasterizes each configured character as white with coverage in alpha, packed into rows of `width`. Characters the
+/// font has no glyph for are left out with a warning
+fn render_atlas(
+    font: &FontVec,
+    config: &FontAtlasConfigs,
+) -> Result<(DynamicImage, AtlasLayout), String> {
+    let scale = PxScale::from(config.size);
+    let scaled = font.as_scaled(scale);
+    let mut characters = config.characters.chars().collect::<Vec<_>>();
+    characters.sort_unstable();
+    characters.dedup();
+    let (characters, missing): (Vec<_>, Vec<_>) = characters
+        .into_iter()
+        .partition(|&character| font.glyph_id(character) != GlyphId(0));
+    if !missing.is_empty() {
+        warn!(
+            "Font has no glyphs for {}, leaving them out of the atlas",
+            missing.iter().collect::<String>()
+        );
+    }
+    let mut glyphs = characters
+        .into_iter()
+        .map(|character| {
+            let id = font.glyph_id(character);
+            let outline = font.outline_glyph(id.with_scale_and_position(scale, point(0.0, 0.0)));
+            (character, scaled.h_advance(id), outline)
+        })
+        .collect::<Vec<_>>();
+    // tallest first, so each row wastes as little height as possible
+    glyphs.sort_by_key(|(_, _, outline)| {
+        std::cmp::Reverse(
+            outline
+                .as_ref()
+                .map_or(0, |outline| outline.px_bounds().height().ceil() as u32),
+        )
+    });
+
+    let padding = config.padding;
+    let (mut cursor_x, mut cursor_y, mut row_height) = (padding, padding, 0);
+    let mut placed = Vec::with_capacity(glyphs.len());
+    for (character, advance, outline) in &glyphs {
+        let mut glyph = AtlasGlyph {
+            character: *character,
+            x: 0,
+            y: 0,
+            width: 0,
+            height: 0,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            advance: *advance,
+        };
+        if let Some(outline) = outline {
+            let bounds = outline.px_bounds();
+            let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+            if width + padding * 2 > config.width {
+                return Err(format!(
+                    "Glyph '{}' is {} pixels wide, which doesn't fit in an atlas {} pixels wide",
+                    character, width, config.width
+                ));
+            }
+            if cursor_x + width + padding > config.width {
+                cursor_x = padding;
+                cursor_y += row_height + padding;
+                row_height = 0;
+            }
+            glyph.x = cursor_x;
+            glyph.y = cursor_y;
+            glyph.width = width;
+            glyph.height = height;
+            glyph.offset_x = bounds.min.x;
+            glyph.offset_y = bounds.min.y;
+            cursor_x += width + padding;
+            row_height = row_height.max(height);
+        }
+        placed.push(glyph);
+    }
+    let atlas_height = (cursor_y + row_height + padding).max(1);
+
+    let mut atlas = RgbaImage::from_pixel(config.width, atlas_height, Rgba([255, 255, 255, 0]));
+    for ((_, _, outline), glyph) in glyphs.iter().zip(&placed) {
+        let Some(outline) = outline else {
+            continue;
+        };
+        outline.draw(|x, y, coverage| {
+            let pixel = atlas.get_pixel_mut(glyph.x + x, glyph.y + y);
+            pixel[3] = pixel[3].max((coverage.clamp(0.0, 1.0) * 255.0).round() as u8);
+        });
+    }
+    let mut image = DynamicImage::ImageRgba8(atlas);
+    if let Some(sdf) = &config.sdf {
+        image = generate_sdf(&image, sdf);
+    }
+    // a scaled down distance field shrinks the rects along with it
+    let rect_scale = image.width() as f32 / config.width as f32;
+    let to_atlas = |value: u32| (value as f32 * rect_scale).round() as u32;
+    let mut glyphs = placed;
+    for glyph in &mut glyphs {
+        glyph.x = to_atlas(glyph.x);
+        glyph.y = to_atlas(glyph.y);
+        glyph.width = to_atlas(glyph.width);
+        glyph.height = to_atlas(glyph.height);
+    }
+    glyphs.sort_by_key(|glyph| glyph.character);
+    let layout = AtlasLayout {
+        size: config.size,
+        ascent: scaled.ascent(),
+        descent: scaled.descent(),
+        line_gap: scaled.line_gap(),
+        width: image.width(),
+        height: image.height(),
+        glyphs,
+    };
+    Ok((image, layout))
+}
@@ -0,0 +1,133 @@
+use bevy::{
+    core::{TaskPoolOptions, TaskPoolPlugin},
+    prelude::*,
+};
+use config::{Config, TaskPoolConfigs};
+use errors::ErrorSummary;
+use manifest::Manifest;
+use pause::ProcessingPaused;
+use processing::{FileProcessed, ProcessingType, RefreshTimer, UnprocessedFiles};
+use profiling::StageTimings;
+use report::ProcessingReport;
+use shutdown::ShutdownRequested;
+use status::ProcessingStatus;
+
+pub mod archive;
+pub mod audio;
+pub mod bench;
+pub mod compress;
+pub mod config;
+pub mod draco;
+pub mod environment;
+pub mod errors;
+pub mod font;
+pub mod manifest;
+pub mod media;
+pub mod mesh;
+pub mod mesh_ops;
+pub mod meta;
+pub mod metrics;
+pub mod pause;
+pub mod preload;
+pub mod processing;
+pub mod profiling;
+pub mod prune;
+pub mod raw;
+pub mod remote;
+pub mod report;
+pub mod scene;
+pub mod shader;
+pub mod shutdown;
+pub mod staging;
+pub mod status;
+pub mod stdin;
+pub mod telemetry;
+pub mod texture;
+pub mod version;
+
+/// Processes every stale source once without a window or renderer, and returns what happened. For embedding bpm in
+/// other tools, e.g. asserting `report.failed.is_empty()` in a game's own tests
+pub fn process_once(config: Config) -> ProcessingReport {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins.set(TaskPoolPlugin {
+        task_pool_options: get_task_pool_options(&config.task_pools),
+    }));
+    let manifest = Manifest::load(&config);
+    add_processing(&mut app, config, manifest);
+    app.add_systems(
+        Update,
+        processing::check_for_stale_files.run_if(pause::is_not_paused),
+    );
+    loop {
+        app.update();
+        if app.should_exit().is_some() || app.world().resource::<UnprocessedFiles>().is_finished()
+        {
+            break;
+        }
+    }
+    app.world_mut()
+        .remove_resource::<ProcessingReport>()
+        .unwrap_or_default()
+}
+
+/// Adds the resources and systems every run needs to process files, leaving how files get queued to the caller.
+/// Insert a [`ShutdownRequested`] first to react to Ctrl-C
+pub fn add_processing(app: &mut App, config: Config, manifest: Manifest) {
+    let error_summary = ErrorSummary::load(&config);
+    app.insert_resource(config)
+        .insert_resource(manifest)
+        .insert_resource(error_summary)
+        .insert_resource(UnprocessedFiles::default())
+        .init_resource::<StageTimings>()
+        .init_resource::<ProcessingReport>()
+        .init_resource::<ProcessingPaused>()
+        .init_resource::<ProcessingStatus>()
+        .init_resource::<ShutdownRequested>()
+        .add_event::<FileProcessed>()
+        .add_systems(Startup, initialize)
+        .add_systems(PreUpdate, processing::drop_deleted_sources)
+        .add_systems(Update, processing::poll_processing_tasks)
+        .add_systems(
+            PostUpdate,
+            (
+                manifest::record_processed_files.after(processing::track_processed_files),
+                errors::record_failures,
+                report::record_report,
+                processing::track_processed_files,
+                compress::precompress_outputs,
+            ),
+        )
+        .add_systems(Last, shutdown::handle_shutdown);
+    raw::ProcessingRaw::register(app);
+    mesh::ProcessingMesh::register(app);
+    texture::ProcessingTexture::register(app);
+    shader::ProcessingShader::register(app);
+    archive::ProcessingArchive::register(app);
+    environment::ProcessingEnvironment::register(app);
+    font::ProcessingFont::register(app);
+}
+
+/// Applies the configured shares on top of Bevy's defaults, which are tuned for game frame budgets
+pub fn get_task_pool_options(config: &TaskPoolConfigs) -> TaskPoolOptions {
+    let mut options = TaskPoolOptions::default();
+    if let Some(max) = config.max_threads {
+        options.max_total_threads = max;
+    }
+    if let Some(share) = config.io_share {
+        options.io.percent = share;
+    }
+    if let Some(share) = config.async_compute_share {
+        options.async_compute.percent = share;
+    }
+    if let Some(share) = config.compute_share {
+        options.compute.percent = share;
+    }
+    options
+}
+
+fn initialize(mut commands: Commands, config: Res<Config>) {
+    commands.spawn(RefreshTimer(Timer::from_seconds(
+        config.file_watching_rate_seconds as f32,
+        TimerMode::Repeating,
+    )));
+}
@@ -1,95 +1,438 @@
 use std::{
     fs::{self},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use bevy::{
+    core::TaskPoolPlugin,
     log::{Level, LogPlugin},
     pbr::{experimental::meshlet::MeshletPlugin, MeshRenderPlugin},
     prelude::*,
-    render::{mesh::MeshPlugin, pipelined_rendering::PipelinedRenderingPlugin, RenderPlugin},
+    render::{
+        mesh::MeshPlugin,
+        pipelined_rendering::PipelinedRenderingPlugin,
+        settings::{RenderCreation, WgpuSettings},
+        RenderPlugin,
+    },
+    tasks::block_on,
 };
 use bevy_gltf_kun::GltfKunPlugin;
-use clap::Parser;
-use config::Config;
-use mesh::ProcessingMesh;
-use processing::{ProcessingType, RefreshTimer, UnprocessedFiles};
-use raw::ProcessingRaw;
-
-mod audio;
-mod config;
-mod mesh;
-mod processing;
-mod raw;
-mod texture;
+use bpm::{
+    bench::BenchRun,
+    config::{self, Config, DummyCamera, OverwritePolicy},
+    get_task_pool_options,
+    manifest::Manifest,
+    metrics, pause, processing,
+    processing::UnprocessedFiles,
+    profiling::StageTimings,
+    prune,
+    remote::{self, SourceBackend},
+    report::ProcessingReport,
+    shutdown::ShutdownRequested,
+    staging::StagedOutput,
+    status::{self, ProcessingStatus},
+    stdin::{self, RequestedPaths},
+    telemetry::{self, OtlpEndpoint},
+    version,
+};
+use clap::{Parser, Subcommand};
+use directories::ProjectDirs;
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about=None)]
+#[command(version, about, long_about=None, disable_version_flag = true)]
 struct Cli {
+    /// Prints the version
+    #[arg(short = 'V', long, value_name = "BOOL", action=clap::ArgAction::SetTrue)]
+    version: Option<bool>,
+    /// With `--version`, also prints the versions of key dependencies and the build target, for bug reports
+    #[arg(long, value_name = "BOOL", action=clap::ArgAction::SetTrue, requires = "version")]
+    detailed: Option<bool>,
     #[arg(short, long, value_name = "BOOL", action=clap::ArgAction::SetTrue)]
     oneshot: Option<bool>,
     #[arg(short, long, value_name = "BOOL", action=clap::ArgAction::SetTrue)]
     verbose: Option<bool>,
+    /// Only prints warnings and errors, for scripts
+    #[arg(short, long, value_name = "BOOL", action=clap::ArgAction::SetTrue, conflicts_with = "verbose")]
+    quiet: Option<bool>,
+    /// Prints a breakdown of time spent in each processing stage when the run finishes. Was `--profile` before config
+    /// profiles took that name
+    #[arg(long, value_name = "BOOL", action=clap::ArgAction::SetTrue)]
+    timings: Option<bool>,
+    /// Applies a named profile from the config, e.g. `--profile mobile`. The stage timing breakdown that used to be
+    /// behind a bare `--profile` is now `--timings`
+    #[arg(long, value_name = "NAME", num_args = 0..=1, default_missing_value = "")]
+    profile: Option<String>,
+    /// Reprocesses every source file once, even ones whose outputs are up to date. Existing outputs are overwritten,
+    /// not deleted first
+    #[arg(long, value_name = "BOOL", action=clap::ArgAction::SetTrue)]
+    force: Option<bool>,
+    /// Runs once, writing into a staging copy of the output directory that only replaces it if every file succeeds
+    #[arg(long, value_name = "BOOL", action=clap::ArgAction::SetTrue)]
+    staged: Option<bool>,
+    /// Processes only the newline separated source paths read from stdin instead of scanning the source directory
+    #[arg(long, value_name = "BOOL", action=clap::ArgAction::SetTrue)]
+    stdin_paths: Option<bool>,
+    /// Path to a configuration file. Takes precedence over the local and user configs
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Creates the output directory structure mirroring the source without processing any files
+    Scaffold,
+    /// Prints the source files that are currently stale, without processing them
+    Stale,
+    /// Checks that every output recorded in the manifest still exists unchanged, listing any that drifted
+    VerifyManifest,
+    /// Deletes outputs whose sources no longer exist, like after deleting or renaming a source file
+    Prune {
+        /// Only list the orphaned outputs instead of deleting them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Lists the files a bpm watching the same output directory is currently working on
+    Status,
+    /// Reprocesses only the files that failed on their most recent run, then exits
+    RetryFailed,
+    /// Generates synthetic source files in a scratch directory and reports how fast a full pass processes them
+    Bench {
+        /// How many files to generate
+        #[arg(long, default_value_t = 100)]
+        files: usize,
+        /// Size of each file in bytes
+        #[arg(long, default_value_t = 64 * 1024)]
+        size: usize,
+    },
 }
 
 fn main() {
     let cli = Cli::parse();
-    let config = load_configuration().unwrap_or_default();
+    if cli.version.unwrap_or(false) {
+        if cli.detailed.unwrap_or(false) {
+            println!("{}", version::get_detailed_version());
+        } else {
+            println!("bpm {}", env!("CARGO_PKG_VERSION"));
+        }
+        return;
+    }
+    let mut config = load_configuration(cli.config.clone()).unwrap_or_default();
+    if let Some(profile) = &cli.profile {
+        if profile.is_empty() {
+            // a bare `--profile` used to print stage timings, so point old scripts at the new flag
+            eprintln!("--profile now takes the name of a config profile. Use --timings for the stage timing breakdown");
+            std::process::exit(1);
+        }
+        config = match config.with_profile(profile) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
+    }
+    if cli.force.unwrap_or(false) {
+        config.overwrite_policy = OverwritePolicy::Always;
+    }
+    let source_backend = SourceBackend::from_config(&mut config);
+    match source_backend.sync() {
+        Ok(summary) => {
+            for err in summary.failed {
+                eprintln!("{}", err);
+            }
+        }
+        Err(err) => {
+            eprintln!("Failed to sync remote source: {}", err);
+            std::process::exit(1);
+        }
+    }
+    let mut bench = None;
+    match cli.command {
+        Some(Command::Scaffold) => {
+            let count = processing::scaffold_output_dirs(&config);
+            println!(
+                "Scaffolded output directories for {} files under {}",
+                count,
+                config.output_dir.display()
+            );
+            return;
+        }
+        Some(Command::Stale) => {
+            for path in processing::list_stale_files(&config) {
+                println!("{}", path.display());
+            }
+            return;
+        }
+        Some(Command::VerifyManifest) => {
+            let drift = Manifest::load(&config).verify();
+            for (path, kind) in &drift {
+                println!("{}: {}", kind, path.display());
+            }
+            if !drift.is_empty() {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Status) => {
+            match ProcessingStatus::load(&config) {
+                Ok(status) => {
+                    for file in &status.files {
+                        let state = match file.kind {
+                            Some(kind) => format!("{:?}", kind).to_lowercase(),
+                            None => "waiting".into(),
+                        };
+                        println!(
+                            "{} ({}, {:.1}s): {}",
+                            file.source.display(),
+                            state,
+                            file.queued_seconds,
+                            file.dest.display()
+                        );
+                    }
+                    println!(
+                        "{} files in progress{}",
+                        status.files.len(),
+                        if status.paused { ", paused" } else { "" }
+                    );
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Prune { dry_run }) => {
+            match prune::prune_orphaned_outputs(&config, dry_run) {
+                Ok(orphans) => {
+                    for path in &orphans {
+                        println!("{}", path.display());
+                    }
+                    println!(
+                        "{} {} orphaned outputs",
+                        if dry_run { "Found" } else { "Pruned" },
+                        orphans.len()
+                    );
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Bench { files, size }) => {
+            match BenchRun::generate(&mut config, files, size) {
+                Ok(run) => bench = Some(run),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Command::RetryFailed) | None => {}
+    }
+    let retry_failed = matches!(cli.command, Some(Command::RetryFailed));
+    let has_render_device = has_render_device();
+    if !has_render_device && config.meshes.use_meshlets {
+        // logging isn't set up yet, and this needs to be seen
+        eprintln!("Meshlet processing needs a GPU render device to build meshlets, but none was found. Either run on a machine with a GPU or set `use_meshlets = false` under [meshes]");
+        std::process::exit(1);
+    }
+    let manifest = Manifest::load(&config);
+    let manifest_failures = manifest.failed.keys().cloned().collect::<Vec<_>>();
+    if retry_failed && manifest_failures.is_empty() {
+        if !cli.quiet.unwrap_or_default() {
+            println!("No failed files to retry");
+        }
+        return;
+    }
+    let staged = if cli.staged.unwrap_or(false) {
+        match StagedOutput::begin(&mut config) {
+            Ok(staged) => Some(staged),
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    let oneshot =
+        cli.oneshot.unwrap_or(false) || retry_failed || bench.is_some() || staged.is_some();
     let mut app = App::new();
+    let otlp_endpoint = OtlpEndpoint::from_config(&config);
+    if let Some(endpoint) = &otlp_endpoint {
+        app.insert_resource(endpoint.clone());
+    }
 
     app.add_plugins((
-        DefaultPlugins.set(LogPlugin {
-            level: if cli.verbose.unwrap_or_default() {
-                Level::DEBUG
-            } else {
-                Level::INFO
-            },
-            filter: "error,bpm=debug".into(),
-            ..default()
-        }),
-        MeshletPlugin,
+        DefaultPlugins
+            .set(LogPlugin {
+                level: if cli.verbose.unwrap_or_default() {
+                    Level::DEBUG
+                } else if cli.quiet.unwrap_or_default() {
+                    Level::WARN
+                } else {
+                    Level::INFO
+                },
+                filter: if cli.quiet.unwrap_or_default() {
+                    "error,bpm=warn".into()
+                } else {
+                    "error,bpm=debug".into()
+                },
+                custom_layer: telemetry::get_otlp_layer,
+            })
+            .set(TaskPoolPlugin {
+                task_pool_options: get_task_pool_options(&config.task_pools),
+            })
+            .set(RenderPlugin {
+                // without a device, skip creating the renderer entirely rather than panicking during startup
+                render_creation: if has_render_device {
+                    RenderCreation::default()
+                } else {
+                    RenderCreation::Automatic(WgpuSettings {
+                        backends: None,
+                        ..default()
+                    })
+                },
+                ..default()
+            }),
         GltfKunPlugin::default(),
-    ))
-    .insert_resource(config)
-    .insert_resource(UnprocessedFiles(1))
-    .add_systems(Startup, initialize)
-    .add_systems(Update, processing::check_for_stale_files);
-    ProcessingRaw::register(&mut app);
-    ProcessingMesh::register(&mut app);
-
-    let oneshot = cli.oneshot.unwrap_or(false);
+    ));
+    if has_render_device {
+        app.add_plugins(MeshletPlugin);
+    } else {
+        warn!("No GPU render device found. Continuing headless, which only affects meshlet processing");
+    }
+    // the camera is never rendered through. Meshlet processing only needs the render device, not a camera
+    let spawn_camera = match config.dummy_camera {
+        DummyCamera::Always => true,
+        DummyCamera::Never => false,
+        DummyCamera::Auto => !oneshot && has_render_device,
+    };
+    app.insert_resource(ShutdownRequested::install());
+    bpm::add_processing(&mut app, config, manifest);
+    let requested = if retry_failed {
+        Some(RequestedPaths::from_list(manifest_failures))
+    } else if cli.stdin_paths.unwrap_or(false) {
+        Some(RequestedPaths::from_stdin())
+    } else {
+        None
+    };
+    if let Some(requested) = requested {
+        app.insert_resource(requested)
+            .add_systems(Update, stdin::queue_requested_paths);
+    } else {
+        app.add_systems(
+            Update,
+            processing::check_for_stale_files.run_if(pause::is_not_paused),
+        );
+    }
+    if !oneshot {
+        app.add_systems(
+            Update,
+            (pause::check_pause_file, status::update_processing_status),
+        );
+    }
+    if spawn_camera {
+        app.add_systems(Startup, spawn_dummy_camera);
+    }
+    if source_backend.is_remote() && !oneshot {
+        app.add_systems(Update, remote::sync_remote_source);
+    }
+    app.insert_resource(source_backend);
 
     if oneshot {
         loop {
             app.update();
-            if app.world().resource::<UnprocessedFiles>().0 <= 0 {
-                // ensures that everything gets processed even if that takes multiple cycles
+            if app.should_exit().is_some() {
+                break;
+            }
+            if app.world().resource::<UnprocessedFiles>().is_finished() {
                 break;
             }
         }
+        info!("{}", app.world().resource::<ProcessingReport>().summary());
     } else {
         app.run();
     }
+    if cli.timings.unwrap_or(false) {
+        info!("{}", app.world().resource::<StageTimings>().report());
+    }
+    if let Some(path) = &app.world().resource::<Config>().metrics_csv {
+        let result = metrics::append_build_metrics(
+            path,
+            app.world().resource::<ProcessingReport>(),
+            app.world().resource::<StageTimings>(),
+        );
+        if let Err(err) = result {
+            error!("{}", err);
+        }
+    }
+    if otlp_endpoint.is_some() {
+        telemetry::shutdown();
+    }
+    if let Some(bench) = bench {
+        bench.finish();
+    }
+    if let Some(staged) = staged {
+        let unprocessed = app.world().resource::<UnprocessedFiles>();
+        let succeeded = unprocessed.is_finished() && unprocessed.failed == 0;
+        if let Err(err) = staged.finish(app.world().resource::<Config>(), succeeded) {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+        if !succeeded {
+            error!("Staged run did not finish cleanly, so the output directory was left unchanged");
+            std::process::exit(1);
+        }
+    }
     debug!("Handled CLI data {:?}", cli);
 }
-fn initialize(mut commands: Commands, config: Res<Config>) {
-    commands.spawn(RefreshTimer(Timer::from_seconds(
-        config.file_watching_rate_seconds as f32,
-        TimerMode::Repeating,
-    )));
+/// Checks for a usable GPU adapter the same way bevy's renderer would, without committing to creating one
+fn has_render_device() -> bool {
+    let instance = wgpu::Instance::default();
+    block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())).is_some()
+}
+
+fn spawn_dummy_camera(mut commands: Commands) {
     commands.spawn(Camera2dBundle::default()); // satisfy bevy's rendering cravings
 }
 
-fn load_configuration() -> Option<Config> {
+/// Loads the configuration, checking in order of precedence:
+/// 1. The `--config` CLI flag
+/// 2. The local `assets-dev/config.toml`
+/// 3. The user config dir (e.g. `~/.config/bpm/config.toml` on linux)
+/// 4. The built-in default (which also gets written out as the local config)
+fn load_configuration(cli_path: Option<PathBuf>) -> Option<Config> {
+    if let Some(path) = cli_path {
+        return read_configuration(&path);
+    }
     let _ = fs::create_dir(Path::new("assets-dev")); // ignore errors
     let config_path = Path::new("assets-dev").join("config.toml");
+    if config_path.exists() {
+        return read_configuration(&config_path);
+    }
+    if let Some(dirs) = ProjectDirs::from("", "", "bpm") {
+        let user_config_path = dirs.config_dir().join("config.toml");
+        if user_config_path.exists() {
+            return read_configuration(&user_config_path);
+        }
+    }
 
-    let Ok(file_data) = fs::read(config_path.clone()) else {
-        let Some(config_text) = config::get_default_configuration_text() else {
-            return None;
-        };
+    let Some(config_text) = config::get_default_configuration_text() else {
+        return None;
+    };
+    let _ = fs::write(config_path, config_text);
+    None
+}
 
-        let _ = fs::write(config_path, config_text);
+fn read_configuration(config_path: &Path) -> Option<Config> {
+    let Ok(file_data) = fs::read(config_path) else {
+        error!("Failed to read configuration at {}", config_path.display());
         return None;
     };
     let Ok(file_text) = String::from_utf8(file_data) else {
@@ -0,0 +1,437 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    hash::{DefaultHasher, Hasher},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    compress::get_compressed_path,
+    config::{Config, ManifestFormat},
+    mesh::get_mesh_dependencies,
+    preload::write_preload_list,
+    processing::{
+        get_cache_key, write_output, AssetProcessing, FileProcessed, ProcessingKind,
+        UnprocessedFiles,
+    },
+};
+
+/// The manifest lives in the output directory so it travels with the processed assets
+pub const MANIFEST_FILE_NAME: &str = ".bpm-manifest.json";
+
+/// The manifest under `manifest_format = "Lines"`
+pub const MANIFEST_LINES_FILE_NAME: &str = ".bpm-manifest.jsonl";
+
+pub const GITATTRIBUTES_FILE_NAME: &str = ".gitattributes";
+
+/// Tells git to merge the line manifest by keeping the lines from both sides instead of conflicting
+const GITATTRIBUTES_LINE: &str = "/.bpm-manifest.jsonl merge=union";
+
+/// How many previous runs are kept per entry for spotting regressions
+const HISTORY_LENGTH: usize = 10;
+
+/// A file taking this many times longer than its recorded average gets called out
+const SLOWDOWN_FACTOR: u64 = 10;
+
+/// A persistent record of every processed file, keyed by source path
+#[derive(Resource, Serialize, Deserialize, Default, Debug)]
+pub struct Manifest {
+    pub entries: BTreeMap<PathBuf, ManifestEntry>,
+    /// Source files whose most recent processing failed, with the error. Re-queued by `bpm retry-failed`
+    #[serde(default)]
+    pub failed: BTreeMap<PathBuf, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestEntry {
+    pub dest: PathBuf,
+    pub last_duration_ms: u64,
+    pub output_bytes: u64,
+    /// Hash of the output's contents when it was written, for spotting outputs changed outside bpm
+    #[serde(default)]
+    pub output_hash: Option<u64>,
+    /// Hash of the settings the output was produced with. A different key means the output is out of date
+    #[serde(default)]
+    pub cache_key: Option<u64>,
+    /// Other source files the output was built from, like the textures a gltf references. Changing one reprocesses it
+    #[serde(default)]
+    pub dependencies: Vec<PathBuf>,
+    /// Other files written alongside `dest`, like LODs, glyph atlases, sequence layouts and precompressed copies
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<PathBuf>,
+    /// Rolling record of previous runs, oldest first
+    #[serde(default)]
+    pub history: Vec<ManifestRecord>,
+}
+
+/// One source's state in the line manifest. A source that has both an entry and a failure gets a line for each
+#[derive(Serialize, Deserialize, Debug)]
+struct ManifestLine {
+    source: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entry: Option<ManifestEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    failed: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ManifestRecord {
+    pub duration_ms: u64,
+    pub output_bytes: u64,
+}
+
+impl Manifest {
+    pub fn get_path(config: &Config) -> PathBuf {
+        config.output_dir.join(match config.manifest_format {
+            ManifestFormat::Json => MANIFEST_FILE_NAME,
+            ManifestFormat::Lines => MANIFEST_LINES_FILE_NAME,
+        })
+    }
+
+    /// Loads the manifest from the output directory, starting fresh if it's missing or unreadable. A manifest in the
+    /// other format is picked up too, so switching `manifest_format` doesn't reprocess everything
+    pub fn load(config: &Config) -> Self {
+        let json_path = config.output_dir.join(MANIFEST_FILE_NAME);
+        let lines_path = config.output_dir.join(MANIFEST_LINES_FILE_NAME);
+        let paths = match config.manifest_format {
+            ManifestFormat::Json => [json_path, lines_path],
+            ManifestFormat::Lines => [lines_path, json_path],
+        };
+        let Some((text, path)) = paths
+            .into_iter()
+            .find_map(|path| Some((fs::read_to_string(&path).ok()?, path)))
+        else {
+            return Self::default();
+        };
+        if path.ends_with(MANIFEST_LINES_FILE_NAME) {
+            return Self::from_lines(&text);
+        }
+        match serde_json::from_str(&text) {
+            Ok(manifest) => manifest,
+            Err(err) => {
+                warn!(
+                    "Manifest appears to be corrupted, starting a new one: {}",
+                    err
+                );
+                Self::default()
+            }
+        }
+    }
+
+    /// Reads the line manifest, skipping lines that don't parse, like leftover conflict markers. After a union merge
+    /// the same source can appear on several lines, in which case the last one wins
+    fn from_lines(text: &str) -> Self {
+        let mut manifest = Self::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str::<ManifestLine>(line) {
+                Ok(line) => {
+                    if let Some(entry) = line.entry {
+                        manifest.entries.insert(line.source.clone(), entry);
+                    }
+                    if let Some(err) = line.failed {
+                        manifest.failed.insert(line.source, err);
+                    }
+                }
+                Err(err) => warn!("Skipping unreadable manifest line: {}", err),
+            }
+        }
+        manifest
+    }
+
+    fn to_lines(&self) -> Result<String, serde_json::Error> {
+        let entries = self.entries.iter().map(|(source, entry)| ManifestLine {
+            source: source.clone(),
+            entry: Some(entry.clone()),
+            failed: None,
+        });
+        let failures = self.failed.iter().map(|(source, err)| ManifestLine {
+            source: source.clone(),
+            entry: None,
+            failed: Some(err.clone()),
+        });
+        let mut lines = entries
+            .chain(failures)
+            .map(|line| serde_json::to_string(&line))
+            .collect::<Result<Vec<_>, _>>()?;
+        lines.sort();
+        Ok(lines.into_iter().map(|line| line + "\n").collect())
+    }
+
+    /// Compares every entry against the output tree, listing outputs that were deleted or changed since they were
+    /// written. Entries recorded before output hashes existed are only checked for existence
+    pub fn verify(&self) -> Vec<(PathBuf, ManifestDrift)> {
+        self.entries
+            .values()
+            .filter_map(|entry| {
+                if !entry.dest.exists() {
+                    return Some((entry.dest.clone(), ManifestDrift::Missing));
+                }
+                let recorded = entry.output_hash?;
+                (get_file_hash(&entry.dest) != Some(recorded))
+                    .then(|| (entry.dest.clone(), ManifestDrift::Modified))
+            })
+            .collect()
+    }
+
+    pub fn save(&self, config: &Config) {
+        let text = match config.manifest_format {
+            ManifestFormat::Json => serde_json::to_string_pretty(self),
+            ManifestFormat::Lines => self.to_lines(),
+        };
+        let text = match text {
+            Ok(text) => text,
+            Err(err) => {
+                error!("Failed to serialize manifest: {}", err);
+                return;
+            }
+        };
+        if let Err(err) = write_output(&Self::get_path(config), text) {
+            error!("Failed to write manifest: {}", err);
+            return;
+        }
+        // the manifest in the other format is stale now
+        let other = match config.manifest_format {
+            ManifestFormat::Json => MANIFEST_LINES_FILE_NAME,
+            ManifestFormat::Lines => MANIFEST_FILE_NAME,
+        };
+        let _ = fs::remove_file(config.output_dir.join(other));
+        if config.manifest_format == ManifestFormat::Lines {
+            if let Err(err) = ensure_union_merge(&config.output_dir) {
+                warn!("Failed to mark the manifest for union merges: {}", err);
+            }
+        }
+    }
+}
+
+/// Adds the union merge line to the output directory's `.gitattributes`, keeping anything already in there
+fn ensure_union_merge(output_dir: &Path) -> std::io::Result<()> {
+    let path = output_dir.join(GITATTRIBUTES_FILE_NAME);
+    let mut text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err),
+    };
+    if text.lines().any(|line| line.trim() == GITATTRIBUTES_LINE) {
+        return Ok(());
+    }
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(GITATTRIBUTES_LINE);
+    text.push('\n');
+    fs::write(path, text)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestDrift {
+    Missing,
+    Modified,
+}
+
+impl fmt::Display for ManifestDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ManifestDrift::Missing => "missing",
+            ManifestDrift::Modified => "modified",
+        })
+    }
+}
+
+fn get_file_hash(path: &Path) -> Option<u64> {
+    let bytes = fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&bytes);
+    Some(hasher.finish())
+}
+
+/// The files the processing type wrote besides the destination, skipping any it didn't need this time (like LODs of a
+/// mesh too small to simplify). The precompressed copy is written after this runs, so it's listed whenever it's on
+fn get_secondary_outputs(event: &FileProcessed, config: &Config) -> Vec<PathBuf> {
+    let mut outputs = AssetProcessing::get_outputs(&event.source, config)
+        .into_iter()
+        .filter(|output| *output != event.dest && output.is_file())
+        .collect::<Vec<_>>();
+    if let Some(compression) = &config.precompress {
+        outputs.push(get_compressed_path(&event.dest, compression));
+    }
+    outputs
+}
+
+/// Records stats for each successfully processed file and tracks failures. Changes are persisted once the queue drains,
+/// or every `file_watching_rate_seconds` while it's busy, rather than rewriting the whole manifest every frame
+pub fn record_processed_files(
+    mut events: EventReader<FileProcessed>,
+    mut manifest: ResMut<Manifest>,
+    mut unsaved: Local<Option<Timer>>,
+    unprocessed: Res<UnprocessedFiles>,
+    time: Res<Time>,
+    config: Res<Config>,
+) {
+    for event in events.read() {
+        unsaved.get_or_insert_with(|| {
+            Timer::from_seconds(config.file_watching_rate_seconds as f32, TimerMode::Once)
+        });
+        if let Err(err) = &event.result {
+            manifest.failed.insert(event.source.clone(), err.clone());
+            continue;
+        }
+        manifest.failed.remove(&event.source);
+        let record = ManifestRecord {
+            duration_ms: event.duration.as_millis() as u64,
+            output_bytes: fs::metadata(&event.dest).map_or(0, |meta| meta.len()),
+        };
+        let mut history = manifest
+            .entries
+            .remove(&event.source)
+            .map(|entry| entry.history)
+            .unwrap_or_default();
+        if !history.is_empty() {
+            let average_ms =
+                history.iter().map(|r| r.duration_ms).sum::<u64>() / history.len() as u64;
+            if average_ms > 0 && record.duration_ms > average_ms * SLOWDOWN_FACTOR {
+                warn!(
+                    "{} took {}ms to process, usually takes around {}ms",
+                    event.source.display(),
+                    record.duration_ms,
+                    average_ms
+                );
+            }
+        }
+        history.push(record.clone());
+        if history.len() > HISTORY_LENGTH {
+            history.remove(0);
+        }
+        manifest.entries.insert(
+            event.source.clone(),
+            ManifestEntry {
+                dest: event.dest.clone(),
+                last_duration_ms: record.duration_ms,
+                output_bytes: record.output_bytes,
+                output_hash: get_file_hash(&event.dest),
+                cache_key: get_cache_key(&event.source, &config),
+                dependencies: match event.kind {
+                    ProcessingKind::Mesh => get_mesh_dependencies(&event.source),
+                    _ => vec![],
+                },
+                outputs: get_secondary_outputs(event, &config),
+                history,
+            },
+        );
+    }
+    let Some(timer) = unsaved.as_mut() else {
+        return;
+    };
+    timer.tick(time.delta());
+    if unprocessed.in_flight == 0 || timer.finished() {
+        *unsaved = None;
+        manifest.save(&config);
+        if config.preload.enabled {
+            write_preload_list(&manifest, &config);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_entry(dest: &str, duration_ms: u64) -> ManifestEntry {
+        ManifestEntry {
+            dest: PathBuf::from(dest),
+            last_duration_ms: duration_ms,
+            output_bytes: 64,
+            output_hash: Some(7),
+            cache_key: Some(11),
+            dependencies: vec![],
+            outputs: vec![],
+            history: vec![ManifestRecord {
+                duration_ms,
+                output_bytes: 64,
+            }],
+        }
+    }
+
+    fn get_line(source: &str, entry: ManifestEntry) -> String {
+        serde_json::to_string(&ManifestLine {
+            source: PathBuf::from(source),
+            entry: Some(entry),
+            failed: None,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_lines() {
+        let mut manifest = Manifest::default();
+        let mut mesh = get_entry("out/ship.glb", 120);
+        mesh.dependencies = vec![PathBuf::from("src/ship.png")];
+        mesh.outputs = vec![PathBuf::from("out/ship_lod1.glb")];
+        manifest.entries.insert(PathBuf::from("src/ship.gltf"), mesh);
+        manifest
+            .entries
+            .insert(PathBuf::from("src/logo.png"), get_entry("out/logo.png", 5));
+        manifest
+            .failed
+            .insert(PathBuf::from("src/broken.png"), "Failed to decode".into());
+        // a source with both an entry and a failure keeps both
+        manifest
+            .failed
+            .insert(PathBuf::from("src/logo.png"), "Failed to encode".into());
+
+        let text = manifest.to_lines().unwrap();
+        assert_eq!(text.lines().count(), 4);
+        let loaded = Manifest::from_lines(&text);
+        assert_eq!(loaded.to_lines().unwrap(), text);
+        let mesh = &loaded.entries[&PathBuf::from("src/ship.gltf")];
+        assert_eq!(mesh.dest, PathBuf::from("out/ship.glb"));
+        assert_eq!(mesh.dependencies, vec![PathBuf::from("src/ship.png")]);
+        assert_eq!(mesh.outputs, vec![PathBuf::from("out/ship_lod1.glb")]);
+        assert_eq!(mesh.history.len(), 1);
+        assert_eq!(loaded.failed.len(), 2);
+        assert!(loaded.entries.contains_key(&PathBuf::from("src/logo.png")));
+    }
+
+    #[test]
+    fn keeps_last_line_after_union_merge() {
+        // a union merge keeps both sides' lines for a source processed on both branches
+        let text = [
+            get_line("src/logo.png", get_entry("out/logo.png", 5)),
+            get_line("src/ship.gltf", get_entry("out/ship.glb", 120)),
+            get_line("src/logo.png", get_entry("out/logo.png", 9)),
+        ]
+        .join("\n");
+        let manifest = Manifest::from_lines(&text);
+        assert_eq!(manifest.entries.len(), 2);
+        assert_eq!(
+            manifest.entries[&PathBuf::from("src/logo.png")].last_duration_ms,
+            9
+        );
+        // saving again leaves one line per source
+        assert_eq!(manifest.to_lines().unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn skips_conflict_markers() {
+        let text = [
+            "<<<<<<< HEAD".to_string(),
+            get_line("src/logo.png", get_entry("out/logo.png", 5)),
+            "=======".to_string(),
+            get_line("src/logo.png", get_entry("out/logo.png", 9)),
+            ">>>>>>> feature".to_string(),
+            String::new(),
+            get_line("src/ship.gltf", get_entry("out/ship.glb", 120)),
+        ]
+        .join("\n");
+        let manifest = Manifest::from_lines(&text);
+        assert_eq!(manifest.entries.len(), 2);
+        assert!(manifest.failed.is_empty());
+        assert_eq!(
+            manifest.entries[&PathBuf::from("src/logo.png")].last_duration_ms,
+            9
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use std::{fs::File, io::Read, path::Path};
+
+use crate::processing::get_extension;
+
+/// Leading bytes for media formats whose header can be checked before decoding. Formats not listed here are only
+/// checked for being empty
+const SIGNATURES: &[(&[&str], &[&[u8]])] = &[
+    (&["png"], &[b"\x89PNG\r\n\x1a\n"]),
+    (&["jpg", "jpeg"], &[b"\xff\xd8\xff"]),
+    (&["gif"], &[b"GIF87a", b"GIF89a"]),
+    (&["bmp"], &[b"BM"]),
+    (&["webp", "wav"], &[b"RIFF"]),
+    (&["ktx2"], &[b"\xabKTX 20\xbb"]),
+    (&["ogg"], &[b"OggS"]),
+    (&["flac"], &[b"fLaC"]),
+    (&["glb"], &[b"glTF"]),
+    (&["hdr"], &[b"#?RADIANCE", b"#?RGBE"]),
+    (&["exr"], &[b"\x76\x2f\x31\x01"]),
+    (
+        &["ttf", "otf"],
+        &[b"\x00\x01\x00\x00", b"OTTO", b"true", b"ttcf"],
+    ),
+];
+
+/// Catches empty and obviously broken media files up front, so they fail with a clear message instead of whatever the
+/// decoder makes of them. Usable by every processor that decodes media
+pub fn check_media_file(path: &Path) -> Result<(), String> {
+    let mut header = [0u8; 16];
+    let mut file = File::open(path).map_err(|err| format!("Failed to open file: {}", err))?;
+    let read = file
+        .read(&mut header)
+        .map_err(|err| format!("Failed to read file header: {}", err))?;
+    if read == 0 {
+        return Err("File is empty, it may still be being written".into());
+    }
+    let Some(ext) = get_extension(&path.to_path_buf()) else {
+        return Ok(());
+    };
+    let expected = SIGNATURES
+        .iter()
+        .find(|(extensions, _)| extensions.contains(&ext.as_str()))
+        .map(|(_, signatures)| *signatures);
+    match expected {
+        Some(signatures) if !signatures.iter().any(|sig| header[..read].starts_with(sig)) => {
+            Err(format!(
+                "File does not start with a valid .{} header, it may be truncated or mislabelled",
+                ext
+            ))
+        }
+        _ => Ok(()),
+    }
+}
@@ -1,15 +1,33 @@
-use std::{fs, path::Path};
+use std::{
+    cmp::Reverse,
+    collections::HashSet,
+    fs,
+    io::{Read, Write},
+    path::PathBuf,
+};
 
-use bevy::prelude::*;
-use bevy_gltf_kun::{
-    export::gltf::{GltfExportEvent, GltfExportResult},
-    import::gltf::{scene::GltfScene, GltfKun},
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+use gltf_kun::{
+    extensions::{DefaultExtensions, ExtensionsIO},
+    graph::{gltf::GltfDocument, Graph},
+    io::format::{
+        glb::{GlbExport, GlbImport},
+        gltf::{GltfExport, GltfFormat, GltfImport},
+    },
 };
-use gltf_kun::{extensions::DefaultExtensions, io::format::glb::GlbExport};
 
 use crate::{
-    config::Config,
-    processing::{get_human_duration, FileQueuedForProcessing, ProcessingType},
+    config::{Config, GltfExtensionConfigs, GltfExtensionSet, MeshConfigs, NodeKind},
+    draco,
+    media::check_media_file,
+    mesh_ops,
+    meta::write_mesh_meta,
+    processing::{
+        get_free_workers, write_output, write_output_with,
+        FileProcessed, FileQueuedForProcessing, ProcessingKind, ProcessingTask, ProcessingType,
+    },
+    profiling::{ProcessingStage, StageTimings},
+    scene,
 };
 
 #[derive(Component)]
@@ -36,76 +54,857 @@ impl ProcessingType for ProcessingMesh {
         FileMesh
     }
 
-    fn matches(ext: &String, config: &bevy::prelude::Res<crate::config::Config>) -> bool {
+    fn matches(ext: &String, config: &Config) -> bool {
         config.extensions.mesh.contains(ext)
+            || config.is_mapped_extension(ProcessingKind::Mesh, ext)
+    }
+
+    fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf> {
+        let mut dest_path = config.get_output_path(source)?;
+        dest_path.set_extension(config.get_output_extension(ProcessingKind::Mesh)?);
+        config.apply_output_template(ProcessingKind::Mesh, source, dest_path, |_| None)
+    }
+
+    fn is_enabled(config: &Config) -> bool {
+        config.meshes.enabled
     }
 
-    fn get_destination(source: &std::path::PathBuf) -> Option<std::path::PathBuf> {
-        let base = source.strip_prefix(Path::new("assets-dev")).ok()?;
-        let mut dest_path = Path::new("assets").join(&base);
-        dest_path.set_extension("glb"); // not a fan of hard coding that. Is GLB the most efficient?
-        Some(dest_path)
+    fn get_outputs(source: &PathBuf, config: &Config) -> Vec<PathBuf> {
+        let Some(dest) = Self::get_destination(source, config) else {
+            return vec![];
+        };
+        let dests = get_storage_dests(&dest, &config.meshes);
+        let mut outputs = dests.clone();
+        for level in 1..=config.meshes.lod_levels.len() {
+            outputs.extend(dests.iter().map(|dest| get_lod_path(dest, level)));
+        }
+        if config.meshes.scene {
+            outputs.push(scene::get_scene_path(&dest));
+        }
+        outputs
     }
 
     fn system(
-        _: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
-        _: Res<Config>,
-        _: Commands,
+        query: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
+        config: Res<Config>,
+        mut commands: Commands,
+        _: EventWriter<FileProcessed>,
+        _: ResMut<StageTimings>,
+        tasks: Query<(&FileQueuedForProcessing, &ProcessingTask)>,
     ) {
+        let free_workers = get_free_workers(&tasks, ProcessingKind::Mesh, config.meshes.workers);
+        // the rest wait for a later frame once a worker frees up
+        let mut entries = query.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(_, entry)| Reverse(entry.priority));
+        for (e, entry) in entries.into_iter().take(free_workers) {
+            let file_config = entry.get_config(&config).clone();
+            let source = entry.source.clone();
+            let dest = entry.dest.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let _span = info_span!("process_file", source = %source.display()).entered();
+                let mut timings = StageTimings::default();
+                let result = process_mesh_file(&source, &dest, &file_config, &mut timings);
+                (result, timings)
+            });
+            commands
+                .entity(e)
+                .remove::<FileMesh>()
+                .insert(ProcessingTask::new(ProcessingKind::Mesh, task));
+        }
+    }
+}
+
+/// Processes a mesh along with the verification, `.meta` and scene files the config asks for
+fn process_mesh_file(
+    source: &PathBuf,
+    dest: &PathBuf,
+    config: &Config,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    process_gltf_format(source, dest, &config.meshes, timings)?;
+    if config.verify_outputs {
+        verify_mesh(dest, config.meshes.extension_set)?;
+    }
+    if config.generate_meta {
+        write_mesh_meta(dest)?;
     }
+    if config.meshes.scene {
+        timings.time(ProcessingStage::Export, || scene::write_scene(dest, config))?;
+    }
+    Ok(())
+}
 
-    fn register(app: &mut bevy::prelude::App) {
-        app.add_systems(Update, (load_gltf_scenes).chain());
+/// Imports a gltf/glb source into a gltf_kun graph and writes it back out in the destination's format, along with any
+/// configured LODs
+fn process_gltf_format(
+    source: &PathBuf,
+    dest: &PathBuf,
+    config: &MeshConfigs,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    let decoded = timings.time(ProcessingStage::Import, || {
+        check_media_file(source)?;
+        check_rejected_extensions(source, &config.gltf_extensions)?;
+        decode_draco_source(source, dest)
+    })?;
+    let result = export_gltf_outputs(
+        decoded.as_ref().unwrap_or(source),
+        source,
+        dest,
+        config,
+        timings,
+    );
+    if let Some(decoded) = decoded {
+        let _ = fs::remove_file(decoded);
     }
+    result
 }
 
-fn load_gltf_scenes(
-    query: Query<&FileQueuedForProcessing, With<FileMesh>>,
-    assets: Res<AssetServer>,
-    kun_scenes: Res<Assets<GltfKun>>,
-    scenes: Res<Assets<GltfScene>>,
-    mut export: EventWriter<GltfExportEvent<DefaultExtensions>>,
-    mut results: ResMut<Events<GltfExportResult>>,
-) {
-    for entry in query.iter() {
-        let Ok(canonical_path) = entry.source.canonicalize() else {
-            error!(
-                "Failed to load canonical path for: {}",
-                entry.source.display()
+/// Exports every configured storage and LOD. `import_path` is the source itself, or its draco decoded copy
+fn export_gltf_outputs(
+    import_path: &PathBuf,
+    source: &PathBuf,
+    dest: &PathBuf,
+    config: &MeshConfigs,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    // every configured storage is exported from the same document, so the outputs only differ in layout
+    let dests = get_storage_dests(dest, config);
+    let (mut graph, doc) = import_mesh(import_path, config, timings)?;
+    for dest in &dests {
+        export_mesh(&mut graph, &doc, dest, config, timings)?;
+        timings.time(ProcessingStage::Write, || {
+            check_animation_data(source, dest)
+        })?;
+    }
+
+    for (level, ratio) in config.lod_levels.iter().enumerate() {
+        // each LOD starts from a fresh import so ratios are always relative to the full-res mesh
+        let (mut graph, doc) = import_mesh(import_path, config, timings)?;
+        timings.time(ProcessingStage::Export, || {
+            mesh_ops::simplify_document(&mut graph, &doc, *ratio)
+        })?;
+        for dest in &dests {
+            export_mesh(
+                &mut graph,
+                &doc,
+                &get_lod_path(dest, level + 1),
+                config,
+                timings,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Imports the source and applies the configured document cleanups
+fn import_mesh(
+    source: &PathBuf,
+    config: &MeshConfigs,
+    timings: &mut StageTimings,
+) -> Result<(Graph, GltfDocument), String> {
+    let (mut graph, doc) = timings.time(ProcessingStage::Import, || {
+        import_gltf(source, config.extension_set)
+    })?;
+    if config.weld_vertices {
+        let welded = timings.time(ProcessingStage::Export, || {
+            mesh_ops::weld_vertices(&mut graph, &doc, config.weld_tolerance)
+        });
+        if welded > 0 {
+            debug!(
+                "Welded {} duplicate vertices in {}",
+                welded,
+                source.display()
+            );
+        }
+    }
+    if config.flip_uv_v {
+        let flipped = timings.time(ProcessingStage::Export, || {
+            mesh_ops::flip_uv_v(&mut graph, &doc)
+        });
+        debug!(
+            "Flipped {} texture coordinate sets in {}",
+            flipped,
+            source.display()
+        );
+    }
+    if config.convert_handedness {
+        let converted = timings.time(ProcessingStage::Export, || {
+            mesh_ops::convert_handedness(&mut graph, &doc)
+        });
+        debug!(
+            "Converted {} primitives to right-handed in {}",
+            converted,
+            source.display()
+        );
+    }
+    if let Some(rules) = &config.sanitize_names {
+        let renamed = timings.time(ProcessingStage::Export, || {
+            mesh_ops::sanitize_names(&mut graph, &doc, rules)
+        });
+        if renamed > 0 {
+            debug!("Sanitized {} names in {}", renamed, source.display());
+        }
+    }
+    if config.merge_duplicate_materials {
+        let merged = timings.time(ProcessingStage::Export, || {
+            mesh_ops::merge_duplicate_materials(&mut graph, &doc)
+        });
+        if merged > 0 {
+            debug!(
+                "Merged {} duplicate materials in {}",
+                merged,
+                source.display()
             );
+        }
+    }
+    Ok((graph, doc))
+}
+
+pub fn import_gltf(
+    source: &PathBuf,
+    extensions: GltfExtensionSet,
+) -> Result<(Graph, GltfDocument), String> {
+    match extensions {
+        GltfExtensionSet::Default => import_gltf_with::<DefaultExtensions>(source),
+        GltfExtensionSet::Minimal => import_gltf_with::<()>(source),
+    }
+}
+
+fn import_gltf_with<E: ExtensionsIO<GltfDocument, GltfFormat>>(
+    source: &PathBuf,
+) -> Result<(Graph, GltfDocument), String> {
+    let mut graph = Graph::default();
+    let is_binary = source
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("glb"));
+    let doc = if is_binary {
+        GlbImport::<E>::import_file(&mut graph, source)
+            .map_err(|err| format!("Failed to import glb: {:?}", err))?
+    } else {
+        GltfImport::<E>::import_file(&mut graph, source)
+            .map_err(|err| format!("Failed to import gltf: {:?}", err))?
+    };
+    Ok((graph, doc))
+}
+
+/// Fails on sources using any extension from the reject list, before spending time importing them
+fn check_rejected_extensions(
+    source: &PathBuf,
+    config: &GltfExtensionConfigs,
+) -> Result<(), String> {
+    if config.reject.is_empty() {
+        return Ok(());
+    }
+    let json = read_source_json(source)?;
+    let used = json["extensionsUsed"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    match used
+        .iter()
+        .filter_map(|name| name.as_str())
+        .find(|name| config.reject.iter().any(|rejected| rejected == name))
+    {
+        Some(name) => Err(format!("Uses rejected gltf extension {}", name)),
+        None => Ok(()),
+    }
+}
+
+/// gltf_kun can't decode draco, and imports compressed primitives without their geometry. Sources using it are
+/// decoded to a temp glb next to the output first, which is returned for importing in the source's place
+fn decode_draco_source(source: &PathBuf, dest: &PathBuf) -> Result<Option<PathBuf>, String> {
+    let compressed = draco::count_compressed_primitives(&read_source_json(source)?);
+    if compressed == 0 {
+        return Ok(None);
+    }
+    if !source
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("glb"))
+    {
+        return Err(format!(
+            "{} primitives use {}, which can only be decoded in glb sources. Export the source as glb",
+            compressed,
+            draco::DRACO_EXTENSION
+        ));
+    }
+    let bytes = fs::read(source).map_err(|err| format!("Failed to read mesh: {}", err))?;
+    let decoded = draco::decode_glb(&bytes)?;
+    let path = get_draco_path(dest);
+    write_output(&path, decoded)
+        .map_err(|err| format!("Failed to write decoded draco mesh: {}", err))?;
+    Ok(Some(path))
+}
+
+/// Starts the name of a draco source's decoded copy while its outputs are exported
+pub const DRACO_TEMP_PREFIX: &str = ".bpm-draco-";
+
+/// `foo.glb` => `.bpm-draco-foo.glb`
+fn get_draco_path(dest: &PathBuf) -> PathBuf {
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!("{}{}.glb", DRACO_TEMP_PREFIX, stem))
+}
+
+/// The extension Blender and most DCC tools export lights with
+const LIGHTS_EXTENSION: &str = "KHR_lights_punctual";
+
+/// Animation channels, skins and morph targets in a gltf document
+#[derive(Debug, PartialEq)]
+struct AnimationData {
+    channels: usize,
+    skins: usize,
+    morph_targets: usize,
+}
+
+impl AnimationData {
+    fn from_json(json: &serde_json::Value) -> Self {
+        let count = |value: &serde_json::Value| value.as_array().map_or(0, |items| items.len());
+        let items = |value: &serde_json::Value| value.as_array().cloned().unwrap_or_default();
+        Self {
+            channels: items(&json["animations"])
+                .iter()
+                .map(|animation| count(&animation["channels"]))
+                .sum(),
+            skins: count(&json["skins"]),
+            morph_targets: items(&json["meshes"])
+                .iter()
+                .flat_map(|mesh| items(&mesh["primitives"]))
+                .map(|primitive| count(&primitive["targets"]))
+                .sum(),
+        }
+    }
+}
+
+/// Fails the file if the export lost any animation data from the source, rather than silently shipping a character
+/// that no longer animates
+fn check_animation_data(source: &PathBuf, dest: &PathBuf) -> Result<(), String> {
+    let expected = AnimationData::from_json(&read_source_json(source)?);
+    if expected.channels == 0 && expected.skins == 0 && expected.morph_targets == 0 {
+        return Ok(());
+    }
+    let actual = AnimationData::from_json(&read_source_json(dest)?);
+    if actual != expected {
+        return Err(format!(
+            "Export dropped animation data. Source has {:?}, output has {:?}",
+            expected, actual
+        ));
+    }
+    Ok(())
+}
+
+/// Reads the JSON document of a gltf or glb source without importing it
+fn read_source_json(source: &PathBuf) -> Result<serde_json::Value, String> {
+    let mut file = fs::File::open(source).map_err(|err| format!("Failed to read mesh: {}", err))?;
+    let mut header = [0u8; 20];
+    let is_glb = file.read_exact(&mut header).is_ok() && header.starts_with(b"glTF");
+    if !is_glb {
+        let bytes = fs::read(source).map_err(|err| format!("Failed to read mesh: {}", err))?;
+        return serde_json::from_slice(&bytes)
+            .map_err(|err| format!("Failed to parse gltf: {}", err));
+    }
+    // only the JSON chunk is read, so checking a glb doesn't load its binary chunk
+    let json_len = u32::from_le_bytes([header[12], header[13], header[14], header[15]]) as usize;
+    let mut json = vec![0u8; json_len];
+    file.read_exact(&mut json)
+        .map_err(|_| "glb has a truncated JSON chunk".to_string())?;
+    serde_json::from_slice(&json).map_err(|err| format!("Failed to parse glb JSON chunk: {}", err))
+}
+
+/// The external files (images and buffers) a mesh source references by relative URI. Embedded `data:` URIs are part of
+/// the source itself and aren't listed
+pub fn get_mesh_dependencies(source: &PathBuf) -> Vec<PathBuf> {
+    let Ok(json) = read_source_json(source) else {
+        return vec![];
+    };
+    let dir = source.parent().map(PathBuf::from).unwrap_or_default();
+    ["images", "buffers"]
+        .iter()
+        .filter_map(|key| json[key].as_array())
+        .flatten()
+        .filter_map(|item| item["uri"].as_str())
+        .filter(|uri| !uri.starts_with("data:"))
+        .map(|uri| dir.join(uri.replace("%20", " ")))
+        .collect()
+}
+
+fn export_mesh(
+    graph: &mut Graph,
+    doc: &GltfDocument,
+    dest: &PathBuf,
+    config: &MeshConfigs,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    if dest.extension().is_some_and(|ext| ext == "gltf") {
+        export_gltf(graph, doc, dest, config, timings)
+    } else {
+        export_glb(graph, doc, dest, config, timings)
+    }
+}
+
+fn export_glb(
+    graph: &mut Graph,
+    doc: &GltfDocument,
+    dest: &PathBuf,
+    config: &MeshConfigs,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    let (bytes, json_bytes, json_end) = timings.time(ProcessingStage::Export, || {
+        let bytes = match config.extension_set {
+            GltfExtensionSet::Default => GlbExport::<DefaultExtensions>::export(graph, doc),
+            GltfExtensionSet::Minimal => GlbExport::<()>::export(graph, doc),
+        }
+        .map_err(|err| format!("Failed to export glb: {:?}", err))?
+        .0;
+        let (json, json_end) = read_glb_json(&bytes)?;
+        let json_bytes = normalize_glb_json(json, config)?;
+        Ok::<_, String>((bytes, json_bytes, json_end))
+    })?;
+    // the binary chunk is written straight from the exported glb rather than copied into a normalized one, so
+    // large buffers are only held in memory once
+    timings.time(ProcessingStage::Write, || {
+        write_output_with(dest, |writer| {
+            let remaining_chunks = &bytes[json_end..];
+            let total_len = 20 + json_bytes.len() + remaining_chunks.len();
+            writer.write_all(&bytes[0..8])?; // magic + version
+            writer.write_all(&(total_len as u32).to_le_bytes())?;
+            writer.write_all(&(json_bytes.len() as u32).to_le_bytes())?;
+            writer.write_all(&bytes[16..20])?; // JSON chunk type
+            writer.write_all(&json_bytes)?;
+            writer.write_all(remaining_chunks)
+        })
+        .map_err(|err| format!("Failed to write glb: {}", err))
+    })
+}
+
+/// Writes the gltf JSON to the destination and its buffers and images alongside it, using the uris it references
+fn export_gltf(
+    graph: &mut Graph,
+    doc: &GltfDocument,
+    dest: &PathBuf,
+    config: &MeshConfigs,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    let (json, resources) = timings.time(ProcessingStage::Export, || {
+        let format = match config.extension_set {
+            GltfExtensionSet::Default => GltfExport::<DefaultExtensions>::export(graph, doc),
+            GltfExtensionSet::Minimal => GltfExport::<()>::export(graph, doc),
+        }
+        .map_err(|err| format!("Failed to export gltf: {:?}", err))?;
+        let json = serde_json::to_value(&format.json)
+            .map_err(|err| format!("Failed to write gltf JSON: {}", err))?;
+        let json = serde_json::to_vec_pretty(&clean_json(json, config))
+            .map_err(|err| format!("Failed to write gltf JSON: {}", err))?;
+        Ok::<_, String>((json, format.resources))
+    })?;
+    timings.time(ProcessingStage::Write, || {
+        let dir = dest.parent().map(PathBuf::from).unwrap_or_default();
+        for (uri, bytes) in resources {
+            write_output(&dir.join(&uri), bytes)
+                .map_err(|err| format!("Failed to write gltf resource {}: {}", uri, err))?;
+        }
+        write_output(dest, json).map_err(|err| format!("Failed to write gltf: {}", err))
+    })
+}
+
+/// The destination plus a copy for each extra configured storage, e.g. `foo.gltf` next to `foo.glb`
+fn get_storage_dests(dest: &PathBuf, config: &MeshConfigs) -> Vec<PathBuf> {
+    let primary = dest
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default();
+    std::iter::once(dest.clone())
+        .chain(
+            config
+                .get_extra_extensions(primary)
+                .into_iter()
+                .map(|ext| dest.with_extension(ext)),
+        )
+        .collect()
+}
+
+/// `foo.glb` => `foo_lod1.glb` for LOD level 1
+fn get_lod_path(dest: &PathBuf, level: usize) -> PathBuf {
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = dest.extension().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!("{}_lod{}.{}", stem, level, ext))
+}
+
+/// Rewrites the JSON chunk of an exported glb with stripped extensions, nodes and extras removed and its object keys sorted so identical
+/// sources always produce byte-identical outputs. gltf_kun keeps properties (nodes, accessors, buffer views) in graph
+/// insertion order, which already follows the source, but maps like `extensions` and `extras` don't have a guaranteed
+/// order. The result is padded so the chunks after it stay aligned
+fn normalize_glb_json(json: serde_json::Value, config: &MeshConfigs) -> Result<Vec<u8>, String> {
+    let mut json_bytes = serde_json::to_vec(&clean_json(json, config))
+        .map_err(|err| format!("Failed to write glb JSON chunk: {}", err))?;
+    while json_bytes.len() % 4 != 0 {
+        json_bytes.push(b' '); // chunks must stay 4 byte aligned. The spec pads JSON with spaces
+    }
+    Ok(json_bytes)
+}
+
+/// Parses the JSON chunk of a glb, returning it with the offset where the following chunks start
+pub fn read_glb_json(bytes: &[u8]) -> Result<(serde_json::Value, usize), String> {
+    // glb layout: 12 byte header, then chunks of [length: u32, type: u32, data]. JSON is always the first chunk
+    if bytes.len() < 20 {
+        return Err("glb is too short to contain a JSON chunk".into());
+    }
+    let json_len = u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]) as usize;
+    let json_end = 20 + json_len;
+    if bytes.len() < json_end {
+        return Err("glb has a truncated JSON chunk".into());
+    }
+    let json = serde_json::from_slice(&bytes[20..json_end])
+        .map_err(|err| format!("Failed to parse glb JSON chunk: {}", err))?;
+    Ok((json, json_end))
+}
+
+fn clean_json(mut json: serde_json::Value, config: &MeshConfigs) -> serde_json::Value {
+    strip_extensions(&mut json, &config.gltf_extensions);
+    if !config.strip_nodes.is_empty() {
+        strip_nodes(&mut json, &config.strip_nodes);
+    }
+    if !config.keep_extras {
+        strip_extras(&mut json);
+    }
+    sort_json_keys(json)
+}
+
+/// Detaches cameras and lights of the given kinds from their nodes, then removes nodes left with nothing in them.
+/// Nodes that still have a mesh, children, a skin, or are animated or used as joints only lose the attachment
+fn strip_nodes(json: &mut serde_json::Value, kinds: &[NodeKind]) {
+    let strip_cameras = kinds.contains(&NodeKind::Camera);
+    let strip_lights = kinds.contains(&NodeKind::Light);
+    let mut referenced = HashSet::new();
+    for animation in json["animations"].as_array().into_iter().flatten() {
+        for channel in animation["channels"].as_array().into_iter().flatten() {
+            referenced.extend(channel["target"]["node"].as_u64());
+        }
+    }
+    for skin in json["skins"].as_array().into_iter().flatten() {
+        referenced.extend(
+            skin["joints"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|j| j.as_u64()),
+        );
+        referenced.extend(skin["skeleton"].as_u64());
+    }
+
+    let Some(nodes) = json.get_mut("nodes").and_then(|nodes| nodes.as_array_mut()) else {
+        return;
+    };
+    let mut remap = Vec::with_capacity(nodes.len());
+    let mut kept = 0;
+    for (index, node) in nodes.iter_mut().enumerate() {
+        let Some(node) = node.as_object_mut() else {
+            remap.push(Some(kept));
+            kept += 1;
             continue;
         };
-        debug!("Asset server path to load: {}", canonical_path.display());
-        let scene_handle = assets.load::<GltfKun>(canonical_path);
-        let gltf = match kun_scenes.get(&scene_handle) {
-            Some(a) => a,
-            None => {
-                error!("Failed to load GltfKun from handle!");
-                continue;
+        let mut stripped = false;
+        if strip_cameras {
+            stripped |= node.remove("camera").is_some();
+        }
+        if strip_lights {
+            if let Some(serde_json::Value::Object(extensions)) = node.get_mut("extensions") {
+                stripped |= extensions.remove(LIGHTS_EXTENSION).is_some();
+                if extensions.is_empty() {
+                    node.remove("extensions");
+                }
             }
+        }
+        let is_empty = ["mesh", "children", "skin", "camera", "extensions"]
+            .iter()
+            .all(|key| !node.contains_key(*key));
+        if stripped && is_empty && !referenced.contains(&(index as u64)) {
+            remap.push(None);
+        } else {
+            remap.push(Some(kept));
+            kept += 1;
+        }
+    }
+    let mut index = 0;
+    nodes.retain(|_| {
+        index += 1;
+        remap[index - 1].is_some()
+    });
+
+    let remap_list = |items: &mut Vec<serde_json::Value>| {
+        *items = items
+            .iter()
+            .filter_map(|item| remap[item.as_u64()? as usize])
+            .map(serde_json::Value::from)
+            .collect();
+    };
+    // gltf doesn't allow empty lists, so one left empty is removed
+    let remap_key = |object: &mut serde_json::Value, key: &str| {
+        let Some(object) = object.as_object_mut() else {
+            return;
+        };
+        if let Some(serde_json::Value::Array(items)) = object.get_mut(key) {
+            remap_list(items);
+            if items.is_empty() {
+                object.remove(key);
+            }
+        }
+    };
+    for node in get_items_mut(json, "nodes") {
+        remap_key(node, "children");
+    }
+    for scene in get_items_mut(json, "scenes") {
+        remap_key(scene, "nodes");
+    }
+    let remap_index = |value: Option<&mut serde_json::Value>| {
+        let Some(value) = value else {
+            return;
         };
-        for scene in gltf.scenes.iter() {
-            if let Some(scene_asset) = scenes.get(scene) {
-                export.send(GltfExportEvent::new(scene_asset.scene.clone()));
-
-                for mut event in results.drain() {
-                    let Ok(doc) = event.result else {
-                        continue;
-                    };
-                    let Ok(bytes) = GlbExport::<DefaultExtensions>::export(&mut event.graph, &doc)
-                    else {
-                        continue;
-                    };
-                    let _ = fs::write(entry.dest.clone(), bytes.0);
-                    let time = entry.queue_time.elapsed();
-                    info!(
-                        "{} => {} -- {}",
-                        entry.source.display(),
-                        entry.dest.display(),
-                        get_human_duration(time)
-                    );
+        if let Some(Some(new)) = value.as_u64().map(|old| remap[old as usize]) {
+            *value = new.into();
+        }
+    };
+    for skin in get_items_mut(json, "skins") {
+        remap_key(skin, "joints");
+        remap_index(skin.get_mut("skeleton"));
+    }
+    for animation in get_items_mut(json, "animations") {
+        for channel in get_items_mut(animation, "channels") {
+            remap_index(
+                channel
+                    .get_mut("target")
+                    .and_then(|target| target.get_mut("node")),
+            );
+        }
+    }
+
+    let Some(root) = json.as_object_mut() else {
+        return;
+    };
+    if strip_cameras {
+        root.remove("cameras");
+    }
+    if strip_lights {
+        if let Some(serde_json::Value::Object(extensions)) = root.get_mut("extensions") {
+            extensions.remove(LIGHTS_EXTENSION);
+            if extensions.is_empty() {
+                root.remove("extensions");
+            }
+        }
+        for key in ["extensionsUsed", "extensionsRequired"] {
+            if let Some(serde_json::Value::Array(names)) = root.get_mut(key) {
+                names.retain(|name| name != LIGHTS_EXTENSION);
+                if names.is_empty() {
+                    root.remove(key);
                 }
             }
         }
     }
 }
+
+/// The items of a json list, if there is one. Unlike indexing with `[]`, doesn't add the key as null when it's missing
+fn get_items_mut<'a>(
+    json: &'a mut serde_json::Value,
+    key: &str,
+) -> impl Iterator<Item = &'a mut serde_json::Value> {
+    json.get_mut(key)
+        .and_then(|items| items.as_array_mut())
+        .into_iter()
+        .flatten()
+}
+
+/// Removes stripped extensions from the used/required lists and from every `extensions` object in the document
+fn strip_extensions(value: &mut serde_json::Value, config: &GltfExtensionConfigs) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for key in ["extensionsUsed", "extensionsRequired"] {
+                if let Some(serde_json::Value::Array(names)) = map.get_mut(key) {
+                    names.retain(|name| {
+                        name.as_str().map_or(true, |name| !config.is_stripped(name))
+                    });
+                }
+            }
+            if let Some(serde_json::Value::Object(extensions)) = map.get_mut("extensions") {
+                extensions.retain(|name, _| !config.is_stripped(name));
+            }
+            map.values_mut()
+                .for_each(|value| strip_extensions(value, config));
+        }
+        serde_json::Value::Array(values) => {
+            values
+                .iter_mut()
+                .for_each(|value| strip_extensions(value, config));
+        }
+        _ => {}
+    }
+}
+
+/// Removes the `extras` custom properties from every object in the document
+fn strip_extras(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            map.remove("extras");
+            map.values_mut().for_each(strip_extras);
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(strip_extras),
+        _ => {}
+    }
+}
+
+fn sort_json_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_json_keys(value)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(sort_json_keys).collect())
+        }
+        other => other,
+    }
+}
+
+/// Re-imports the written mesh to make sure it isn't corrupted. Removes the output if it is so it gets reprocessed
+fn verify_mesh(dest: &PathBuf, extensions: GltfExtensionSet) -> Result<(), String> {
+    let Err(err) = import_gltf(dest, extensions) else {
+        return Ok(());
+    };
+    let _ = fs::remove_file(dest);
+    Err(format!("Output failed verification: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_test_source(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("assets-dev/meshes")
+            .join(name)
+    }
+
+    /// A fresh output directory per test, so tests running in parallel don't share outputs
+    fn get_test_dest(test: &str, name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bpm-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(name)
+    }
+
+    fn get_accessor_count(json: &serde_json::Value, accessor: &serde_json::Value) -> Option<u64> {
+        json["accessors"][accessor.as_u64()? as usize]["count"].as_u64()
+    }
+
+    #[test]
+    fn decodes_draco_primitives() {
+        let source = get_test_source("DracoQuad.glb");
+        let dest = get_test_dest("draco", "DracoQuad.glb");
+        let config = Config::default().meshes;
+        process_gltf_format(&source, &dest, &config, &mut StageTimings::default()).unwrap();
+
+        let json = read_source_json(&dest).unwrap();
+        assert_eq!(draco::count_compressed_primitives(&json), 0);
+        assert!(json.get("extensionsRequired").is_none());
+        let primitive = &json["meshes"][0]["primitives"][0];
+        assert_eq!(
+            get_accessor_count(&json, &primitive["attributes"]["POSITION"]),
+            Some(4)
+        );
+        assert_eq!(
+            get_accessor_count(&json, &primitive["attributes"]["NORMAL"]),
+            Some(4)
+        );
+        assert_eq!(get_accessor_count(&json, &primitive["indices"]), Some(6));
+        assert!(!get_draco_path(&dest).exists());
+    }
+
+    #[test]
+    fn keeps_animations_and_skins() {
+        let source = get_test_source("Pug.glb");
+        let dest = get_test_dest("animation", "Pug.glb");
+        let config = Config::default().meshes;
+        process_gltf_format(&source, &dest, &config, &mut StageTimings::default()).unwrap();
+
+        let expected = read_source_json(&source).unwrap();
+        let json = read_source_json(&dest).unwrap();
+        let count = |json: &serde_json::Value, key: &str| json[key].as_array().map_or(0, Vec::len);
+        assert!(count(&expected, "animations") > 0 && count(&expected, "skins") > 0);
+        assert_eq!(count(&json, "animations"), count(&expected, "animations"));
+        assert_eq!(count(&json, "skins"), count(&expected, "skins"));
+        assert_eq!(
+            AnimationData::from_json(&json),
+            AnimationData::from_json(&expected)
+        );
+    }
+
+    #[test]
+    fn strips_nodes_and_remaps_indices() {
+        let light = serde_json::json!({ LIGHTS_EXTENSION: { "light": 0 } });
+        let mut json = serde_json::json!({
+            "scenes": [{ "nodes": [0, 5] }],
+            "nodes": [
+                { "name": "root", "children": [1, 2, 3] },
+                { "name": "camera", "camera": 0 },
+                { "name": "body", "mesh": 0, "children": [4] },
+                { "name": "hand", "camera": 0 },
+                { "name": "lamp", "extensions": light },
+                { "name": "spine", "children": [6] },
+                { "name": "swinging camera", "camera": 0 },
+            ],
+            "skins": [{ "joints": [3, 5], "skeleton": 5 }],
+            "animations": [{
+                "channels": [{ "sampler": 0, "target": { "node": 6, "path": "rotation" } }],
+                "samplers": [{ "input": 0, "output": 1 }],
+            }],
+            "cameras": [{ "type": "perspective" }],
+            "extensions": { LIGHTS_EXTENSION: { "lights": [{ "type": "point" }] } },
+            "extensionsUsed": [LIGHTS_EXTENSION],
+        });
+        strip_nodes(&mut json, &[NodeKind::Camera, NodeKind::Light]);
+
+        // only the camera and light nodes nothing else points at are removed
+        let names = json["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| node["name"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["root", "body", "hand", "spine", "swinging camera"]);
+        assert_eq!(json["nodes"][0]["children"], serde_json::json!([1, 2]));
+        assert_eq!(json["nodes"][3]["children"], serde_json::json!([4]));
+        assert_eq!(json["scenes"][0]["nodes"], serde_json::json!([0, 3]));
+        assert_eq!(json["skins"][0]["joints"], serde_json::json!([2, 3]));
+        assert_eq!(json["skins"][0]["skeleton"], 3);
+        assert_eq!(json["animations"][0]["channels"][0]["target"]["node"], 4);
+
+        // lists left empty are removed rather than written as empty or null, which gltf doesn't allow
+        let nodes = json["nodes"].as_array().unwrap();
+        assert!(!nodes[1].as_object().unwrap().contains_key("children"));
+        assert!(nodes.iter().all(|node| node.get("camera").is_none()));
+        let root = json.as_object().unwrap();
+        for key in ["cameras", "extensions", "extensionsUsed"] {
+            assert!(!root.contains_key(key), "{}", key);
+        }
+        assert!(!json.to_string().contains("null"));
+    }
+
+    #[test]
+    fn strips_nodes_without_adding_missing_lists() {
+        let mut json = serde_json::json!({
+            "nodes": [{ "mesh": 0 }, { "camera": 0 }],
+            "scenes": [{ "nodes": [0, 1] }],
+        });
+        strip_nodes(&mut json, &[NodeKind::Camera]);
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "nodes": [{ "mesh": 0 }],
+                "scenes": [{ "nodes": [0] }],
+            })
+        );
+    }
+}
@@ -0,0 +1,610 @@
+use std::collections::{HashMap, HashSet};
+
+use gltf_kun::graph::{
+    gltf::{
+        accessor::{ComponentType, ElementType},
+        material::Material,
+        primitive::{Mode, Primitive, Semantic},
+        GltfDocument,
+    },
+    Edge, Graph, GraphNodeWeights,
+};
+use meshopt::{SimplifyOptions, VertexDataAdapter};
+
+use crate::config::NameSanitizeConfigs;
+
+/// How far simplification may move the surface, relative to the mesh extents
+const SIMPLIFY_TARGET_ERROR: f32 = 0.01;
+
+/// Decimates every indexed primitive in the document down to roughly `ratio` of its original triangles. Index
+/// accessors shared between primitives are only simplified once
+pub fn simplify_document(graph: &mut Graph, doc: &GltfDocument, ratio: f32) -> Result<(), String> {
+    let mut simplified_indices = HashSet::new();
+    for mesh in doc.meshes(graph) {
+        for primitive in mesh.primitives(graph) {
+            let Some(positions) = primitive.attribute(graph, Semantic::Positions) else {
+                continue;
+            };
+            let Some(indices) = primitive.indices(graph) else {
+                // unindexed geometry has nothing for meshopt to collapse
+                continue;
+            };
+            if !simplified_indices.insert(indices.0) {
+                continue;
+            }
+            let position_data = positions.get(graph).data.clone();
+            let index_weight = indices.get(graph);
+            let index_values = read_indices(&index_weight.data, index_weight.component_type)?;
+
+            let target_count = (index_values.len() as f32 * ratio) as usize / 3 * 3;
+            // positions are always tightly packed vec3 f32s in gltf_kun's accessor data
+            let vertices = VertexDataAdapter::new(&position_data, 12, 0)
+                .map_err(|err| format!("Failed to read vertex positions: {:?}", err))?;
+            let simplified = meshopt::simplify(
+                &index_values,
+                &vertices,
+                target_count,
+                SIMPLIFY_TARGET_ERROR,
+                SimplifyOptions::None,
+                None,
+            );
+
+            let index_weight = indices.get_mut(graph);
+            index_weight.data = simplified
+                .iter()
+                .flat_map(|index| index.to_le_bytes())
+                .collect();
+            index_weight.component_type = ComponentType::U32;
+        }
+    }
+    Ok(())
+}
+
+/// Builds an index buffer for unindexed primitives, merging vertices whose attributes all match. Positions that round
+/// to the same multiple of `tolerance` on every axis count as matching; a tolerance of zero only merges exact copies.
+/// Primitives with the same attribute accessors share one index buffer, and accessors also used by a primitive with
+/// different attributes are left alone. Returns how many vertices were removed
+pub fn weld_vertices(graph: &mut Graph, doc: &GltfDocument, tolerance: f32) -> usize {
+    // the attribute accessors in a stable order, for telling which primitives read exactly the same data
+    let get_attribute_ids = |graph: &Graph, primitive: &Primitive| {
+        let mut ids = primitive
+            .attributes(graph)
+            .into_iter()
+            .map(|(_, accessor)| accessor.0)
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    };
+    // welding reorders an accessor's vertices, which is only safe when every primitive reading it reads the same set
+    let mut owners = HashMap::new();
+    let mut shared_unsafely = HashSet::new();
+    for mesh in doc.meshes(graph) {
+        for primitive in mesh.primitives(graph) {
+            let owner = (
+                get_attribute_ids(graph, &primitive),
+                primitive.indices(graph).is_some(),
+            );
+            for id in owner.0.clone() {
+                let first = owners.entry(id).or_insert_with(|| owner.clone());
+                if *first != owner {
+                    shared_unsafely.extend(first.0.iter().chain(&owner.0).copied());
+                }
+            }
+        }
+    }
+
+    let mut welded = HashMap::new();
+    let mut removed = 0;
+    for mesh in doc.meshes(graph) {
+        for primitive in mesh.primitives(graph) {
+            if primitive.indices(graph).is_some() {
+                continue;
+            }
+            let Some(positions) = primitive.attribute(graph, Semantic::Positions) else {
+                continue;
+            };
+            let ids = get_attribute_ids(graph, &primitive);
+            if ids.iter().any(|id| shared_unsafely.contains(id)) {
+                continue;
+            }
+            if let Some(index_accessor) = welded.get(&ids) {
+                primitive.set_indices(graph, Some(*index_accessor));
+                continue;
+            }
+            let vertex_count = positions.get(graph).data.len() / 12;
+            if vertex_count == 0 {
+                continue;
+            }
+            let attributes = primitive
+                .attributes(graph)
+                .into_iter()
+                .map(|(_, accessor)| {
+                    let data = accessor.get(graph).data.clone();
+                    let stride = data.len() / vertex_count;
+                    (accessor, data, stride)
+                })
+                .collect::<Vec<_>>();
+
+            let mut unique = HashMap::new();
+            let mut kept_vertices = Vec::new();
+            let mut indices = Vec::with_capacity(vertex_count);
+            for vertex in 0..vertex_count {
+                let mut key = Vec::new();
+                for (accessor, data, stride) in &attributes {
+                    let bytes = &data[vertex * stride..(vertex + 1) * stride];
+                    if accessor.0 == positions.0 && tolerance > 0.0 {
+                        for axis in bytes.chunks_exact(4) {
+                            let value = f32::from_le_bytes([axis[0], axis[1], axis[2], axis[3]]);
+                            key.extend(((value / tolerance).round() as i64).to_le_bytes());
+                        }
+                    } else {
+                        key.extend_from_slice(bytes);
+                    }
+                }
+                let next = kept_vertices.len() as u32;
+                let index = *unique.entry(key).or_insert_with(|| {
+                    kept_vertices.push(vertex);
+                    next
+                });
+                indices.push(index);
+            }
+
+            for (accessor, data, stride) in &attributes {
+                accessor.get_mut(graph).data = kept_vertices
+                    .iter()
+                    .flat_map(|vertex| &data[vertex * stride..(vertex + 1) * stride])
+                    .copied()
+                    .collect();
+            }
+            let index_accessor = doc.create_accessor(graph);
+            let index_weight = index_accessor.get_mut(graph);
+            index_weight.data = indices
+                .iter()
+                .flat_map(|index| index.to_le_bytes())
+                .collect();
+            index_weight.component_type = ComponentType::U32;
+            index_weight.element_type = ElementType::Scalar;
+            if let Some(buffer) = positions.buffer(graph) {
+                index_accessor.set_buffer(graph, Some(buffer));
+            }
+            primitive.set_indices(graph, Some(index_accessor));
+            welded.insert(ids, index_accessor);
+            removed += vertex_count - kept_vertices.len();
+        }
+    }
+    removed
+}
+
+/// Mirrors every texture coordinate set vertically. Accessors shared between primitives are only flipped once.
+/// Returns how many accessors were flipped
+pub fn flip_uv_v(graph: &mut Graph, doc: &GltfDocument) -> usize {
+    let mut flipped = HashSet::new();
+    for mesh in doc.meshes(graph) {
+        for primitive in mesh.primitives(graph) {
+            for (semantic, accessor) in primitive.attributes(graph) {
+                if !matches!(semantic, Semantic::TexCoords(_)) || !flipped.insert(accessor.0) {
+                    continue;
+                }
+                let weight = accessor.get_mut(graph);
+                let component_type = weight.component_type;
+                let size = component_type.size();
+                // texture coordinates are vec2s, so every second component is a V value
+                for v in weight.data.chunks_exact_mut(size).skip(1).step_by(2) {
+                    match component_type {
+                        ComponentType::F32 => {
+                            let value = f32::from_le_bytes([v[0], v[1], v[2], v[3]]);
+                            v.copy_from_slice(&(1.0 - value).to_le_bytes());
+                        }
+                        ComponentType::U16 => {
+                            let value = u16::from_le_bytes([v[0], v[1]]);
+                            v.copy_from_slice(&(u16::MAX - value).to_le_bytes());
+                        }
+                        ComponentType::U8 => v[0] = u8::MAX - v[0],
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    flipped.len()
+}
+
+/// Converts left-handed geometry to right-handed by negating Z on positions, normals and tangents, then reversing the
+/// winding of every triangle so faces keep pointing outwards. Returns how many primitives were converted
+pub fn convert_handedness(graph: &mut Graph, doc: &GltfDocument) -> usize {
+    let mut mirrored = HashSet::new();
+    let mut reversed = HashSet::new();
+    let mut converted = 0;
+    for mesh in doc.meshes(graph) {
+        for primitive in mesh.primitives(graph) {
+            if primitive.get(graph).mode != Mode::Triangles {
+                continue;
+            }
+            let attributes = primitive.attributes(graph);
+            for (semantic, accessor) in &attributes {
+                let stride = match semantic {
+                    Semantic::Positions | Semantic::Normals => 3,
+                    Semantic::Tangents => 4,
+                    _ => continue,
+                };
+                if !mirrored.insert(accessor.0) {
+                    continue;
+                }
+                let weight = accessor.get_mut(graph);
+                if weight.component_type != ComponentType::F32 {
+                    continue;
+                }
+                for element in weight.data.chunks_exact_mut(stride * 4) {
+                    negate_f32(&mut element[8..12]);
+                    if stride == 4 {
+                        // the tangent's bitangent sign flips along with the basis
+                        negate_f32(&mut element[12..16]);
+                    }
+                }
+            }
+            if let Some(indices) = primitive.indices(graph) {
+                if mirrored.insert(indices.0) {
+                    let weight = indices.get_mut(graph);
+                    let size = weight.component_type.size();
+                    for triangle in weight.data.chunks_exact_mut(size * 3) {
+                        let (second, third) = triangle[size..].split_at_mut(size);
+                        second.swap_with_slice(third);
+                    }
+                }
+            } else {
+                // unindexed triangles are reversed by swapping their last two vertices in every attribute
+                for (_, accessor) in &attributes {
+                    if !reversed.insert(accessor.0) {
+                        continue;
+                    }
+                    let weight = accessor.get_mut(graph);
+                    let stride = weight.element_type.size() * weight.component_type.size();
+                    for triangle in weight.data.chunks_exact_mut(stride * 3) {
+                        let (second, third) = triangle[stride..].split_at_mut(stride);
+                        second.swap_with_slice(third);
+                    }
+                }
+            }
+            converted += 1;
+        }
+    }
+    converted
+}
+
+fn negate_f32(bytes: &mut [u8]) {
+    let value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    bytes.copy_from_slice(&(-value).to_le_bytes());
+}
+
+/// Rewrites node, mesh and material names so they only contain ASCII letters, digits, `_` and `-`, e.g.
+/// `Wall.001 (copy)` becomes `Wall_001_copy`. Names that end up identical get a numbered suffix.
+/// Returns how many names changed
+pub fn sanitize_names(
+    graph: &mut Graph,
+    doc: &GltfDocument,
+    config: &NameSanitizeConfigs,
+) -> usize {
+    let mut changed = 0;
+    let mut seen = HashSet::new();
+    for node in doc.nodes(graph) {
+        changed += rename(&mut node.get_mut(graph).name, &mut seen, config);
+    }
+    seen.clear();
+    for mesh in doc.meshes(graph) {
+        changed += rename(&mut mesh.get_mut(graph).name, &mut seen, config);
+    }
+    seen.clear();
+    for material in doc.materials(graph) {
+        changed += rename(&mut material.get_mut(graph).name, &mut seen, config);
+    }
+    changed
+}
+
+/// Sanitizes a single name, making it unique among `seen`. Returns 1 if the name changed
+fn rename(
+    name: &mut Option<String>,
+    seen: &mut HashSet<String>,
+    config: &NameSanitizeConfigs,
+) -> usize {
+    let Some(original) = name.as_ref() else {
+        return 0;
+    };
+    let base = sanitize_name(original, config);
+    let mut sanitized = base.clone();
+    let mut suffix = 2;
+    while !seen.insert(sanitized.clone()) {
+        sanitized = format!("{}{}{}", base, config.replacement, suffix);
+        suffix += 1;
+    }
+    if &sanitized == original {
+        return 0;
+    }
+    *name = Some(sanitized);
+    1
+}
+
+fn sanitize_name(name: &str, config: &NameSanitizeConfigs) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    let mut pending_replacement = false;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+            if pending_replacement && !sanitized.is_empty() {
+                sanitized.push_str(&config.replacement);
+            }
+            pending_replacement = false;
+            sanitized.push(c);
+        } else {
+            pending_replacement = true;
+        }
+    }
+    if config.lowercase {
+        sanitized = sanitized.to_ascii_lowercase();
+    }
+    sanitized
+}
+
+fn read_indices(data: &[u8], component_type: ComponentType) -> Result<Vec<u32>, String> {
+    match component_type {
+        ComponentType::U8 => Ok(data.iter().map(|index| *index as u32).collect()),
+        ComponentType::U16 => Ok(data
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+            .collect()),
+        ComponentType::U32 => Ok(data
+            .chunks_exact(4)
+            .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect()),
+        other => Err(format!("Unsupported index component type: {:?}", other)),
+    }
+}
+
+/// Points every primitive using a duplicate material at the first identical one, then removes the duplicates.
+/// Materials are identical when everything but their name matches, including which textures they reference.
+/// Materials with extensions are left alone. Returns how many materials were removed
+pub fn merge_duplicate_materials(graph: &mut Graph, doc: &GltfDocument) -> usize {
+    let mut unique: Vec<(String, Material)> = Vec::new();
+    let mut replacements = HashMap::new();
+    for material in doc.materials(graph) {
+        if has_extensions(graph, &material) {
+            // extension data lives in separate nodes the key can't see, so two materials that look the same may not be
+            continue;
+        }
+        let key = get_material_key(graph, &material);
+        match unique.iter().find(|(unique_key, _)| *unique_key == key) {
+            Some((_, original)) => {
+                replacements.insert(material.0, *original);
+            }
+            None => unique.push((key, material)),
+        }
+    }
+    if replacements.is_empty() {
+        return 0;
+    }
+
+    for mesh in doc.meshes(graph) {
+        for primitive in mesh.primitives(graph) {
+            let Some(material) = primitive.material(graph) else {
+                continue;
+            };
+            if let Some(original) = replacements.get(&material.0) {
+                primitive.set_material(graph, Some(*original));
+            }
+        }
+    }
+    for duplicate in replacements.keys() {
+        graph.remove_node(*duplicate);
+    }
+    replacements.len()
+}
+
+/// The weight's dump covers the factors and `extras`, the texture ids cover the texture slots
+fn get_material_key(graph: &Graph, material: &Material) -> String {
+    let mut weight = material.get(graph).clone();
+    weight.name = None;
+    let textures = [
+        material.base_color_texture(graph),
+        material.metallic_roughness_texture(graph),
+        material.normal_texture(graph),
+        material.occlusion_texture(graph),
+        material.emissive_texture(graph),
+    ]
+    .map(|texture| texture.map(|texture| texture.0));
+    format!("{:?}{:?}", weight, textures)
+}
+
+fn has_extensions(graph: &Graph, material: &Material) -> bool {
+    graph
+        .edges(material.0)
+        .any(|edge| matches!(edge.weight(), Edge::Extension(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{config::GltfExtensionSet, mesh::import_gltf};
+
+    /// Two triangles making a quad, without an index buffer so the shared corners are stored twice
+    const QUAD: [[f32; 3]; 6] = [
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [1.0, 1.0, 0.0],
+    ];
+
+    fn get_positions(positions: &[[f32; 3]]) -> (Vec<u8>, serde_json::Value) {
+        let bytes = positions
+            .iter()
+            .flatten()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let bound = |pick: fn(f32, f32) -> f32| {
+            (0..3)
+                .map(|axis| positions.iter().map(|p| p[axis]).reduce(pick).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let accessor = json!({
+            "componentType": 5126,
+            "type": "VEC3",
+            "count": positions.len(),
+            "min": bound(f32::min),
+            "max": bound(f32::max),
+        });
+        (bytes, accessor)
+    }
+
+    fn get_normals(count: usize) -> (Vec<u8>, serde_json::Value) {
+        let bytes = [0.0f32, 0.0, 1.0]
+            .repeat(count)
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        (bytes, json!({ "componentType": 5126, "type": "VEC3", "count": count }))
+    }
+
+    /// Writes a `.gltf` with one mesh made of `primitives`, giving each accessor its own buffer view over a `.bin` next
+    /// to it, then imports it the way mesh processing does
+    fn import_test_mesh(
+        test: &str,
+        primitives: serde_json::Value,
+        accessors: Vec<(Vec<u8>, serde_json::Value)>,
+    ) -> (Graph, GltfDocument) {
+        let dir = env::temp_dir().join(format!("bpm-mesh-ops-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut buffer = Vec::new();
+        let mut views = Vec::new();
+        let mut accessor_json = Vec::new();
+        for (index, (bytes, mut accessor)) in accessors.into_iter().enumerate() {
+            views.push(json!({ "buffer": 0, "byteOffset": buffer.len(), "byteLength": bytes.len() }));
+            buffer.extend(bytes);
+            // every view starts 4 byte aligned
+            buffer.resize(buffer.len().next_multiple_of(4), 0);
+            accessor["bufferView"] = index.into();
+            accessor_json.push(accessor);
+        }
+        let gltf = json!({
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "mesh.bin", "byteLength": buffer.len() }],
+            "bufferViews": views,
+            "accessors": accessor_json,
+            "meshes": [{ "primitives": primitives }],
+            "nodes": [{ "mesh": 0 }],
+            "scenes": [{ "nodes": [0] }],
+            "scene": 0,
+        });
+        fs::write(dir.join("mesh.bin"), buffer).unwrap();
+        let path = dir.join("mesh.gltf");
+        fs::write(&path, gltf.to_string()).unwrap();
+        import_gltf(&path, GltfExtensionSet::Minimal).unwrap()
+    }
+
+    fn get_primitives(graph: &Graph, doc: &GltfDocument) -> Vec<Primitive> {
+        doc.meshes(graph)
+            .into_iter()
+            .flat_map(|mesh| mesh.primitives(graph))
+            .collect()
+    }
+
+    #[test]
+    fn skips_welding_accessors_shared_with_different_attributes() {
+        // both primitives read the same positions, but only one of them has normals
+        let (mut graph, doc) = import_test_mesh(
+            "weld-shared",
+            json!([
+                { "attributes": { "POSITION": 0 } },
+                { "attributes": { "POSITION": 0, "NORMAL": 1 } },
+            ]),
+            vec![get_positions(&QUAD), get_normals(QUAD.len())],
+        );
+        assert_eq!(weld_vertices(&mut graph, &doc, 0.0), 0);
+        for primitive in get_primitives(&graph, &doc) {
+            assert!(primitive.indices(&graph).is_none());
+            let positions = primitive.attribute(&graph, Semantic::Positions).unwrap();
+            assert_eq!(positions.get(&graph).data.len(), QUAD.len() * 12);
+        }
+    }
+
+    #[test]
+    fn welds_primitives_with_the_same_attributes_once() {
+        let (mut graph, doc) = import_test_mesh(
+            "weld-same",
+            json!([
+                { "attributes": { "POSITION": 0, "NORMAL": 1 } },
+                { "attributes": { "POSITION": 0, "NORMAL": 1 } },
+            ]),
+            vec![get_positions(&QUAD), get_normals(QUAD.len())],
+        );
+        assert_eq!(weld_vertices(&mut graph, &doc, 0.0), 2);
+        let primitives = get_primitives(&graph, &doc);
+        let indices = primitives
+            .iter()
+            .map(|primitive| primitive.indices(&graph).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(indices[0].0, indices[1].0);
+        let weight = indices[0].get(&graph);
+        assert_eq!(
+            read_indices(&weight.data, weight.component_type).unwrap(),
+            [0, 1, 2, 2, 1, 3]
+        );
+        let positions = primitives[0]
+            .attribute(&graph, Semantic::Positions)
+            .unwrap();
+        assert_eq!(positions.get(&graph).data.len(), 4 * 12);
+    }
+
+    #[test]
+    fn welds_positions_within_tolerance() {
+        let mut positions = QUAD;
+        positions[3][0] = 0.001;
+        let import = |test| {
+            import_test_mesh(
+                test,
+                json!([{ "attributes": { "POSITION": 0 } }]),
+                vec![get_positions(&positions)],
+            )
+        };
+        let (mut graph, doc) = import("weld-exact");
+        assert_eq!(weld_vertices(&mut graph, &doc, 0.0), 1);
+        let (mut graph, doc) = import("weld-tolerance");
+        assert_eq!(weld_vertices(&mut graph, &doc, 0.01), 2);
+    }
+
+    #[test]
+    fn flips_normalized_u16_coordinates_once() {
+        let uvs = [0u16, 0, 65535, 16384, 1000, 65535];
+        let uv_accessor = (
+            uvs.iter().flat_map(|value| value.to_le_bytes()).collect(),
+            json!({ "componentType": 5123, "normalized": true, "type": "VEC2", "count": 3 }),
+        );
+        // both primitives read the same coordinates, which must only be flipped once
+        let (mut graph, doc) = import_test_mesh(
+            "flip-u16",
+            json!([
+                { "attributes": { "POSITION": 0, "TEXCOORD_0": 1 } },
+                { "attributes": { "POSITION": 0, "TEXCOORD_0": 1 } },
+            ]),
+            vec![get_positions(&QUAD[..3]), uv_accessor],
+        );
+        assert_eq!(flip_uv_v(&mut graph, &doc), 1);
+        for primitive in get_primitives(&graph, &doc) {
+            let weight = primitive
+                .attribute(&graph, Semantic::TexCoords(0))
+                .unwrap()
+                .get(&graph);
+            assert!(weight.component_type == ComponentType::U16);
+            let values = weight
+                .data
+                .chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect::<Vec<_>>();
+            // U is untouched and V becomes 1 - V, which for normalized u16s is 65535 - V
+            assert_eq!(values, [0, 65535, 65535, 49151, 1000, 0]);
+        }
+    }
+}
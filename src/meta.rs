@@ -0,0 +1,71 @@
+use std::path::PathBuf;
+
+use bevy::{
+    asset::{
+        meta::{AssetAction, AssetMeta},
+        AssetLoader,
+    },
+    gltf::{GltfLoader, GltfLoaderSettings},
+    render::texture::{
+        ImageAddressMode, ImageFilterMode, ImageLoader, ImageLoaderSettings, ImageSampler,
+        ImageSamplerDescriptor,
+    },
+};
+
+use crate::{
+    config::{TextureConfigs, TextureFilter, TextureWrapMode},
+    processing::write_output,
+};
+
+/// Bevy looks for `<asset>.meta` next to each asset to configure its loader
+fn get_meta_path(dest: &PathBuf) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(".meta");
+    dest.with_file_name(name)
+}
+
+/// Writes a `.meta` sidecar so Bevy samples the texture with the configured wrap mode, filter, and color space
+pub fn write_texture_meta(
+    source: &PathBuf,
+    dest: &PathBuf,
+    config: &TextureConfigs,
+) -> Result<(), String> {
+    let address_mode = match config.get_wrap_mode(dest) {
+        TextureWrapMode::Repeat => ImageAddressMode::Repeat,
+        TextureWrapMode::ClampToEdge => ImageAddressMode::ClampToEdge,
+        TextureWrapMode::MirrorRepeat => ImageAddressMode::MirrorRepeat,
+    };
+    let filter_mode = match config.get_filter(source) {
+        TextureFilter::Nearest => ImageFilterMode::Nearest,
+        TextureFilter::Linear => ImageFilterMode::Linear,
+    };
+    let settings = ImageLoaderSettings {
+        sampler: ImageSampler::Descriptor(ImageSamplerDescriptor {
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+            address_mode_w: address_mode,
+            mag_filter: filter_mode,
+            min_filter: filter_mode,
+            mipmap_filter: filter_mode,
+            ..Default::default()
+        }),
+        is_srgb: config.is_srgb_output(dest),
+        ..Default::default()
+    };
+    write_meta::<ImageLoader>(dest, settings)
+}
+
+/// Writes a `.meta` sidecar so Bevy loads the mesh with explicit loader settings rather than whatever the defaults are at the time
+pub fn write_mesh_meta(dest: &PathBuf) -> Result<(), String> {
+    write_meta::<GltfLoader>(dest, GltfLoaderSettings::default())
+}
+
+fn write_meta<L: AssetLoader>(dest: &PathBuf, settings: L::Settings) -> Result<(), String> {
+    let meta = AssetMeta::<L, ()>::new(AssetAction::Load {
+        loader: std::any::type_name::<L>().to_string(),
+        settings,
+    });
+    let text = ron::ser::to_string_pretty(&meta, ron::ser::PrettyConfig::default())
+        .map_err(|err| format!("Failed to serialize meta: {}", err))?;
+    write_output(&get_meta_path(dest), text).map_err(|err| format!("Failed to write meta: {}", err))
+}
@@ -0,0 +1,93 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use humantime::format_rfc3339_seconds;
+
+use crate::{
+    processing::ProcessingKind,
+    profiling::{ProcessingStage, StageTimings},
+    report::ProcessingReport,
+};
+
+/// Processing kinds get a duration column each, in this order
+const METRIC_KINDS: [ProcessingKind; 8] = [
+    ProcessingKind::Raw,
+    ProcessingKind::Texture,
+    ProcessingKind::Mesh,
+    ProcessingKind::Audio,
+    ProcessingKind::Shader,
+    ProcessingKind::Archive,
+    ProcessingKind::Environment,
+    ProcessingKind::Font,
+];
+
+/// Appends one row for the run to `metrics_csv`, writing the header first when the file is new. Durations are in
+/// milliseconds so the columns plot directly
+pub fn append_build_metrics(
+    path: &Path,
+    report: &ProcessingReport,
+    timings: &StageTimings,
+) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("Failed to open {}: {}", path.display(), err))?;
+    let is_new = file.metadata().map(|meta| meta.len() == 0).unwrap_or(true);
+    let mut text = String::new();
+    if is_new {
+        text.push_str(&get_header());
+        text.push('\n');
+    }
+    text.push_str(&get_row(report, timings));
+    text.push('\n');
+    file.write_all(text.as_bytes())
+        .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
+
+fn get_header() -> String {
+    let mut columns = vec![
+        "timestamp".to_string(),
+        "processed".into(),
+        "skipped".into(),
+        "failed".into(),
+        "total_ms".into(),
+    ];
+    columns.extend(
+        ProcessingStage::ALL
+            .iter()
+            .map(|stage| format!("{}_ms", stage.name())),
+    );
+    columns.extend(
+        METRIC_KINDS
+            .iter()
+            .map(|kind| format!("{:?}_ms", kind).to_lowercase()),
+    );
+    columns.join(",")
+}
+
+fn get_row(report: &ProcessingReport, timings: &StageTimings) -> String {
+    let millis = |duration: Duration| duration.as_millis().to_string();
+    let mut values = vec![
+        format_rfc3339_seconds(SystemTime::now()).to_string(),
+        report.processed.len().to_string(),
+        report.skipped.len().to_string(),
+        report.failed.len().to_string(),
+        millis(report.duration),
+    ];
+    values.extend(
+        ProcessingStage::ALL
+            .iter()
+            .map(|stage| millis(timings.total(*stage))),
+    );
+    values.extend(
+        METRIC_KINDS
+            .iter()
+            .map(|kind| millis(report.kind_durations.get(kind).copied().unwrap_or_default())),
+    );
+    values.join(",")
+}
@@ -0,0 +1,48 @@
+use bevy::prelude::*;
+
+use crate::config::Config;
+
+/// While this file exists in the source directory, watching stops queueing and processing files. Useful during
+/// bulk imports, so half copied batches don't get processed
+pub const PAUSE_FILE_NAME: &str = ".bpm-pause";
+
+#[derive(Resource, Default)]
+pub struct ProcessingPaused(pub bool);
+
+/// Checks for the pause file at the file watching rate, logging whenever processing pauses or resumes
+pub fn check_pause_file(
+    mut paused: ResMut<ProcessingPaused>,
+    config: Res<Config>,
+    time: Res<Time>,
+    mut timer: Local<Option<Timer>>,
+) {
+    let timer = timer.get_or_insert_with(|| {
+        Timer::from_seconds(
+            config.file_watching_rate_seconds as f32,
+            TimerMode::Repeating,
+        )
+    });
+    timer.tick(time.delta());
+    if !timer.just_finished() {
+        return;
+    }
+    let pause_file = config.source_dir.join(PAUSE_FILE_NAME);
+    let is_paused = pause_file.exists();
+    if is_paused == paused.0 {
+        return;
+    }
+    if is_paused {
+        info!(
+            "Processing paused until {} is deleted. Files already being processed will finish",
+            pause_file.display()
+        );
+    } else {
+        info!("Processing resumed");
+    }
+    paused.0 = is_paused;
+}
+
+/// Run condition for the systems that queue and process files
+pub fn is_not_paused(paused: Res<ProcessingPaused>) -> bool {
+    !paused.0
+}
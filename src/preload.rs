@@ -0,0 +1,46 @@
+use std::{collections::BTreeSet, path::Path};
+
+use bevy::prelude::*;
+
+use crate::{config::Config, manifest::Manifest, processing::write_output};
+
+pub const PRELOAD_FILE_NAME: &str = "preload.json";
+
+/// Writes the asset paths of every output in the manifest, secondary outputs like LODs and atlases included. Paths are
+/// relative to the output directory so they can be handed straight to the `AssetServer`
+pub fn write_preload_list(manifest: &Manifest, config: &Config) {
+    let extensions = &config.preload.extensions;
+    let paths: BTreeSet<String> = manifest
+        .entries
+        .values()
+        .flat_map(|entry| std::iter::once(&entry.dest).chain(&entry.outputs))
+        .filter(|output| {
+            extensions.is_empty()
+                || output.extension().is_some_and(|ext| {
+                    extensions
+                        .iter()
+                        .any(|allowed| ext.eq_ignore_ascii_case(allowed.as_str()))
+                })
+        })
+        .filter_map(|output| output.strip_prefix(&config.output_dir).ok())
+        .map(get_asset_path)
+        .collect();
+    let text = match serde_json::to_string_pretty(&paths) {
+        Ok(text) => text,
+        Err(err) => {
+            error!("Failed to serialize preload list: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = write_output(&config.output_dir.join(PRELOAD_FILE_NAME), text) {
+        error!("Failed to write preload list: {}", err);
+    }
+}
+
+/// Asset paths always use forward slashes, regardless of platform
+pub fn get_asset_path(path: &Path) -> String {
+    path.components()
+        .map(|part| part.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
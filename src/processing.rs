@@ -1,21 +1,60 @@
 use std::{
-    cmp::Ordering,
+    cmp::{Ordering, Reverse},
+    collections::{HashMap, HashSet},
+    ffi::OsString,
     fs,
+    io::{BufWriter, ErrorKind, Write},
     path::{Path, PathBuf},
+    thread,
     time::{Duration, Instant},
 };
 
 use crate::{
-    config::Config,
+    archive::ProcessingArchive,
+    config::{
+        self, CaseCollisionPolicy, Config, InvalidMediaPolicy, MetadataErrorPolicy,
+        OverwritePolicy, UnknownExtensionPolicy,
+    },
+    environment::ProcessingEnvironment,
+    font::ProcessingFont,
+    manifest::Manifest,
+    media::check_media_file,
     mesh::ProcessingMesh,
+    pause::{self, PAUSE_FILE_NAME},
+    profiling::{ProcessingStage, StageTimings},
     raw::{self, ProcessingRaw},
+    remote::HTTP_VALIDATORS_FILE_NAME,
+    report::ProcessingReport,
+    shader::ProcessingShader,
+    shutdown::ShutdownRequested,
+    texture::{self, ProcessingTexture},
 };
-use bevy::prelude::*;
-use humantime::format_duration;
-use walkdir::WalkDir;
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future::poll_once, Task},
+};
+use fs4::{fs_std::FileExt, lock_contended_error};
+use humantime::{format_duration, format_rfc3339_seconds};
+use serde::{Deserialize, Serialize};
+use walkdir::{DirEntry, WalkDir};
+
+/// Tracks queued files that haven't finished processing. Incremented when a file is queued and decremented when its
+/// `FileProcessed` event arrives, so it stays accurate no matter how long processors take
+#[derive(Resource, Default)]
+pub struct UnprocessedFiles {
+    pub in_flight: usize,
+    /// Set once a scan walked the whole source tree without stopping at `max_queue_per_scan`
+    pub scan_complete: bool,
+    /// How many files failed to process this run
+    pub failed: usize,
+}
 
-#[derive(Resource)]
-pub struct UnprocessedFiles(pub usize);
+impl UnprocessedFiles {
+    /// True when every stale file has been queued and finished processing
+    pub fn is_finished(&self) -> bool {
+        self.scan_complete && self.in_flight == 0
+    }
+}
 
 /// The core component that links an entity to a specific file in the staging directory
 #[derive(Component, Debug)]
@@ -23,6 +62,80 @@ pub struct FileQueuedForProcessing {
     pub source: PathBuf,
     pub dest: PathBuf,
     pub queue_time: Instant,
+    /// Higher priority files are processed first, from `priorities`
+    pub priority: i32,
+    /// The config with this file's `.bpm.toml` sidecar applied, if it has one
+    pub overrides: Option<Config>,
+}
+
+impl FileQueuedForProcessing {
+    /// The config this file should be processed with
+    pub fn get_config<'a>(&'a self, config: &'a Config) -> &'a Config {
+        self.overrides.as_ref().unwrap_or(config)
+    }
+}
+
+/// Per-file override sidecars are named after the file they apply to, e.g. `foo.png.bpm.toml`
+pub const SIDECAR_EXTENSION: &str = ".bpm.toml";
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Why a file was queued, logged with each queued file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueReason {
+    OutputMissing,
+    SourceNewer,
+    SidecarNewer,
+    SettingsChanged,
+    /// A file the output was built from, like a texture referenced by a gltf, changed
+    DependencyChanged,
+    /// Matched `always_process`
+    Forced,
+    /// Handled by [`MetadataErrorPolicy::Reprocess`]
+    MetadataUnreadable,
+    /// Passed in explicitly, through `--stdin-paths` or `retry-failed`
+    Requested,
+}
+
+impl std::fmt::Display for QueueReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            QueueReason::OutputMissing => "output missing",
+            QueueReason::SourceNewer => "source newer",
+            QueueReason::SidecarNewer => "sidecar newer",
+            QueueReason::SettingsChanged => "settings changed",
+            QueueReason::DependencyChanged => "dependency changed",
+            QueueReason::Forced => "always_process",
+            QueueReason::MetadataUnreadable => "metadata unreadable",
+            QueueReason::Requested => "requested",
+        })
+    }
+}
+
+/// Which processing type handled a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessingKind {
+    Raw,
+    Texture,
+    Mesh,
+    Audio,
+    Shader,
+    Archive,
+    Environment,
+    Font,
+}
+
+/// Sent once per queued file when a processor is finished with it, whether or not it succeeded.
+/// Lets an embedding app react to processed files without scraping logs.
+#[derive(Event, Debug, Clone)]
+pub struct FileProcessed {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    pub kind: ProcessingKind,
+    pub result: Result<(), String>,
+    /// Time from being queued to being finished
+    pub duration: Duration,
 }
 
 /// The core trait for processing information.
@@ -30,77 +143,352 @@ pub struct FileQueuedForProcessing {
 pub trait ProcessingType: 'static {
     type Comp: Component;
     fn get_component() -> Self::Comp;
-    fn matches(ext: &String, config: &Res<Config>) -> bool;
-    fn get_destination(source: &PathBuf) -> Option<PathBuf>;
+    fn matches(ext: &String, config: &Config) -> bool;
+    fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf>;
     fn system(
         query: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
         config: Res<Config>,
         commands: Commands,
+        processed: EventWriter<FileProcessed>,
+        timings: ResMut<StageTimings>,
+        tasks: Query<(&FileQueuedForProcessing, &ProcessingTask)>,
     );
 
+    /// Whether this processing type is turned on in the config. Disabled types are never registered or matched
+    fn is_enabled(_config: &Config) -> bool {
+        true
+    }
+
+    /// Every file this processing type writes for the source, its destination first. Types writing more than their
+    /// destination should override this, so pruning and the preload list know about the extra files
+    fn get_outputs(source: &PathBuf, config: &Config) -> Vec<PathBuf> {
+        Self::get_destination(source, config).into_iter().collect()
+    }
+
+    /// Whether this processing type would have written the output file from a source that still exists. Pruning
+    /// deletes outputs no processing type claims
+    fn owns_output(path: &Path, config: &Config) -> bool {
+        is_output_of(path, config, |source, config| {
+            match get_extension(source).filter(|ext| Self::matches(ext, config)) {
+                Some(_) => Self::get_outputs(source, config),
+                None => vec![],
+            }
+        })
+    }
+
     fn register(app: &mut App) {
-        app.add_systems(Update, Self::system);
+        if !Self::is_enabled(app.world().resource::<Config>()) {
+            return;
+        }
+        app.add_systems(Update, Self::system.run_if(pause::is_not_paused));
+    }
+}
+
+/// Work a processor handed off to a task pool, polled by [`poll_processing_tasks`]. The entity keeps its
+/// [`FileQueuedForProcessing`] until the task finishes so it still counts as queued
+#[derive(Component)]
+pub struct ProcessingTask {
+    kind: ProcessingKind,
+    task: Task<(Result<(), String>, StageTimings)>,
+}
+
+impl ProcessingTask {
+    pub fn new(kind: ProcessingKind, task: Task<(Result<(), String>, StageTimings)>) -> Self {
+        Self { kind, task }
+    }
+
+    pub fn kind(&self) -> ProcessingKind {
+        self.kind
+    }
+}
+
+/// How many more tasks of the kind can be started without going over `workers`
+pub fn get_free_workers(
+    tasks: &Query<(&FileQueuedForProcessing, &ProcessingTask)>,
+    kind: ProcessingKind,
+    workers: usize,
+) -> usize {
+    let running = tasks.iter().filter(|(_, task)| task.kind == kind).count();
+    workers.saturating_sub(running)
+}
+
+/// Drops queued files whose source was deleted after the scan queued them, before a processor tries to read it.
+/// Anything the source already produced is left for `bpm prune`
+pub fn drop_deleted_sources(
+    query: Query<(Entity, &FileQueuedForProcessing), Without<ProcessingTask>>,
+    mut commands: Commands,
+    mut unprocessed: ResMut<UnprocessedFiles>,
+) {
+    for (e, entry) in query.iter() {
+        if !entry.source.exists() {
+            drop_deleted_source(e, entry, &mut commands, &mut unprocessed);
+        }
+    }
+}
+
+/// Forgets the file without sending [`FileProcessed`], so it's neither recorded as processed nor as failed
+fn drop_deleted_source(
+    e: Entity,
+    entry: &FileQueuedForProcessing,
+    commands: &mut Commands,
+    unprocessed: &mut UnprocessedFiles,
+) {
+    debug!(
+        "Dropping {}, it was deleted after being queued",
+        entry.source.display()
+    );
+    unprocessed.in_flight = unprocessed.in_flight.saturating_sub(1);
+    commands.entity(e).despawn_recursive();
+}
+
+/// Reports tasks that finished since the last frame the same way processors report inline work
+pub fn poll_processing_tasks(
+    mut query: Query<(Entity, &FileQueuedForProcessing, &mut ProcessingTask)>,
+    mut commands: Commands,
+    mut processed: EventWriter<FileProcessed>,
+    mut timings: ResMut<StageTimings>,
+    mut unprocessed: ResMut<UnprocessedFiles>,
+) {
+    for (e, entry, mut processing_task) in query.iter_mut() {
+        let Some((result, task_timings)) = block_on(poll_once(&mut processing_task.task)) else {
+            continue;
+        };
+        timings.merge(&task_timings);
+        if result.is_err() && !entry.source.exists() {
+            // deleted while the task was reading it, which isn't worth reporting as a failure
+            drop_deleted_source(e, entry, &mut commands, &mut unprocessed);
+            continue;
+        }
+        match &result {
+            Ok(_) => info!(
+                "{} => {} -- {}",
+                entry.source.display(),
+                entry.dest.display(),
+                get_human_duration(entry.queue_time.elapsed())
+            ),
+            Err(err) => error!("Failed to process {}: {}", entry.source.display(), err),
+        }
+        processed.send(FileProcessed {
+            source: entry.source.clone(),
+            dest: entry.dest.clone(),
+            kind: processing_task.kind,
+            result,
+            duration: entry.queue_time.elapsed(),
+        });
+        commands.entity(e).despawn_recursive();
     }
 }
 
 pub struct AssetProcessing;
 
 impl AssetProcessing {
-    // I thought a type to encapsulate the fns would be useful, but right now there's just the one func. Shame about that
+    /// Asks the processing type that would handle the file for its destination, checked in the same order as [`queue_file`].
+    /// Directories and unhandled files fall back to mirroring the path as-is
+    pub fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf> {
+        match get_processing_kind(source, config) {
+            Some(ProcessingKind::Texture) => ProcessingTexture::get_destination(source, config),
+            Some(ProcessingKind::Mesh) => ProcessingMesh::get_destination(source, config),
+            Some(ProcessingKind::Shader) => ProcessingShader::get_destination(source, config),
+            Some(ProcessingKind::Archive) => ProcessingArchive::get_destination(source, config),
+            Some(ProcessingKind::Environment) => {
+                ProcessingEnvironment::get_destination(source, config)
+            }
+            Some(ProcessingKind::Font) => ProcessingFont::get_destination(source, config),
+            _ => raw::ProcessingRaw::get_destination(source, config),
+        }
+    }
 
-    fn get_destination(source: &PathBuf) -> Option<PathBuf> {
-        if let Some(path) = raw::ProcessingRaw::get_destination(source) {
-            return Some(path);
+    /// Every file the processing type handling the file writes for it, with the file's sidecar overrides applied
+    pub fn get_outputs(source: &PathBuf, config: &Config) -> Vec<PathBuf> {
+        let overrides = load_sidecar_config(source, config);
+        let config = overrides.as_ref().unwrap_or(config);
+        match get_processing_kind(source, config) {
+            Some(ProcessingKind::Texture) => ProcessingTexture::get_outputs(source, config),
+            Some(ProcessingKind::Mesh) => ProcessingMesh::get_outputs(source, config),
+            Some(ProcessingKind::Shader) => ProcessingShader::get_outputs(source, config),
+            Some(ProcessingKind::Archive) => ProcessingArchive::get_outputs(source, config),
+            Some(ProcessingKind::Environment) => ProcessingEnvironment::get_outputs(source, config),
+            Some(ProcessingKind::Font) => ProcessingFont::get_outputs(source, config),
+            _ => raw::ProcessingRaw::get_outputs(source, config),
         }
-        None
     }
 }
 
+/// Finds which enabled processing type handles the file, if any. Checked in a fixed order so the first match wins.
+/// Files nothing matches go to the raw processor when `unknown_extension_policy` is `Copy`
+pub fn get_processing_kind(source: &PathBuf, config: &Config) -> Option<ProcessingKind> {
+    if let Some(ext) = get_extension(source) {
+        if ProcessingRaw::is_enabled(config) && ProcessingRaw::matches(&ext, config) {
+            return Some(ProcessingKind::Raw);
+        }
+        if ProcessingTexture::is_enabled(config) && ProcessingTexture::matches(&ext, config) {
+            return Some(ProcessingKind::Texture);
+        }
+        if ProcessingMesh::is_enabled(config) && ProcessingMesh::matches(&ext, config) {
+            return Some(ProcessingKind::Mesh);
+        }
+        if ProcessingShader::is_enabled(config) && ProcessingShader::matches(&ext, config) {
+            return Some(ProcessingKind::Shader);
+        }
+        if ProcessingArchive::is_enabled(config) && ProcessingArchive::matches(&ext, config) {
+            return Some(ProcessingKind::Archive);
+        }
+        if ProcessingEnvironment::is_enabled(config) && ProcessingEnvironment::matches(&ext, config)
+        {
+            return Some(ProcessingKind::Environment);
+        }
+        if ProcessingFont::is_enabled(config) && ProcessingFont::matches(&ext, config) {
+            return Some(ProcessingKind::Font);
+        }
+    }
+    match config.unknown_extension_policy {
+        UnknownExtensionPolicy::Copy if ProcessingRaw::is_enabled(config) => {
+            Some(ProcessingKind::Raw)
+        }
+        _ => None,
+    }
+}
+
+/// Checks the sources in the directory mirroring the output's, since processing types write their outputs to the
+/// mirrored location unless `output_templates` says otherwise. `get_outputs` lists what a source would be written to, or nothing if it isn't handled
+pub fn is_output_of(
+    output: &Path,
+    config: &Config,
+    get_outputs: impl Fn(&PathBuf, &Config) -> Vec<PathBuf>,
+) -> bool {
+    let is_source_of = |source: &PathBuf| {
+        if !source.is_file() || is_bpm_file(source) {
+            return false;
+        }
+        let overrides = load_sidecar_config(source, config);
+        get_outputs(source, overrides.as_ref().unwrap_or(config))
+            .iter()
+            .any(|dest| dest == output)
+    };
+    if !config.output_templates.is_empty() {
+        // templated outputs can be anywhere, so every source has to be checked
+        return walk_source_files(config).any(|(_, source)| is_source_of(&source));
+    }
+    let Some(parent) = output
+        .strip_prefix(&config.output_dir)
+        .ok()
+        .and_then(|relative| relative.parent())
+    else {
+        return false;
+    };
+    config.get_source_roots().into_iter().any(|root| {
+        let Ok(entries) = fs::read_dir(root.join(parent)) else {
+            return false;
+        };
+        entries.flatten().any(|entry| is_source_of(&entry.path()))
+    })
+}
+
 #[derive(Component, Debug)]
 pub struct RefreshTimer(pub Timer);
 
+/// Remembered between scans so once-per-run work stays once per run
+#[derive(Default)]
+pub struct ScanState {
+    /// `always_process` files already forced this run
+    forced: HashSet<PathBuf>,
+    /// Empty or broken media files already reported under `invalid_media_policy = "Skip"`
+    invalid_media: HashSet<PathBuf>,
+    /// Raw files already reported for exceeding `raw.max_size_bytes`
+    oversized: HashSet<PathBuf>,
+    /// Stale outputs already reported as protected by `overwrite_policy = "Never"`
+    protected: HashSet<PathBuf>,
+    /// Case collisions already reported, so watching doesn't repeat them every scan
+    reported_collisions: HashSet<PathBuf>,
+    /// Sources already reported for sharing their file name with a source in another directory
+    reported_duplicates: HashSet<PathBuf>,
+    /// Files waiting to be processed after the previous scan
+    last_queue_depth: usize,
+    /// How many scans in a row the queue has grown
+    backlog_growth_scans: u32,
+}
+
 pub fn check_for_stale_files(
     mut timer_query: Query<&mut RefreshTimer>,
     currently_queued: Query<&FileQueuedForProcessing>,
     mut commands: Commands,
     mut unprocessed: ResMut<UnprocessedFiles>,
+    mut timings: ResMut<StageTimings>,
+    mut scan_state: Local<ScanState>,
+    mut report: ResMut<ProcessingReport>,
+    manifest: Res<Manifest>,
+    shutdown: Res<ShutdownRequested>,
     time: Res<Time>,
     config: Res<Config>,
 ) {
+    if shutdown.is_requested() {
+        return;
+    }
     let mut timer = timer_query.single_mut();
     timer.0.tick(time.delta());
     if !timer.0.finished() {
         return;
     }
+    timings.time(ProcessingStage::Scan, || {
+        scan_for_stale_files(
+            &currently_queued,
+            &mut commands,
+            &mut unprocessed,
+            &mut scan_state,
+            &mut report,
+            &manifest,
+            &config,
+        )
+    });
+    check_backlog(&unprocessed, &mut scan_state, &config);
+}
+
+/// Watches the queue depth between scans, warning when files arrive faster than processors finish them
+fn check_backlog(unprocessed: &UnprocessedFiles, scan_state: &mut ScanState, config: &Config) {
+    let depth = unprocessed.in_flight;
+    if depth > scan_state.last_queue_depth {
+        scan_state.backlog_growth_scans += 1;
+    } else {
+        scan_state.backlog_growth_scans = 0;
+    }
+    scan_state.last_queue_depth = depth;
+    let limit = config.backlog_warning_scans;
+    // repeats every `limit` scans for as long as it keeps growing, rather than on every scan
+    if limit > 0
+        && scan_state.backlog_growth_scans > 0
+        && scan_state.backlog_growth_scans % limit == 0
+    {
+        warn!(
+            "processing backlog growing (queue={}). Consider raising task_pools.max_threads or file_watching_rate_seconds",
+            depth
+        );
+    }
+}
+
+fn scan_for_stale_files(
+    currently_queued: &Query<&FileQueuedForProcessing>,
+    commands: &mut Commands,
+    unprocessed: &mut ResMut<UnprocessedFiles>,
+    scan_state: &mut ScanState,
+    report: &mut ProcessingReport,
+    manifest: &Manifest,
+    config: &Res<Config>,
+) {
     let currently_queued_paths = currently_queued
         .iter()
         .map(|comp| comp.source.clone())
         .collect::<Vec<_>>();
 
     let mut count: usize = 0;
+    let mut reached_max = false;
     let mut unhandled_files = Vec::<PathBuf>::new();
+    let mut folded_dests = HashMap::new();
+    let mut dests = HashMap::new();
+    let mut basenames = HashMap::new();
+    let mut stale = vec![];
 
-    for entry_result in WalkDir::new(Path::new("assets-dev"))
-        .follow_links(true)
-        .sort_by_file_name()
-    {
-        let entry = match entry_result {
-            Ok(e) => e,
-            Err(err) => {
-                // handle IO errors
-                error!("Error encountered while checking for stale files: {:}", err);
-                continue;
-            }
-        };
-        if entry.path() == Path::new("assets-dev").join("config.toml") {
-            continue;
-        }
-        let Ok(entry_path) = entry.path().strip_prefix(Path::new("assets-dev")) else {
-            error!("Failed to strip prefix 'assets-dev' from source file path. This likely means we somehow got in the wrong folder!!!");
-            continue;
-        };
-        let source_path = Path::new("assets-dev").join(entry_path);
-        let Some(dest_path) = AssetProcessing::get_destination(&source_path) else {
+    for (entry, source_path) in walk_source_files(config) {
+        let Some(dest_path) = AssetProcessing::get_destination(&source_path, config) else {
             continue;
         };
 
@@ -112,20 +500,138 @@ pub fn check_for_stale_files(
             // replicate directory structure
             // TODO: would be nice to be able to omit empty dirs.
 
-            let _ = fs::create_dir_all(dest_path);
+            if let Err(err) = fs::create_dir_all(&dest_path) {
+                warn!(
+                    "Failed to create output directory {}: {}",
+                    dest_path.display(),
+                    err
+                );
+            }
+            continue;
+        }
+
+        if config.report_duplicate_basenames {
+            check_duplicate_basename(&mut basenames, scan_state, &source_path, config);
+        }
+        if let Some(other) = check_output_collision(&mut dests, &source_path, &dest_path) {
+            if scan_state.reported_collisions.insert(source_path.clone()) {
+                error!(
+                    "{} and {} both map to {}. Skipping {}",
+                    other.display(),
+                    source_path.display(),
+                    dest_path.display(),
+                    source_path.display()
+                );
+            }
+            report.skipped.insert(source_path.clone());
+            continue;
+        }
+        if let Some(other) = check_case_collision(&mut folded_dests, &source_path, &dest_path) {
+            if scan_state.reported_collisions.insert(source_path.clone()) {
+                let message = format!(
+                    "{} and {} only differ by case and map to the same output on case-insensitive filesystems",
+                    other.display(),
+                    source_path.display()
+                );
+                match config.case_collision_policy {
+                    CaseCollisionPolicy::Warn => warn!("{}", message),
+                    CaseCollisionPolicy::Error => {
+                        error!("{}. Skipping {}", message, source_path.display())
+                    }
+                }
+            }
+            if matches!(config.case_collision_policy, CaseCollisionPolicy::Error) {
+                report.skipped.insert(source_path.clone());
+                continue;
+            }
+        }
+
+        if let Some(err) = raw::check_size_limit(&source_path, config) {
+            if scan_state.oversized.insert(source_path.clone()) {
+                warn!("Skipping raw file {}: {}", source_path.display(), err);
+            }
+            report.skipped.insert(source_path.clone());
             continue;
         }
+        scan_state.oversized.remove(&source_path);
 
-        if is_stale(&source_path, &dest_path) {
-            if queue_file(&mut commands, source_path.clone(), dest_path, &config) {
-                count += 1;
-                debug!("Queued for processing: {}", source_path.display());
-            } else {
-                unhandled_files.push(source_path);
+        // `always_process` files are forced once per run, not on every scan of a watching session
+        let forced_now = (config.is_always_processed(&source_path)
+            || config.overwrite_policy == OverwritePolicy::Always)
+            && scan_state.forced.insert(source_path.clone());
+        let reason = if forced_now {
+            Some(QueueReason::Forced)
+        } else {
+            needs_processing(&source_path, &dest_path, manifest, config)
+        };
+        let reason = reason.filter(|reason| {
+            let protected = is_overwrite_protected(&dest_path, config);
+            if protected {
+                report.skipped.insert(source_path.clone());
+            }
+            if protected && scan_state.protected.insert(source_path.clone()) {
+                warn!(
+                    "{} is stale ({}) but overwrite_policy = \"Never\" leaves {} untouched",
+                    source_path.display(),
+                    reason,
+                    dest_path.display()
+                );
+            }
+            !protected
+        });
+        let Some(reason) = reason else {
+            continue;
+        };
+        // only probed for stale files, so up to date files aren't opened or decoded on every scan
+        if is_locked(&source_path) {
+            // it stays stale (or forced), so the next scan tries again once the writer is done
+            debug!(
+                "Skipping {} while another process has it locked",
+                source_path.display()
+            );
+            scan_state.forced.remove(&source_path);
+            continue;
+        }
+        if matches!(config.invalid_media_policy, InvalidMediaPolicy::Skip)
+            && is_media_file(&source_path, config)
+        {
+            if let Err(err) = check_media_file(&source_path) {
+                if scan_state.invalid_media.insert(source_path.clone()) {
+                    warn!(
+                        "Skipping {} until it is readable: {}",
+                        source_path.display(),
+                        err
+                    );
+                }
+                report.skipped.insert(source_path.clone());
+                scan_state.forced.remove(&source_path);
+                continue;
+            }
+            scan_state.invalid_media.remove(&source_path);
+        }
+        stale.push((source_path, dest_path, reason));
+    }
+    // stable, so files of equal priority keep the walk order
+    stale.sort_by_key(|(source, _, _)| Reverse(config.get_priority(source)));
+    for (source_path, dest_path, reason) in stale {
+        if reached_max {
+            // forced again on the scan that gets to queue it
+            scan_state.forced.remove(&source_path);
+            continue;
+        }
+        if queue_file(commands, source_path.clone(), dest_path, config) {
+            count += 1;
+            debug!("Queued {}: {}", source_path.display(), reason);
+            if config.max_queue_per_scan.is_some_and(|max| count >= max) {
+                debug!("Reached max_queue_per_scan, remaining stale files will be queued on later scans");
+                reached_max = true;
             }
+        } else {
+            unhandled_files.push(source_path);
         }
     }
-    unprocessed.0 = count;
+    unprocessed.in_flight += count;
+    unprocessed.scan_complete = !reached_max;
     if count > 0 {
         let total = count + currently_queued_paths.len();
         debug!(
@@ -145,14 +651,302 @@ pub fn check_for_stale_files(
     }
 }
 
-fn is_stale(source: &PathBuf, dest: &PathBuf) -> bool {
-    // get metadata, defaulting to mark as stale if it cannot be found
-    // no need to check if the paths exist since that's built in to the metadata error
-    let Ok(meta_source) = fs::metadata(source) else {
-        return true;
+/// Counts each finished file, successful or not, against the files in flight
+pub fn track_processed_files(
+    mut events: EventReader<FileProcessed>,
+    mut unprocessed: ResMut<UnprocessedFiles>,
+) {
+    for event in events.read() {
+        unprocessed.in_flight = unprocessed.in_flight.saturating_sub(1);
+        if event.result.is_err() {
+            unprocessed.failed += 1;
+        }
+    }
+}
+
+/// Warns once per source about file names already used by a source in another directory, which get confusing in
+/// editors and collide when outputs are flattened
+fn check_duplicate_basename(
+    basenames: &mut HashMap<OsString, PathBuf>,
+    scan_state: &mut ScanState,
+    source: &PathBuf,
+    config: &Config,
+) {
+    let Some(name) = source.file_name() else {
+        return;
+    };
+    let Some(other) = basenames.get(name) else {
+        basenames.insert(name.to_os_string(), source.clone());
+        return;
+    };
+    if !scan_state.reported_duplicates.insert(source.clone()) {
+        return;
+    }
+    let get_dir = |path: &PathBuf| {
+        let dir = config
+            .get_source_relative_path(path)
+            .and_then(|relative| relative.parent().map(Path::to_path_buf))
+            .unwrap_or_default();
+        format!("{}/", dir.display())
+    };
+    warn!(
+        "duplicate basename '{}' in dirs {} and {}",
+        name.to_string_lossy(),
+        get_dir(other),
+        get_dir(source)
+    );
+}
+
+/// Finds another source already mapped to the same output, like two sources named alike in different directories
+/// under an output template without `{dir}`. Frames of a sequence share their combined output on purpose
+fn check_output_collision(
+    dests: &mut HashMap<PathBuf, PathBuf>,
+    source: &PathBuf,
+    dest: &PathBuf,
+) -> Option<PathBuf> {
+    match dests.get(dest) {
+        Some(other) => (!texture::is_same_sequence(other, source)).then(|| other.clone()),
+        None => {
+            dests.insert(dest.clone(), source.clone());
+            None
+        }
+    }
+}
+
+/// Records the destination case-folded, returning the source already using it when a different source's destination
+/// only differs by case. Sources sharing the exact same destination (like sequence frames) aren't collisions
+fn check_case_collision(
+    folded_dests: &mut HashMap<String, (PathBuf, PathBuf)>,
+    source: &PathBuf,
+    dest: &PathBuf,
+) -> Option<PathBuf> {
+    let folded = dest.to_string_lossy().to_lowercase();
+    match folded_dests.get(&folded) {
+        Some((other_source, other_dest)) => (other_dest != dest).then(|| other_source.clone()),
+        None => {
+            folded_dests.insert(folded, (source.clone(), dest.clone()));
+            None
+        }
+    }
+}
+
+/// Walks the source directory and extracted archives up to `max_depth`, skipping bpm's own files. Yields each entry
+/// with its path relative to the working directory
+pub fn walk_source_files(config: &Config) -> impl Iterator<Item = (DirEntry, PathBuf)> + '_ {
+    config
+        .get_source_roots()
+        .into_iter()
+        // nothing has been extracted yet until the first archive is processed
+        .filter(|root| *root == config.source_dir || root.is_dir())
+        .flat_map(move |root| walk_source_root(root, config))
+}
+
+fn walk_source_root<'a>(
+    root: &'a Path,
+    config: &'a Config,
+) -> impl Iterator<Item = (DirEntry, PathBuf)> + 'a {
+    WalkDir::new(root)
+        .follow_links(true)
+        .max_depth(config.max_depth.unwrap_or(usize::MAX))
+        .sort_by_file_name()
+        .into_iter()
+        // archives are extracted into a temp directory before being swapped in
+        .filter_entry(|entry| {
+            !entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with(TEMP_FILE_SUFFIX)
+        })
+        .filter_map(move |entry_result| {
+            let entry = match entry_result {
+                Ok(e) => e,
+                Err(err) => {
+                    // handle IO errors
+                    error!("Error encountered while checking for stale files: {:}", err);
+                    return None;
+                }
+            };
+            if is_bpm_file(entry.path()) {
+                return None;
+            }
+            let source_path = entry.path().to_path_buf();
+            Some((entry, source_path))
+        })
+}
+
+/// Why the source needs processing, if it does: its (or its sidecar's) destination is out of date, the settings
+/// that produced the destination have changed since, or one of its recorded dependencies changed
+fn needs_processing(
+    source: &PathBuf,
+    dest: &PathBuf,
+    manifest: &Manifest,
+    config: &Config,
+) -> Option<QueueReason> {
+    let sidecar_path = get_sidecar_path(source);
+    // editing a sidecar should reprocess the file it applies to
+    is_stale(source, dest, &config.on_metadata_error)
+        .or_else(|| {
+            if !sidecar_path.exists() {
+                return None;
+            }
+            is_stale(&sidecar_path, dest, &config.on_metadata_error).map(|reason| match reason {
+                QueueReason::SourceNewer => QueueReason::SidecarNewer,
+                other => other,
+            })
+        })
+        .or_else(|| {
+            is_config_changed(source, manifest, config).then_some(QueueReason::SettingsChanged)
+        })
+        .or_else(|| {
+            is_dependency_changed(source, dest, manifest).then_some(QueueReason::DependencyChanged)
+        })
+}
+
+/// Whether the file is decoded by a media processor, which [`check_media_file`] applies to
+fn is_media_file(source: &PathBuf, config: &Config) -> bool {
+    matches!(
+        get_processing_kind(source, config),
+        Some(
+            ProcessingKind::Texture
+                | ProcessingKind::Mesh
+                | ProcessingKind::Audio
+                | ProcessingKind::Environment
+                | ProcessingKind::Font
+        )
+    )
+}
+
+/// Whether `overwrite_policy` keeps an existing output from being replaced. Missing outputs are always written
+fn is_overwrite_protected(dest: &Path, config: &Config) -> bool {
+    config.overwrite_policy == OverwritePolicy::Never && dest.exists()
+}
+
+/// Whether any dependency recorded for the source was modified after its output was written. Missing dependencies
+/// are left for the processor to report
+fn is_dependency_changed(source: &PathBuf, dest: &PathBuf, manifest: &Manifest) -> bool {
+    let Some(entry) = manifest.entries.get(source) else {
+        return false;
     };
-    let Ok(meta_dest) = fs::metadata(dest) else {
-        return true;
+    let Ok(dest_modified) = fs::metadata(dest).and_then(|meta| meta.modified()) else {
+        return false;
+    };
+    entry.dependencies.iter().any(|dependency| {
+        fs::metadata(dependency)
+            .and_then(|meta| meta.modified())
+            .is_ok_and(|modified| modified > dest_modified)
+    })
+}
+
+/// Compares the cache key recorded for the source's last output against its current settings. Entries written
+/// before cache keys were recorded are left alone rather than reprocessing everything
+fn is_config_changed(source: &PathBuf, manifest: &Manifest, config: &Config) -> bool {
+    let Some(recorded) = manifest
+        .entries
+        .get(source)
+        .and_then(|entry| entry.cache_key)
+    else {
+        return false;
+    };
+    let changed = get_cache_key(source, config).is_some_and(|key| key != recorded);
+    if changed {
+        debug!(
+            "Settings for {} changed since it was processed",
+            source.display()
+        );
+    }
+    changed
+}
+
+/// A hash of the effective settings (including any sidecar overrides) for the processor handling the source
+pub fn get_cache_key(source: &PathBuf, config: &Config) -> Option<u64> {
+    let overrides = load_sidecar_config(source, config);
+    let config = overrides.as_ref().unwrap_or(config);
+    let kind = get_processing_kind(source, config)?;
+    Some(config.get_cache_key(kind))
+}
+
+/// Lists every source file that the next processing run would queue, without queueing anything
+pub fn list_stale_files(config: &Config) -> Vec<PathBuf> {
+    let manifest = Manifest::load(config);
+    walk_source_files(config)
+        .filter(|(entry, _)| !entry.file_type().is_dir())
+        .filter(|(_, source)| get_processing_kind(source, config).is_some())
+        .filter_map(|(_, source)| {
+            let dest = AssetProcessing::get_destination(&source, config)?;
+            let stale = config.is_always_processed(&source)
+                || config.overwrite_policy == OverwritePolicy::Always
+                || needs_processing(&source, &dest, &manifest, config).is_some();
+            (stale && !is_overwrite_protected(&dest, config)).then_some(source)
+        })
+        .collect()
+}
+
+/// Whether another process holds an exclusive lock on the file, which exporters can hold while saving. Only
+/// detects locks taken through the OS locking APIs, and reports unlocked wherever locking isn't supported
+fn is_locked(path: &Path) -> bool {
+    let Ok(file) = fs::File::open(path) else {
+        return false;
+    };
+    // called through the trait, since newer std versions add inherent methods with the same names
+    match FileExt::try_lock_shared(&file) {
+        Ok(_) => {
+            let _ = FileExt::unlock(&file);
+            false
+        }
+        Err(err) => err.raw_os_error() == lock_contended_error().raw_os_error(),
+    }
+}
+
+/// Creates the mirrored output directory structure without processing anything.
+/// Only directories that will hold at least one file get created. Returns the number of files whose parent dirs were ensured
+pub fn scaffold_output_dirs(config: &Config) -> usize {
+    let mut count = 0;
+    for (entry, source_path) in walk_source_files(config) {
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let Some(dest_path) = AssetProcessing::get_destination(&source_path, config) else {
+            continue;
+        };
+        if let Some(parent) = dest_path.parent() {
+            let _ = fs::create_dir_all(parent);
+            count += 1;
+        }
+    }
+    count
+}
+
+fn is_stale(source: &PathBuf, dest: &PathBuf, policy: &MetadataErrorPolicy) -> Option<QueueReason> {
+    // a missing destination always needs processing. Any other stat failure is handled by the configured policy
+    let metadata =
+        fs::metadata(source).and_then(|meta_source| Ok((meta_source, fs::metadata(dest)?)));
+    let (meta_source, meta_dest) = match metadata {
+        Ok(pair) => pair,
+        Err(err) if err.kind() == ErrorKind::NotFound && source.exists() => {
+            return Some(QueueReason::OutputMissing)
+        }
+        Err(err) => {
+            return match policy {
+                MetadataErrorPolicy::Reprocess => Some(QueueReason::MetadataUnreadable),
+                MetadataErrorPolicy::Retry => {
+                    // not slept on here, the scan runs on the main schedule. The next scan reads it again
+                    debug!(
+                        "Retrying {} on the next scan since its metadata could not be read: {}",
+                        source.display(),
+                        err
+                    );
+                    None
+                }
+                MetadataErrorPolicy::Skip => {
+                    warn!(
+                        "Skipping {} since its metadata could not be read: {}",
+                        source.display(),
+                        err
+                    );
+                    None
+                }
+            }
+        }
     };
     let time_source = match meta_source.modified() {
         Ok(time) => Some(time),
@@ -170,44 +964,273 @@ fn is_stale(source: &PathBuf, dest: &PathBuf) -> bool {
     };
     if time_source.is_none() || time_dest.is_none() {
         error_once!("Your system does not support some of the basic file operations required for this app to work. Honestly I have no clue how we got here. Error referring to: {}", source.display());
-        return false;
+        return None;
     }
     // unwrapping should technically be safe at this point.
-    time_source.unwrap().cmp(&time_dest.unwrap()) == Ordering::Greater
+    let (time_source, time_dest) = (time_source.unwrap(), time_dest.unwrap());
+    if time_source.cmp(&time_dest) == Ordering::Greater {
+        return Some(QueueReason::SourceNewer);
+    }
+    trace!(
+        "Skipped {}: dest newer (src {}, dest {})",
+        source.display(),
+        format_rfc3339_seconds(time_source),
+        format_rfc3339_seconds(time_dest)
+    );
+    None
 }
-fn queue_file(
+
+pub fn queue_file(
     commands: &mut Commands,
     source: PathBuf,
     dest: PathBuf,
     config: &Res<Config>,
 ) -> bool {
-    let Some(file_ext_os) = source.extension() else {
-        return false;
-    };
-    let Some(file_ext) = file_ext_os
-        .to_ascii_lowercase()
-        .to_str()
-        .and_then(|s| Some(s.to_string()))
-    else {
+    let Some(kind) = get_processing_kind(&source, config) else {
         return false;
     };
 
+    let overrides = load_sidecar_config(&source, config);
     let fqfp = FileQueuedForProcessing {
+        priority: config.get_priority(&source),
         source,
         dest,
         queue_time: Instant::now(),
+        overrides,
     };
-    if ProcessingRaw::matches(&file_ext, config) {
-        commands.spawn((fqfp, ProcessingRaw::get_component()));
-        return true;
+    match kind {
+        ProcessingKind::Raw => commands.spawn((fqfp, ProcessingRaw::get_component())),
+        ProcessingKind::Texture => commands.spawn((fqfp, ProcessingTexture::get_component())),
+        ProcessingKind::Mesh => commands.spawn((fqfp, ProcessingMesh::get_component())),
+        ProcessingKind::Shader => commands.spawn((fqfp, ProcessingShader::get_component())),
+        ProcessingKind::Archive => commands.spawn((fqfp, ProcessingArchive::get_component())),
+        ProcessingKind::Environment => {
+            commands.spawn((fqfp, ProcessingEnvironment::get_component()))
+        }
+        ProcessingKind::Font => commands.spawn((fqfp, ProcessingFont::get_component())),
+        ProcessingKind::Audio => return false, // no audio processing yet
+    };
+    true
+}
+
+pub fn get_sidecar_path(source: &PathBuf) -> PathBuf {
+    let mut path = source.clone().into_os_string();
+    path.push(SIDECAR_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Files anywhere in the source tree that configure bpm rather than being assets: `config.toml`, directory level
+/// `.bpm.toml` files, `*.bpm.toml` sidecars, the pause file, and what remote syncs keep in their cache
+pub fn is_bpm_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| {
+            name == CONFIG_FILE_NAME
+                || name == PAUSE_FILE_NAME
+                || name == HTTP_VALIDATORS_FILE_NAME
+                || name.ends_with(SIDECAR_EXTENSION)
+        })
+}
+
+fn load_sidecar_config(source: &PathBuf, config: &Config) -> Option<Config> {
+    let sidecar_path = get_sidecar_path(source);
+    let text = fs::read_to_string(&sidecar_path).ok()?;
+    match config::apply_overrides(config, &text) {
+        Ok(file_config) => Some(file_config),
+        Err(err) => {
+            error!(
+                "Ignoring invalid sidecar {}: {}",
+                sidecar_path.display(),
+                err
+            );
+            None
+        }
     }
-    if ProcessingMesh::matches(&file_ext, config) {
-        commands.spawn((fqfp, ProcessingMesh::get_component()));
-        return true;
+}
+
+/// The lowercased extension used for matching files to processing types
+pub fn get_extension(path: &PathBuf) -> Option<String> {
+    path.extension()?
+        .to_ascii_lowercase()
+        .to_str()
+        .and_then(|s| Some(s.to_string()))
+}
+
+/// Writes to a temporary sibling file then renames it into place, so an interrupted write never leaves a partial output
+pub fn write_output(dest: &PathBuf, bytes: impl AsRef<[u8]>) -> std::io::Result<()> {
+    create_parent_dir(dest)?;
+    let temp_path = get_temp_path(dest);
+    fs::write(&temp_path, bytes)?;
+    fs::rename(&temp_path, dest)
+}
+
+/// Like [`write_output`] but streaming the contents through a buffered writer, so large outputs assembled from several
+/// pieces never need to be joined in memory first
+pub fn write_output_with(
+    dest: &PathBuf,
+    write: impl FnOnce(&mut dyn Write) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    create_parent_dir(dest)?;
+    let temp_path = get_temp_path(dest);
+    let mut writer = BufWriter::new(fs::File::create(&temp_path)?);
+    write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&temp_path, dest)
+}
+
+/// Like [`write_output`] but copying an existing file
+pub fn copy_output(source: &PathBuf, dest: &PathBuf) -> std::io::Result<u64> {
+    create_parent_dir(dest)?;
+    let temp_path = get_temp_path(dest);
+    let bytes = fs::copy(source, &temp_path)?;
+    fs::rename(&temp_path, dest)?;
+    Ok(bytes)
+}
+
+/// How many times creating an output directory is attempted before the write is failed
+const CREATE_DIR_ATTEMPTS: u32 = 3;
+
+/// Makes sure the directory an output is written into exists. The scan already mirrors the source tree, but that can
+/// race with processors running in parallel or with the directory being removed in between, so it is checked again
+/// right before writing
+fn create_parent_dir(dest: &Path) -> std::io::Result<()> {
+    let Some(parent) = dest
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    else {
+        return Ok(());
+    };
+    let mut result = Ok(());
+    for attempt in 0..CREATE_DIR_ATTEMPTS {
+        if attempt > 0 {
+            thread::sleep(Duration::from_millis(50));
+        }
+        result = fs::create_dir_all(parent);
+        match &result {
+            // another writer creating the same directory at the same time is fine
+            Err(err) if err.kind() == ErrorKind::AlreadyExists && parent.is_dir() => return Ok(()),
+            Err(_) => continue,
+            Ok(_) => break,
+        }
     }
-    false
+    result
+}
+
+/// Appended to an output's name while it's being written, until it's renamed into place
+pub const TEMP_FILE_SUFFIX: &str = ".bpm-tmp";
+
+fn get_temp_path(dest: &PathBuf) -> PathBuf {
+    let mut name = dest.file_name().unwrap_or_default().to_os_string();
+    name.push(TEMP_FILE_SUFFIX);
+    dest.with_file_name(name)
 }
 
 pub fn get_human_duration(duration: Duration) -> String {
     format_duration(duration).to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(source: &str, dest: &str) -> (PathBuf, PathBuf) {
+        (PathBuf::from(source), PathBuf::from(dest))
+    }
+
+    #[test]
+    fn finds_case_collisions() {
+        let mut folded_dests = HashMap::new();
+        let (upper, upper_dest) = paths("src/ui/Logo.png", "out/ui/Logo.png");
+        let (lower, lower_dest) = paths("src/ui/logo.png", "out/ui/logo.png");
+        assert_eq!(
+            check_case_collision(&mut folded_dests, &upper, &upper_dest),
+            None
+        );
+        assert_eq!(
+            check_case_collision(&mut folded_dests, &lower, &lower_dest),
+            Some(upper.clone())
+        );
+        // the source that got there first keeps its output
+        assert_eq!(
+            check_case_collision(&mut folded_dests, &upper, &upper_dest),
+            None
+        );
+
+        let (other, other_dest) = paths("src/ui/logo_dark.png", "out/ui/logo_dark.png");
+        assert_eq!(
+            check_case_collision(&mut folded_dests, &other, &other_dest),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_sources_sharing_the_exact_dest_for_case_collisions() {
+        // sequence frames all map to the combined output
+        let mut folded_dests = HashMap::new();
+        let (first, dest) = paths("src/walk_00.png", "out/walk.png");
+        let second = PathBuf::from("src/walk_01.png");
+        assert_eq!(check_case_collision(&mut folded_dests, &first, &dest), None);
+        assert_eq!(check_case_collision(&mut folded_dests, &second, &dest), None);
+    }
+
+    #[test]
+    fn finds_sources_mapped_to_one_dest() {
+        let mut dests = HashMap::new();
+        let dest = PathBuf::from("out/logo.png");
+        let first = PathBuf::from("src/a/logo.png");
+        let second = PathBuf::from("src/b/logo.png");
+        assert_eq!(check_output_collision(&mut dests, &first, &dest), None);
+        assert_eq!(
+            check_output_collision(&mut dests, &second, &dest),
+            Some(first.clone())
+        );
+        assert_eq!(dests[&dest], first);
+
+        // dests that only differ by case are left to the case collision check
+        let upper = PathBuf::from("src/a/Logo.png");
+        assert_eq!(
+            check_output_collision(&mut dests, &upper, &PathBuf::from("out/Logo.png")),
+            None
+        );
+    }
+
+    #[test]
+    fn allows_sequence_frames_to_share_a_dest() {
+        let mut dests = HashMap::new();
+        let dest = PathBuf::from("out/walk.png");
+        for frame in ["src/walk_00.png", "src/walk_01.png", "src/walk_02.png"] {
+            assert_eq!(
+                check_output_collision(&mut dests, &PathBuf::from(frame), &dest),
+                None
+            );
+        }
+        // a frame of another sequence in another directory still collides
+        let other = PathBuf::from("src/npc/walk_00.png");
+        assert!(check_output_collision(&mut dests, &other, &dest).is_some());
+    }
+
+    #[test]
+    fn reports_duplicate_basenames_once() {
+        let config = Config::default();
+        let mut basenames = HashMap::new();
+        let mut scan_state = ScanState::default();
+        let first = config.source_dir.join("a/logo.png");
+        let second = config.source_dir.join("b/logo.png");
+        check_duplicate_basename(&mut basenames, &mut scan_state, &first, &config);
+        assert!(scan_state.reported_duplicates.is_empty());
+        check_duplicate_basename(&mut basenames, &mut scan_state, &second, &config);
+        check_duplicate_basename(&mut basenames, &mut scan_state, &second, &config);
+        assert_eq!(
+            scan_state.reported_duplicates,
+            HashSet::from([second])
+        );
+        // the first source to claim the name stays the one others are compared against
+        assert_eq!(basenames[&OsString::from("logo.png")], first);
+
+        // names only differing by case aren't duplicates, the case collision check covers their outputs
+        let upper = config.source_dir.join("c/Logo.png");
+        check_duplicate_basename(&mut basenames, &mut scan_state, &upper, &config);
+        assert!(!scan_state.reported_duplicates.contains(&upper));
+    }
+}
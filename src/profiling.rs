@@ -0,0 +1,79 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+
+use crate::processing::get_human_duration;
+
+/// The major phases of a processing run, in the order they're reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingStage {
+    Scan,
+    Import,
+    Export,
+    Write,
+}
+
+impl ProcessingStage {
+    pub const ALL: [ProcessingStage; 4] = [
+        ProcessingStage::Scan,
+        ProcessingStage::Import,
+        ProcessingStage::Export,
+        ProcessingStage::Write,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessingStage::Scan => "scan",
+            ProcessingStage::Import => "import",
+            ProcessingStage::Export => "export",
+            ProcessingStage::Write => "write",
+        }
+    }
+}
+
+/// Accumulates time spent in each stage across the whole run. Reported at exit when `--timings` is passed
+#[derive(Resource, Default, Debug)]
+pub struct StageTimings {
+    totals: [Duration; 4],
+}
+
+impl StageTimings {
+    /// Runs `f` inside a tracing span for the stage and adds its duration to the stage total
+    pub fn time<T>(&mut self, stage: ProcessingStage, f: impl FnOnce() -> T) -> T {
+        let _span = info_span!("processing_stage", stage = stage.name()).entered();
+        let start = Instant::now();
+        let result = f();
+        self.totals[stage as usize] += start.elapsed();
+        result
+    }
+
+    pub fn total(&self, stage: ProcessingStage) -> Duration {
+        self.totals[stage as usize]
+    }
+
+    /// Adds time measured elsewhere, like inside a task on another thread
+    pub fn add(&mut self, stage: ProcessingStage, duration: Duration) {
+        self.totals[stage as usize] += duration;
+    }
+
+    /// Adds the totals a task collected on its own while running on another thread
+    pub fn merge(&mut self, other: &StageTimings) {
+        for (total, other) in self.totals.iter_mut().zip(other.totals) {
+            *total += other;
+        }
+    }
+
+    /// A one line breakdown like "scan 120ms, import 3s 200ms, export 1s 100ms, write 400ms"
+    pub fn report(&self) -> String {
+        ProcessingStage::ALL
+            .iter()
+            .map(|stage| {
+                let total = self.totals[*stage as usize];
+                // drop sub-millisecond noise so the report stays readable
+                let total = Duration::from_millis(total.as_millis() as u64);
+                format!("{} {}", stage.name(), get_human_duration(total))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
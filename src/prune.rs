@@ -0,0 +1,150 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    archive::{remove_orphaned_extractions, ProcessingArchive},
+    config::Config,
+    environment::ProcessingEnvironment,
+    errors::ERRORS_FILE_NAME,
+    font::ProcessingFont,
+    manifest::{Manifest, GITATTRIBUTES_FILE_NAME, MANIFEST_FILE_NAME, MANIFEST_LINES_FILE_NAME},
+    mesh::{ProcessingMesh, DRACO_TEMP_PREFIX},
+    preload::PRELOAD_FILE_NAME,
+    processing::{ProcessingType, TEMP_FILE_SUFFIX},
+    raw::ProcessingRaw,
+    shader::ProcessingShader,
+    status::STATUS_FILE_NAME,
+    texture::ProcessingTexture,
+};
+
+/// Files written next to an output rather than by its processor: loader `.meta` files, texture sequence layouts and
+/// precompressed copies. They belong to whichever processor owns the output they're named after
+const GENERATED_SUFFIXES: &[&str] = &[".meta", ".sequence.json", ".gz", ".br"];
+
+/// Files bpm writes to the root of the output directory that don't come from any source
+const BPM_FILE_NAMES: &[&str] = &[
+    MANIFEST_FILE_NAME,
+    MANIFEST_LINES_FILE_NAME,
+    ERRORS_FILE_NAME,
+    STATUS_FILE_NAME,
+    PRELOAD_FILE_NAME,
+    GITATTRIBUTES_FILE_NAME,
+];
+
+/// Deletes outputs no registered processor claims, which are left behind when sources get deleted or renamed.
+/// Returns the orphaned outputs, which are only listed when `dry_run` is set
+pub fn prune_orphaned_outputs(config: &Config, dry_run: bool) -> Result<Vec<PathBuf>, String> {
+    let mut manifest = Manifest::load(config);
+    // contents of deleted archives stay sources until their extraction is gone, which only happens on a real run
+    let extractions = if dry_run {
+        vec![]
+    } else {
+        remove_orphaned_extractions(&manifest, config)
+    };
+    let bpm_files = get_bpm_files(config);
+    let mut orphans = vec![];
+    for entry in WalkDir::new(&config.output_dir).min_depth(1) {
+        let entry = entry.map_err(|err| format!("Failed to read output directory: {}", err))?;
+        if entry.file_type().is_dir()
+            || bpm_files.contains(entry.path())
+            || is_working_file(entry.path())
+        {
+            continue;
+        }
+        if !is_claimed(&get_owning_output(entry.path()), config) {
+            orphans.push(entry.into_path());
+        }
+    }
+    if dry_run || (orphans.is_empty() && extractions.is_empty()) {
+        return Ok(orphans);
+    }
+    for orphan in &orphans {
+        fs::remove_file(orphan)
+            .map_err(|err| format!("Failed to remove {}: {}", orphan.display(), err))?;
+    }
+    remove_empty_dirs(&config.output_dir);
+
+    manifest.entries.retain(|_, entry| {
+        !orphans.contains(&entry.dest) && !extractions.contains(&entry.dest)
+    });
+    manifest.save(config);
+    Ok(orphans)
+}
+
+/// Asks each enabled processing type whether it wrote the output
+fn is_claimed(output: &Path, config: &Config) -> bool {
+    fn owns<T: ProcessingType>(output: &Path, config: &Config) -> bool {
+        T::is_enabled(config) && T::owns_output(output, config)
+    }
+    owns::<ProcessingRaw>(output, config)
+        || owns::<ProcessingTexture>(output, config)
+        || owns::<ProcessingMesh>(output, config)
+        || owns::<ProcessingShader>(output, config)
+        || owns::<ProcessingArchive>(output, config)
+        || owns::<ProcessingEnvironment>(output, config)
+        || owns::<ProcessingFont>(output, config)
+}
+
+/// `foo.ktx2.meta` => `foo.ktx2`
+fn get_owning_output(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    GENERATED_SUFFIXES
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .filter(|stripped| !stripped.is_empty())
+        .map(|stripped| path.with_file_name(stripped))
+        .unwrap_or_else(|| path.to_path_buf())
+}
+
+/// bpm's own files in the output directory, like the manifest, the preload list and the `.gitattributes` marking the
+/// manifest for union merges, plus the metrics CSV when it's configured to live in there
+fn get_bpm_files(config: &Config) -> HashSet<PathBuf> {
+    let mut files = BPM_FILE_NAMES
+        .iter()
+        .map(|name| config.output_dir.join(name))
+        .collect::<HashSet<_>>();
+    files.extend(get_metrics_path(config));
+    files
+}
+
+/// `metrics_csv` as a path under the output directory, the same way the walked entries are, if it's in there
+fn get_metrics_path(config: &Config) -> Option<PathBuf> {
+    let path = config.metrics_csv.as_ref()?;
+    if path.starts_with(&config.output_dir) {
+        return Some(path.clone());
+    }
+    // the paths can still point at the same place when only one of them is absolute
+    let output_dir = config.output_dir.canonicalize().ok()?;
+    let relative = path.canonicalize().ok()?.strip_prefix(output_dir).ok()?.to_path_buf();
+    Some(config.output_dir.join(relative))
+}
+
+/// Temp files from writes and decodes that are still running, or were interrupted
+fn is_working_file(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.ends_with(TEMP_FILE_SUFFIX) || name.starts_with(DRACO_TEMP_PREFIX))
+}
+
+fn remove_empty_dirs(root: &Path) {
+    // deepest first, so parents emptied by removing their children get removed too
+    for entry in WalkDir::new(root)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .flatten()
+    {
+        if entry.file_type().is_dir() {
+            // only succeeds for empty directories
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+}
@@ -3,9 +3,16 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use bevy::prelude::*;
+use bevy::{prelude::*, tasks::IoTaskPool};
 
-use crate::processing::{FileQueuedForProcessing, ProcessingType};
+use crate::{
+    config::{Config, RawConfigs},
+    processing::{
+        copy_output, get_processing_kind, is_output_of, FileProcessed, FileQueuedForProcessing,
+        ProcessingKind, ProcessingTask, ProcessingType,
+    },
+    profiling::{ProcessingStage, StageTimings},
+};
 
 #[derive(Component)]
 pub struct FileRaw;
@@ -19,33 +26,119 @@ impl ProcessingType for ProcessingRaw {
         FileRaw
     }
 
-    fn get_destination(source: &PathBuf) -> Option<PathBuf> {
-        let base = source.strip_prefix(Path::new("assets-dev")).ok()?;
-        Some(Path::new("assets").join(&base))
+    fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf> {
+        let mut dest_path = config.get_output_path(source)?;
+        if let Some(ext) = config.get_output_extension(ProcessingKind::Raw) {
+            dest_path.set_extension(ext);
+        }
+        config.apply_output_template(ProcessingKind::Raw, source, dest_path, |_| None)
     }
 
-    fn matches(ext: &String, config: &Res<crate::config::Config>) -> bool {
+    fn matches(ext: &String, config: &Config) -> bool {
         let valid_ext = config.extensions.raw.clone();
-        valid_ext.contains(&ext)
+        valid_ext.contains(&ext) || config.is_mapped_extension(ProcessingKind::Raw, ext)
+    }
+
+    fn is_enabled(config: &Config) -> bool {
+        config.raw.enabled
+    }
+
+    fn owns_output(path: &Path, config: &Config) -> bool {
+        // also claims copies of unknown extensions made under `unknown_extension_policy = "Copy"`
+        is_output_of(path, config, |source, config| {
+            (get_processing_kind(source, config) == Some(ProcessingKind::Raw))
+                .then(|| Self::get_destination(source, config))
+                .flatten()
+                .into_iter()
+                .collect()
+        })
     }
 
     fn system(
         query: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
-        _: Res<crate::config::Config>, // config needed for other processing types. Not here
+        config: Res<crate::config::Config>,
         mut commands: Commands,
+        _: EventWriter<FileProcessed>,
+        _: ResMut<StageTimings>,
+        _: Query<(&FileQueuedForProcessing, &ProcessingTask)>,
     ) {
         for (e, entry) in query.iter() {
-            match fs::copy(entry.source.clone(), entry.dest.clone()) {
-                Ok(_) => {
-                    let time = crate::processing::get_human_duration(entry.queue_time.elapsed());
-                    info!("RAW => {} -- {}", entry.dest.display(), time);
-                    commands.entity(e).despawn_recursive()
-                }
-                Err(err) => panic!(
-                    "Failed to copy raw file to assets dir. File data {:#?}. Error: {}",
-                    entry, err
-                ),
-            }
+            // copies are pure IO, so they run on the IO pool instead of holding up the frame for everything else
+            let raw_config = entry.get_config(&config).raw.clone();
+            let source = entry.source.clone();
+            let dest = entry.dest.clone();
+            let task = IoTaskPool::get().spawn(async move {
+                let mut timings = StageTimings::default();
+                let result = timings.time(ProcessingStage::Write, || {
+                    copy_raw(&source, &dest, &raw_config)
+                });
+                (result, timings)
+            });
+            commands
+                .entity(e)
+                .remove::<FileRaw>()
+                .insert(ProcessingTask::new(ProcessingKind::Raw, task));
         }
     }
 }
+
+fn copy_raw(source: &PathBuf, dest: &PathBuf, config: &RawConfigs) -> Result<(), String> {
+    copy_output(source, dest).map_err(|err| format!("Failed to copy raw file: {}", err))?;
+    if config.preserve_mtime {
+        if let Err(err) = copy_modified_time(source, dest) {
+            warn!(
+                "Failed to preserve modification time on {}: {}",
+                dest.display(),
+                err
+            );
+        }
+    }
+    if config.preserve_mode {
+        if let Err(err) = copy_mode(source, dest) {
+            warn!(
+                "Failed to preserve permissions on {}: {}",
+                dest.display(),
+                err
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Describes why a raw source is too large to copy, if it is. Checked before queueing, so oversized files are
+/// skipped instead of failing
+pub fn check_size_limit(source: &PathBuf, config: &Config) -> Option<String> {
+    let max = config.raw.max_size_bytes?;
+    if get_processing_kind(source, config) != Some(ProcessingKind::Raw) {
+        return None;
+    }
+    let size = fs::metadata(source).ok()?.len();
+    (size > max).then(|| {
+        format!(
+            "{} bytes is over the raw.max_size_bytes limit of {} bytes",
+            size, max
+        )
+    })
+}
+
+/// `fs::copy` doesn't carry over the modification time on every platform, so this applies it explicitly
+fn copy_modified_time(source: &PathBuf, dest: &PathBuf) -> std::io::Result<()> {
+    let modified = fs::metadata(source)?.modified()?;
+    fs::File::options()
+        .write(true)
+        .open(dest)?
+        .set_modified(modified)
+}
+
+/// Applies the source's permission bits to the destination, so executables stay executable
+#[cfg(unix)]
+fn copy_mode(source: &PathBuf, dest: &PathBuf) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(source)?.permissions().mode();
+    fs::set_permissions(dest, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn copy_mode(_source: &PathBuf, _dest: &PathBuf) -> std::io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,266 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, futures_lite::future::poll_once, IoTaskPool, Task},
+};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{config::Config, processing::write_output};
+
+/// HTTP sources list their files here, one path relative to the source URL per line, since HTTP has no directory
+/// listing of its own
+pub const HTTP_INDEX_FILE_NAME: &str = "bpm-index.txt";
+
+/// Kept in the root of the cache directory with the `ETag` and `Last-Modified` of each cached file, so later syncs
+/// only download files that changed
+pub const HTTP_VALIDATORS_FILE_NAME: &str = ".bpm-remote-validators.json";
+
+/// What a sync changed in the local cache
+#[derive(Debug, Default)]
+pub struct SyncSummary {
+    pub updated: usize,
+    pub removed: usize,
+    /// Files that couldn't be synced, with why. They're tried again on the next sync
+    pub failed: Vec<String>,
+}
+
+/// What the server said about a cached file's version, sent back so unchanged files get a `304 Not Modified`
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+struct Validators {
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+}
+
+/// Where processing reads sources from. Remote sources are mirrored into a local cache directory that the rest of
+/// bpm treats as `source_dir`, so every processor keeps working on plain files
+#[derive(Resource, Debug, Clone)]
+pub enum SourceBackend {
+    Local,
+    /// An `s3://bucket/prefix` location, synced with the AWS CLI so its credentials and config apply as usual
+    S3 {
+        url: String,
+        cache_dir: PathBuf,
+    },
+    /// An `http://` or `https://` location serving a [`HTTP_INDEX_FILE_NAME`]
+    Http {
+        url: String,
+        cache_dir: PathBuf,
+    },
+}
+
+impl SourceBackend {
+    /// Picks the backend from `source_dir`, pointing `source_dir` at the local cache for remote locations
+    pub fn from_config(config: &mut Config) -> Self {
+        let location = config.source_dir.to_string_lossy().to_string();
+        let backend = if location.starts_with("s3://") {
+            SourceBackend::S3 {
+                url: location,
+                cache_dir: config.remote_cache_dir.clone(),
+            }
+        } else if location.starts_with("http://") || location.starts_with("https://") {
+            SourceBackend::Http {
+                url: location.trim_end_matches('/').to_string(),
+                cache_dir: config.remote_cache_dir.clone(),
+            }
+        } else {
+            return SourceBackend::Local;
+        };
+        config.source_dir = config.remote_cache_dir.clone();
+        backend
+    }
+
+    pub fn is_remote(&self) -> bool {
+        !matches!(self, SourceBackend::Local)
+    }
+
+    /// Brings the local cache up to date with the remote location. Unchanged files are left alone so their
+    /// timestamps don't make them look stale, and files no longer at the remote location are removed
+    pub fn sync(&self) -> Result<SyncSummary, String> {
+        match self {
+            SourceBackend::Local => Ok(SyncSummary::default()),
+            SourceBackend::S3 { url, cache_dir } => sync_s3(url, cache_dir),
+            SourceBackend::Http { url, cache_dir } => sync_http(url, cache_dir),
+        }
+    }
+}
+
+fn sync_s3(url: &str, cache_dir: &Path) -> Result<SyncSummary, String> {
+    let output = Command::new("aws")
+        .args(["s3", "sync", "--delete", "--only-show-errors", url])
+        .arg(cache_dir)
+        .output()
+        .map_err(|err| {
+            format!(
+                "Failed to run `aws s3 sync`, is the AWS CLI installed? {}",
+                err
+            )
+        })?;
+    if !output.status.success() {
+        return Err(format!(
+            "`aws s3 sync` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    // the CLI doesn't report what it changed when only showing errors, staleness checks work that out
+    Ok(SyncSummary::default())
+}
+
+fn sync_http(url: &str, cache_dir: &Path) -> Result<SyncSummary, String> {
+    let index = fetch(&format!("{}/{}", url, HTTP_INDEX_FILE_NAME), None)?
+        .ok_or_else(|| format!("Unexpected 304 Not Modified for {}", HTTP_INDEX_FILE_NAME))?;
+    let index = String::from_utf8(index.bytes)
+        .map_err(|err| format!("Failed to read {}: {}", HTTP_INDEX_FILE_NAME, err))?;
+    let validators_path = cache_dir.join(HTTP_VALIDATORS_FILE_NAME);
+    let mut validators = fs::read_to_string(&validators_path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<BTreeMap<String, Validators>>(&text).ok())
+        .unwrap_or_default();
+    let mut summary = SyncSummary::default();
+    let mut listed = HashSet::new();
+    for relative in index.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let relative_path = Path::new(relative);
+        if relative_path
+            .components()
+            .any(|part| !matches!(part, Component::Normal(_)))
+        {
+            warn!(
+                "Ignoring {} in the remote index, it leaves the source directory",
+                relative
+            );
+            continue;
+        }
+        let path = cache_dir.join(relative_path);
+        listed.insert(path.clone());
+        // the validators only describe the cached copy while it's still there
+        let cached = path.is_file().then(|| validators.get(relative)).flatten();
+        match fetch(&format!("{}/{}", url, relative), cached) {
+            Ok(None) => {}
+            Ok(Some(fetched)) => {
+                if !fs::read(&path).is_ok_and(|existing| existing == fetched.bytes) {
+                    if let Err(err) = write_output(&path, &fetched.bytes) {
+                        summary
+                            .failed
+                            .push(format!("Failed to cache {}: {}", relative, err));
+                        continue;
+                    }
+                    summary.updated += 1;
+                }
+                validators.insert(relative.to_string(), fetched.validators);
+            }
+            Err(err) => summary.failed.push(err),
+        }
+    }
+    validators.retain(|relative, _| listed.contains(&cache_dir.join(relative)));
+    summary.removed = remove_unlisted_files(cache_dir, &listed);
+    let text = serde_json::to_string_pretty(&validators)
+        .map_err(|err| format!("Failed to serialize remote validators: {}", err))?;
+    write_output(&validators_path, text)
+        .map_err(|err| format!("Failed to write remote validators: {}", err))?;
+    Ok(summary)
+}
+
+/// Deletes cached files the index no longer lists, mirroring what `aws s3 sync --delete` does for S3 sources.
+/// Returns how many were removed
+fn remove_unlisted_files(cache_dir: &Path, listed: &HashSet<PathBuf>) -> usize {
+    let validators_path = cache_dir.join(HTTP_VALIDATORS_FILE_NAME);
+    let mut removed = 0;
+    for entry in WalkDir::new(cache_dir)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .flatten()
+    {
+        let path = entry.path();
+        if entry.file_type().is_dir() {
+            // only succeeds once the directory's files are all gone
+            let _ = fs::remove_dir(path);
+        } else if path != validators_path
+            && !listed.contains(path)
+            && fs::remove_file(path).is_ok()
+        {
+            removed += 1;
+        }
+    }
+    removed
+}
+
+struct Fetched {
+    bytes: Vec<u8>,
+    validators: Validators,
+}
+
+/// Downloads the file, or `None` when the server says it hasn't changed since `cached` was fetched
+fn fetch(url: &str, cached: Option<&Validators>) -> Result<Option<Fetched>, String> {
+    let mut request = ureq::get(url);
+    if let Some(etag) = cached.and_then(|cached| cached.etag.as_ref()) {
+        request = request.set("If-None-Match", etag);
+    }
+    if let Some(modified) = cached.and_then(|cached| cached.last_modified.as_ref()) {
+        request = request.set("If-Modified-Since", modified);
+    }
+    let response = request
+        .call()
+        .map_err(|err| format!("Failed to fetch {}: {}", url, err))?;
+    if response.status() == 304 {
+        return Ok(None);
+    }
+    let validators = Validators {
+        etag: response.header("ETag").map(str::to_string),
+        last_modified: response.header("Last-Modified").map(str::to_string),
+    };
+    let mut bytes = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut bytes)
+        .map_err(|err| format!("Failed to fetch {}: {}", url, err))?;
+    Ok(Some(Fetched { bytes, validators }))
+}
+
+/// Re-syncs remote sources in the background every `remote_sync_seconds` while watching, so remote changes get
+/// picked up by later scans
+pub fn sync_remote_source(
+    backend: Res<SourceBackend>,
+    config: Res<Config>,
+    time: Res<Time>,
+    mut timer: Local<Option<Timer>>,
+    mut task: Local<Option<Task<Result<SyncSummary, String>>>>,
+) {
+    if let Some(running) = task.as_mut() {
+        let Some(result) = block_on(poll_once(running)) else {
+            return;
+        };
+        *task = None;
+        match result {
+            Ok(summary) => {
+                if summary.updated > 0 || summary.removed > 0 {
+                    debug!(
+                        "Synced {} remote source files, removed {}",
+                        summary.updated, summary.removed
+                    );
+                }
+                for err in summary.failed {
+                    warn!("{}", err);
+                }
+            }
+            Err(err) => error!("Failed to sync remote source: {}", err),
+        }
+    }
+    let timer = timer.get_or_insert_with(|| {
+        Timer::from_seconds(config.remote_sync_seconds as f32, TimerMode::Repeating)
+    });
+    timer.tick(time.delta());
+    if timer.just_finished() {
+        let backend = backend.clone();
+        *task = Some(IoTaskPool::get().spawn(async move { backend.sync() }));
+    }
+}
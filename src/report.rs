@@ -0,0 +1,94 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+
+use crate::processing::{get_human_duration, FileProcessed, ProcessingKind};
+
+/// A summary of everything a run did, collected from `FileProcessed` events and the scans, so callers can inspect
+/// the outcome directly instead of reading logs
+#[derive(Resource, Debug)]
+pub struct ProcessingReport {
+    /// Sources that processed successfully
+    pub processed: Vec<PathBuf>,
+    /// Sources left alone on purpose, like protected outputs or unreadable media under the skip policy
+    pub skipped: BTreeSet<PathBuf>,
+    /// Sources whose most recent attempt failed, each listed once with its latest error
+    pub failed: Vec<(PathBuf, ProcessingError)>,
+    /// Total time files of each kind took from being queued to finishing, successful or not
+    pub kind_durations: HashMap<ProcessingKind, Duration>,
+    pub duration: Duration,
+    start: Instant,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessingError {
+    pub kind: ProcessingKind,
+    pub message: String,
+}
+
+impl fmt::Display for ProcessingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.message)
+    }
+}
+
+impl Default for ProcessingReport {
+    fn default() -> Self {
+        Self {
+            processed: vec![],
+            skipped: BTreeSet::new(),
+            failed: vec![],
+            kind_durations: HashMap::new(),
+            duration: Duration::ZERO,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl ProcessingReport {
+    /// One line overview for the end of a run
+    pub fn summary(&self) -> String {
+        format!(
+            "Processed {} files, skipped {}, failed {} in {}",
+            self.processed.len(),
+            self.skipped.len(),
+            self.failed.len(),
+            get_human_duration(self.duration)
+        )
+    }
+}
+
+pub fn record_report(mut events: EventReader<FileProcessed>, mut report: ResMut<ProcessingReport>) {
+    for event in events.read() {
+        match &event.result {
+            Ok(_) => {
+                report.processed.push(event.source.clone());
+                report.failed.retain(|(source, _)| *source != event.source);
+            }
+            Err(err) => {
+                let error = ProcessingError {
+                    kind: event.kind,
+                    message: err.clone(),
+                };
+                // rescans in watch mode retry a failing file every time, which should still only be listed once
+                match report
+                    .failed
+                    .iter_mut()
+                    .find(|(source, _)| *source == event.source)
+                {
+                    Some(entry) => entry.1 = error,
+                    None => report.failed.push((event.source.clone(), error)),
+                }
+            }
+        }
+        *report.kind_durations.entry(event.kind).or_default() += event.duration;
+        // a file skipped on one scan and processed on a later one isn't skipped anymore
+        report.skipped.remove(&event.source);
+    }
+    report.duration = report.start.elapsed();
+}
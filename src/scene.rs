@@ -0,0 +1,112 @@
+use std::path::PathBuf;
+
+use bevy::{prelude::*, reflect::TypeRegistry, scene::DynamicScene};
+use gltf_kun::graph::{
+    gltf::{node::Node, GltfDocument},
+    Graph, GraphNodeWeights,
+};
+
+use crate::{config::Config, mesh::import_gltf, preload::get_asset_path, processing::write_output};
+
+/// `foo.glb` => `foo.scn.ron`
+pub fn get_scene_path(dest: &PathBuf) -> PathBuf {
+    dest.with_extension("scn.ron")
+}
+
+/// The labeled asset paths of a node's mesh primitives, written into scenes instead of handles since a handle can't be
+/// serialized. Games loading the scene register this type and swap it for `Handle<Mesh>`/`Handle<StandardMaterial>`
+#[derive(Component, Reflect, Default, Debug, Clone, PartialEq)]
+#[reflect(Component)]
+pub struct ScenePrimitives {
+    pub primitives: Vec<ScenePrimitive>,
+}
+
+#[derive(Reflect, Default, Debug, Clone, PartialEq)]
+pub struct ScenePrimitive {
+    /// Like `foo.glb#Mesh0/Primitive0`
+    pub mesh: String,
+    /// Like `foo.glb#Material0`, or none for primitives using the default material
+    pub material: Option<String>,
+}
+
+/// Writes the node hierarchy of the processed mesh as a Bevy scene. Each entity gets its `Name` and `Transform`, and
+/// entities with a mesh get `ScenePrimitives` pointing at the labeled assets of its primitives and materials
+pub fn write_scene(dest: &PathBuf, config: &Config) -> Result<(), String> {
+    let (graph, doc) = import_gltf(dest, config.meshes.extension_set)?;
+    let asset_path = get_asset_path(
+        dest.strip_prefix(&config.output_dir)
+            .map_err(|_| "Mesh output is outside the output directory".to_string())?,
+    );
+
+    let mut registry = TypeRegistry::default();
+    registry.register::<Name>();
+    registry.register::<Transform>();
+    registry.register::<Parent>();
+    registry.register::<Children>();
+    registry.register::<ScenePrimitives>();
+    let mut world = World::new();
+    world.insert_resource(AppTypeRegistry::default());
+    *world.resource::<AppTypeRegistry>().write() = registry;
+
+    for scene in doc.scenes(&graph) {
+        for node in scene.nodes(&graph) {
+            spawn_node(&mut world, &graph, &doc, &node, &asset_path, None);
+        }
+    }
+
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let text = DynamicScene::from_world(&world)
+        .serialize(&registry.read())
+        .map_err(|err| format!("Failed to serialize scene: {}", err))?;
+    write_output(&get_scene_path(dest), text)
+        .map_err(|err| format!("Failed to write scene: {}", err))
+}
+
+fn spawn_node(
+    world: &mut World,
+    graph: &Graph,
+    doc: &GltfDocument,
+    node: &Node,
+    asset_path: &str,
+    parent: Option<Entity>,
+) {
+    let weight = node.get(graph);
+    let transform = Transform {
+        translation: Vec3::from_array(weight.translation.to_array()),
+        rotation: Quat::from_array(weight.rotation.to_array()),
+        scale: Vec3::from_array(weight.scale.to_array()),
+    };
+    let name = Name::new(weight.name.clone().unwrap_or_default());
+    let mut entity = world.spawn((name, transform));
+    if let Some(mesh) = node.mesh(graph) {
+        let mesh_index = doc.meshes(graph).iter().position(|other| other.0 == mesh.0);
+        let materials = doc.materials(graph);
+        let primitives = mesh
+            .primitives(graph)
+            .iter()
+            .enumerate()
+            .map(|(primitive_index, primitive)| {
+                let material = primitive
+                    .material(graph)
+                    .and_then(|material| materials.iter().position(|other| other.0 == material.0));
+                ScenePrimitive {
+                    mesh: format!(
+                        "{}#Mesh{}/Primitive{}",
+                        asset_path,
+                        mesh_index.unwrap_or_default(),
+                        primitive_index
+                    ),
+                    material: material.map(|index| format!("{}#Material{}", asset_path, index)),
+                }
+            })
+            .collect();
+        entity.insert(ScenePrimitives { primitives });
+    }
+    let id = entity.id();
+    if let Some(parent) = parent {
+        world.entity_mut(parent).add_child(id);
+    }
+    for child in node.children(graph) {
+        spawn_node(world, graph, doc, &child, asset_path, Some(id));
+    }
+}
@@ -0,0 +1,180 @@
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+use naga::valid::{Capabilities, ValidationFlags, Validator};
+use naga_oil::compose::{
+    get_preprocessor_data, ComposableModuleDescriptor, Composer, NagaModuleDescriptor,
+    ShaderLanguage, ShaderType,
+};
+
+use crate::{
+    config::Config,
+    processing::{
+        copy_output, get_extension, walk_source_files, FileProcessed, FileQueuedForProcessing,
+        ProcessingKind, ProcessingTask, ProcessingType,
+    },
+    profiling::{ProcessingStage, StageTimings},
+};
+
+#[derive(Component)]
+pub struct FileShader;
+
+/// Copies shader sources to the output only once they pass validation, so a broken shader fails the build instead of
+/// failing at runtime
+pub struct ProcessingShader;
+
+impl ProcessingType for ProcessingShader {
+    type Comp = FileShader;
+
+    fn get_component() -> Self::Comp {
+        FileShader
+    }
+
+    fn matches(ext: &String, config: &Config) -> bool {
+        config.extensions.shader.contains(ext)
+            || config.is_mapped_extension(ProcessingKind::Shader, ext)
+    }
+
+    fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf> {
+        let dest_path = config.get_output_path(source)?;
+        config.apply_output_template(ProcessingKind::Shader, source, dest_path, |_| None)
+    }
+
+    fn is_enabled(config: &Config) -> bool {
+        config.shaders.enabled
+    }
+
+    fn system(
+        query: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
+        config: Res<Config>,
+        mut commands: Commands,
+        _: EventWriter<FileProcessed>,
+        _: ResMut<StageTimings>,
+        _: Query<(&FileQueuedForProcessing, &ProcessingTask)>,
+    ) {
+        for (e, entry) in query.iter() {
+            let file_config = entry.get_config(&config).clone();
+            let source = entry.source.clone();
+            let dest = entry.dest.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                let mut timings = StageTimings::default();
+                let result = process_shader(&source, &dest, &file_config, &mut timings);
+                (result, timings)
+            });
+            commands
+                .entity(e)
+                .remove::<FileShader>()
+                .insert(ProcessingTask::new(ProcessingKind::Shader, task));
+        }
+    }
+}
+
+fn process_shader(
+    source: &PathBuf,
+    dest: &PathBuf,
+    config: &Config,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    timings.time(ProcessingStage::Import, || validate_wgsl(source, config))?;
+    timings.time(ProcessingStage::Write, || {
+        copy_output(source, dest)
+            .map(|_| ())
+            .map_err(|err| format!("Failed to copy shader: {}", err))
+    })
+}
+
+/// Preprocesses the shader the way Bevy does at load time, resolving `#ifdef`s and `#import`s of modules defined by
+/// other shaders in the source tree, then validates the result. Returns naga's report (with line and column info) on
+/// failure. Shaders importing modules only Bevy itself provides, like `bevy_pbr::forward_io`, are checked as far as
+/// their own module definition goes
+fn validate_wgsl(source: &PathBuf, config: &Config) -> Result<(), String> {
+    let text =
+        fs::read_to_string(source).map_err(|err| format!("Failed to read shader: {}", err))?;
+    let file_path = source.to_string_lossy().to_string();
+    let mut composer = Composer::default().with_capabilities(Capabilities::all());
+    let modules = get_import_modules(source, config);
+    add_import_modules(&mut composer, &modules);
+
+    let (name, imports, _) = get_preprocessor_data(&text);
+    let missing = imports
+        .iter()
+        .map(|import| &import.definition.import)
+        .find(|import| !composer.contains_module(&get_module_name(import, &composer)));
+    if let Some(missing) = missing {
+        debug!(
+            "Only validating {} up to its imports, {} isn't defined in the source tree",
+            source.display(),
+            missing
+        );
+        return Ok(());
+    }
+    if name.is_some() {
+        // modules are validated as they're added, which also checks them against their own imports
+        return composer
+            .add_composable_module(ComposableModuleDescriptor {
+                source: &text,
+                file_path: &file_path,
+                language: ShaderLanguage::Wgsl,
+                ..Default::default()
+            })
+            .map(|_| ())
+            .map_err(|err| err.emit_to_string(&composer));
+    }
+    let module = composer
+        .make_naga_module(NagaModuleDescriptor {
+            source: &text,
+            file_path: &file_path,
+            shader_type: ShaderType::Wgsl,
+            ..Default::default()
+        })
+        .map_err(|err| err.emit_to_string(&composer))?;
+    Validator::new(ValidationFlags::all(), Capabilities::all())
+        .validate(&module)
+        .map_err(|err| err.emit_to_string(&text))?;
+    Ok(())
+}
+
+/// Every other shader in the source tree declaring a `#define_import_path`, keyed by that path
+fn get_import_modules(source: &PathBuf, config: &Config) -> HashMap<String, (PathBuf, String)> {
+    walk_source_files(config)
+        .filter(|(entry, _)| entry.file_type().is_file())
+        .map(|(_, path)| path)
+        .filter(|path| path != source)
+        .filter(|path| get_extension(path).is_some_and(|ext| ProcessingShader::matches(&ext, config)))
+        .filter_map(|path| {
+            let text = fs::read_to_string(&path).ok()?;
+            let (name, _, _) = get_preprocessor_data(&text);
+            Some((name?, (path, text)))
+        })
+        .collect()
+}
+
+/// Adds the modules to the composer. A module has to be added after the modules it imports, so this keeps going
+/// round until nothing more can be added. Modules that never get added are reported by whatever imports them
+fn add_import_modules(composer: &mut Composer, modules: &HashMap<String, (PathBuf, String)>) {
+    let mut pending = modules.values().collect::<Vec<_>>();
+    loop {
+        let before = pending.len();
+        pending.retain(|(path, text)| {
+            composer
+                .add_composable_module(ComposableModuleDescriptor {
+                    source: text,
+                    file_path: &path.to_string_lossy(),
+                    language: ShaderLanguage::Wgsl,
+                    ..Default::default()
+                })
+                .is_err()
+        });
+        if pending.is_empty() || pending.len() == before {
+            return;
+        }
+    }
+}
+
+/// Imports can name a module (`my_lib::lighting`) or an item in one (`my_lib::lighting::shade`)
+fn get_module_name(import: &str, composer: &Composer) -> String {
+    match import.rsplit_once("::") {
+        Some((module, _)) if !composer.contains_module(import) => module.to_string(),
+        _ => import.to_string(),
+    }
+}
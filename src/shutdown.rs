@@ -0,0 +1,56 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use bevy::{app::AppExit, prelude::*};
+
+use crate::{config::Config, manifest::Manifest, processing::FileQueuedForProcessing, status};
+
+/// Set by the Ctrl-C handler. Once set no new files get queued, and the app exits after in-flight files finish
+#[derive(Resource, Clone, Default)]
+pub struct ShutdownRequested(Arc<AtomicBool>);
+
+impl ShutdownRequested {
+    /// Installs the Ctrl-C handler. A second Ctrl-C exits immediately for when something is stuck
+    pub fn install() -> Self {
+        let flag = Self::default();
+        let handler_flag = flag.0.clone();
+        let result = ctrlc::set_handler(move || {
+            if handler_flag.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+        });
+        if let Err(err) = result {
+            eprintln!("Failed to install Ctrl-C handler: {}", err);
+        }
+        flag
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+pub fn handle_shutdown(
+    shutdown: Res<ShutdownRequested>,
+    queued: Query<(), With<FileQueuedForProcessing>>,
+    manifest: Res<Manifest>,
+    config: Res<Config>,
+    mut exit: EventWriter<AppExit>,
+    mut announced: Local<bool>,
+) {
+    if !shutdown.is_requested() {
+        return;
+    }
+    if !*announced {
+        info!("Shutting down once in-flight files finish processing. Press Ctrl-C again to force quit");
+        *announced = true;
+    }
+    if !queued.is_empty() {
+        return;
+    }
+    manifest.save(&config);
+    status::remove_status_file(&config);
+    exit.send(AppExit::Success);
+}
@@ -0,0 +1,118 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use walkdir::WalkDir;
+
+use crate::{config::Config, errors::ErrorSummary, manifest::Manifest};
+
+/// A `--staged` run, which writes into a copy of the output directory and only replaces the real one once every
+/// file processed successfully
+pub struct StagedOutput {
+    output_dir: PathBuf,
+    staging_dir: PathBuf,
+}
+
+impl StagedOutput {
+    /// Copies the current outputs into a staging directory next to the output directory and points the config at it,
+    /// so up to date outputs are kept and only stale files get processed
+    pub fn begin(config: &mut Config) -> Result<Self, String> {
+        let output_dir = config.output_dir.clone();
+        let staging_dir = get_sibling_dir(&output_dir, "bpm-staging");
+        // a previous staged run that was killed can leave its staging directory behind
+        remove_dir_if_exists(&staging_dir)
+            .map_err(|err| format!("Failed to clear old staging directory: {}", err))?;
+        copy_dir(&output_dir, &staging_dir)
+            .map_err(|err| format!("Failed to create staging directory: {}", err))?;
+        config.output_dir = staging_dir.clone();
+        Ok(Self {
+            output_dir,
+            staging_dir,
+        })
+    }
+
+    /// Swaps the staging directory into place if the run succeeded. Otherwise discards it, leaving the outputs in the
+    /// output directory as they were but carrying over what failed so `bpm retry-failed` can pick it up
+    pub fn finish(self, config: &Config, succeeded: bool) -> Result<(), String> {
+        if !succeeded {
+            self.keep_failures(config);
+            return remove_dir_if_exists(&self.staging_dir)
+                .map_err(|err| format!("Failed to remove staging directory: {}", err));
+        }
+        // outputs were recorded under the staging directory, so point them back at the real one
+        let mut manifest = Manifest::load(config);
+        for entry in manifest.entries.values_mut() {
+            if let Ok(relative) = entry.dest.strip_prefix(&self.staging_dir) {
+                entry.dest = self.output_dir.join(relative);
+            }
+        }
+        manifest.save(config);
+
+        let previous_dir = get_sibling_dir(&self.output_dir, "bpm-previous");
+        remove_dir_if_exists(&previous_dir)
+            .map_err(|err| format!("Failed to clear old output backup: {}", err))?;
+        if self.output_dir.exists() {
+            fs::rename(&self.output_dir, &previous_dir)
+                .map_err(|err| format!("Failed to move aside the output directory: {}", err))?;
+        }
+        if let Err(err) = fs::rename(&self.staging_dir, &self.output_dir) {
+            // put the old outputs back rather than leaving no output directory at all
+            let _ = fs::rename(&previous_dir, &self.output_dir);
+            return Err(format!("Failed to swap in the staged outputs: {}", err));
+        }
+        remove_dir_if_exists(&previous_dir)
+            .map_err(|err| format!("Failed to remove the previous outputs: {}", err))
+    }
+
+    /// Copies the error summary and merges the manifest's failed files from the staging directory into the output
+    /// directory
+    fn keep_failures(&self, config: &Config) {
+        let mut output_config = config.clone();
+        output_config.output_dir = self.output_dir.clone();
+        ErrorSummary::load(config).save(&output_config);
+        let failed = Manifest::load(config).failed;
+        if failed.is_empty() {
+            return;
+        }
+        let mut manifest = Manifest::load(&output_config);
+        manifest.failed.extend(failed);
+        manifest.save(&output_config);
+    }
+}
+
+/// `assets` becomes `assets.<suffix>`, next to it so the final rename stays on the same filesystem
+fn get_sibling_dir(dir: &Path, suffix: &str) -> PathBuf {
+    let mut name = dir.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    dir.with_file_name(name)
+}
+
+fn copy_dir(source: &Path, dest: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dest)?;
+    if !source.exists() {
+        return Ok(());
+    }
+    for entry in WalkDir::new(source).min_depth(1) {
+        let entry = entry?;
+        let Ok(relative) = entry.path().strip_prefix(source) else {
+            continue;
+        };
+        let target = dest.join(relative);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)?;
+        } else {
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn remove_dir_if_exists(dir: &Path) -> std::io::Result<()> {
+    match fs::remove_dir_all(dir) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    pause::ProcessingPaused,
+    processing::{self, FileQueuedForProcessing, ProcessingKind, ProcessingTask},
+};
+
+/// Written into the output directory while watching, so editors and `bpm status` can see what a running bpm is doing
+pub const STATUS_FILE_NAME: &str = ".bpm-status.json";
+
+/// How many missed updates before a status file is treated as left behind by a bpm that's no longer running
+const STALE_UPDATES: f64 = 10.0;
+
+/// What a running bpm is currently working on. Kept up to date while watching, both as a resource for embedding apps
+/// and in [`STATUS_FILE_NAME`] for external tools
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ProcessingStatus {
+    pub pid: u32,
+    /// Seconds since the unix epoch when this was last updated
+    pub updated_at: u64,
+    pub paused: bool,
+    pub files: Vec<QueuedFileStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueuedFileStatus {
+    pub source: PathBuf,
+    pub dest: PathBuf,
+    /// Set once a processor has handed the file to a worker, unset while it's waiting for one
+    pub kind: Option<ProcessingKind>,
+    pub queued_seconds: f64,
+}
+
+impl ProcessingStatus {
+    /// Reads the status file a running bpm writes, failing when none is running for the config
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let text = fs::read_to_string(get_status_path(config))
+            .map_err(|_| "bpm isn't watching this output directory".to_string())?;
+        let status: Self = serde_json::from_str(&text)
+            .map_err(|err| format!("Failed to read {}: {}", STATUS_FILE_NAME, err))?;
+        let max_age = (config.file_watching_rate_seconds * STALE_UPDATES).ceil() as u64;
+        if get_unix_seconds().saturating_sub(status.updated_at) > max_age {
+            return Err(format!(
+                "bpm isn't watching this output directory, the last status is from process {}",
+                status.pid
+            ));
+        }
+        Ok(status)
+    }
+}
+
+fn get_status_path(config: &Config) -> PathBuf {
+    config.output_dir.join(STATUS_FILE_NAME)
+}
+
+fn get_unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+/// Refreshes [`ProcessingStatus`] and its status file at the file watching rate
+pub fn update_processing_status(
+    mut status: ResMut<ProcessingStatus>,
+    queued: Query<(&FileQueuedForProcessing, Option<&ProcessingTask>)>,
+    paused: Res<ProcessingPaused>,
+    config: Res<Config>,
+    time: Res<Time>,
+    mut timer: Local<Option<Timer>>,
+) {
+    let timer = timer.get_or_insert_with(|| {
+        Timer::from_seconds(
+            config.file_watching_rate_seconds as f32,
+            TimerMode::Repeating,
+        )
+    });
+    timer.tick(time.delta());
+    if !timer.just_finished() {
+        return;
+    }
+    let mut files = queued
+        .iter()
+        .map(|(fqfp, task)| QueuedFileStatus {
+            source: fqfp.source.clone(),
+            dest: fqfp.dest.clone(),
+            kind: task.map(ProcessingTask::kind),
+            queued_seconds: fqfp.queue_time.elapsed().as_secs_f64(),
+        })
+        .collect::<Vec<_>>();
+    files.sort_by(|a, b| a.source.cmp(&b.source));
+    *status = ProcessingStatus {
+        pid: std::process::id(),
+        updated_at: get_unix_seconds(),
+        paused: paused.0,
+        files,
+    };
+    let result = processing::write_output_with(&get_status_path(&config), |writer| {
+        serde_json::to_writer_pretty(writer, &*status).map_err(std::io::Error::other)
+    });
+    if let Err(err) = result {
+        error!("Failed to write {}: {}", STATUS_FILE_NAME, err);
+    }
+}
+
+/// Removes the status file when watching stops, so tools don't mistake it for a running bpm
+pub fn remove_status_file(config: &Config) {
+    let _ = fs::remove_file(get_status_path(config));
+}
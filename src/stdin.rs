@@ -0,0 +1,134 @@
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, TryRecvError},
+        Mutex,
+    },
+    thread,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    config::Config,
+    processing::{
+        is_bpm_file, queue_file, AssetProcessing, FileQueuedForProcessing, QueueReason,
+        UnprocessedFiles,
+    },
+    raw,
+    report::ProcessingReport,
+    shutdown::ShutdownRequested,
+};
+
+/// An explicit list of source paths to process instead of scanning the source directory, either piped in by
+/// `--stdin-paths` or given up front like the failures `retry-failed` re-queues
+#[derive(Resource)]
+pub struct RequestedPaths(Mutex<Receiver<String>>);
+
+impl RequestedPaths {
+    /// A background thread reads stdin so the app never blocks on input
+    pub fn from_stdin() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Self(Mutex::new(receiver))
+    }
+
+    pub fn from_list(paths: impl IntoIterator<Item = PathBuf>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        for path in paths {
+            let _ = sender.send(path.to_string_lossy().into_owned());
+        }
+        // dropping the sender marks the list as complete
+        Self(Mutex::new(receiver))
+    }
+}
+
+/// Queues each requested path in place of scanning the source directory. Requested paths are queued whether or not
+/// they look stale, since whoever requested them already decided they need processing
+pub fn queue_requested_paths(
+    requested: Res<RequestedPaths>,
+    currently_queued: Query<&FileQueuedForProcessing>,
+    mut commands: Commands,
+    mut unprocessed: ResMut<UnprocessedFiles>,
+    mut report: ResMut<ProcessingReport>,
+    shutdown: Res<ShutdownRequested>,
+    config: Res<Config>,
+) {
+    if shutdown.is_requested() {
+        return;
+    }
+    let Ok(receiver) = requested.0.lock() else {
+        return;
+    };
+    loop {
+        let line = match receiver.try_recv() {
+            Ok(line) => line,
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                // the list is finished, so everything that will ever be queued has been
+                unprocessed.scan_complete = true;
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some(source) = resolve_source_path(line, &config) else {
+            warn!(
+                "Ignoring '{}', it isn't a file in {}",
+                line,
+                config.source_dir.display()
+            );
+            continue;
+        };
+        if currently_queued
+            .iter()
+            .any(|queued| queued.source == source)
+        {
+            continue;
+        }
+        let Some(dest) = AssetProcessing::get_destination(&source, &config) else {
+            continue;
+        };
+        if let Some(err) = raw::check_size_limit(&source, &config) {
+            warn!("Skipping raw file {}: {}", source.display(), err);
+            report.skipped.insert(source);
+            continue;
+        }
+        if let Some(parent) = dest.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        if queue_file(&mut commands, source.clone(), dest, &config) {
+            unprocessed.in_flight += 1;
+            debug!("Queued {}: {}", source.display(), QueueReason::Requested);
+        } else {
+            debug!("Unhandled File: {}", source.display());
+        }
+    }
+}
+
+/// Accepts paths relative to the source directory, paths starting with it, or absolute paths. Anything that resolves
+/// outside the source directory is rejected
+fn resolve_source_path(line: &str, config: &Config) -> Option<PathBuf> {
+    let path = PathBuf::from(line);
+    let path = if path.is_absolute() || path.starts_with(&config.source_dir) {
+        path
+    } else {
+        config.source_dir.join(path)
+    };
+    let source = config
+        .source_dir
+        .join(config.get_source_relative_path(&path)?);
+    (source.is_file() && !is_bpm_file(&source)).then_some(source)
+}
@@ -0,0 +1,57 @@
+use std::env;
+
+use bevy::{log::BoxedLayer, prelude::*};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{trace, Resource as OtelResource};
+
+use crate::config::Config;
+
+/// The standard OpenTelemetry variable, which takes precedence over `telemetry.otlp_endpoint`
+const OTLP_ENDPOINT_ENV: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Where spans get exported to. Inserted before the log plugin builds its subscriber, which is when the exporting layer
+/// gets created
+#[derive(Resource, Debug, Clone)]
+pub struct OtlpEndpoint(pub String);
+
+impl OtlpEndpoint {
+    pub fn from_config(config: &Config) -> Option<Self> {
+        env::var(OTLP_ENDPOINT_ENV)
+            .ok()
+            .filter(|endpoint| !endpoint.is_empty())
+            .or_else(|| config.telemetry.otlp_endpoint.clone())
+            .map(Self)
+    }
+}
+
+/// Exports bpm's tracing spans, like the scan, import, export and write stages of each file, over OTLP/HTTP when an
+/// endpoint is configured. Used as the log plugin's custom layer
+pub fn get_otlp_layer(app: &mut App) -> Option<BoxedLayer> {
+    let endpoint = app.world().get_resource::<OtlpEndpoint>()?.0.clone();
+    let tracer =
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(&endpoint),
+            )
+            .with_trace_config(trace::config().with_resource(OtelResource::new(vec![
+                KeyValue::new("service.name", "bpm"),
+            ])))
+            // exports each span as it closes, so nothing is lost when a oneshot run exits
+            .install_simple();
+    match tracer {
+        Ok(tracer) => Some(Box::new(tracing_opentelemetry::layer().with_tracer(tracer))),
+        Err(err) => {
+            // logging isn't set up yet
+            eprintln!("Failed to export spans to {}: {}", endpoint, err);
+            None
+        }
+    }
+}
+
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
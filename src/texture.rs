@@ -0,0 +1,1085 @@
+use std::{
+    cell::OnceCell,
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    fs,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::SystemTime,
+};
+
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+use image::{
+    imageops::{self, FilterType},
+    ColorType, DynamicImage, GenericImage, ImageFormat, ImageReader, Rgba, RgbaImage,
+};
+use libktx_rs::{sinks::StreamSink, sources::Ktx2CreateInfo, sys, Texture};
+use serde::Serialize;
+
+use crate::{
+    config::{
+        Config, Ktx2Encoding, PowerOfTwoMode, SdfChannel, SdfConfigs, SequenceLayout,
+        TextureConfigs,
+    },
+    media::check_media_file,
+    meta::write_texture_meta,
+    processing::{
+        get_free_workers, get_human_duration, write_output, FileProcessed, FileQueuedForProcessing,
+        ProcessingKind, ProcessingTask, ProcessingType,
+    },
+    profiling::{ProcessingStage, StageTimings},
+};
+
+/// Vulkan formats for the RGBA8 images handed to libktx, which also record whether the data is sRGB
+const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+#[derive(Component)]
+pub struct FileTexture;
+
+pub struct ProcessingTexture;
+
+impl ProcessingType for ProcessingTexture {
+    type Comp = FileTexture;
+
+    fn get_component() -> Self::Comp {
+        FileTexture
+    }
+
+    fn matches(ext: &String, config: &Config) -> bool {
+        config.extensions.texture.contains(ext)
+            || config.is_mapped_extension(ProcessingKind::Texture, ext)
+    }
+
+    fn get_destination(source: &PathBuf, config: &Config) -> Option<PathBuf> {
+        let mut dest_path = config.get_output_path(source)?;
+        if config.textures.combine_sequences {
+            if let Some((name, _)) =
+                get_sequence_frames(source).and_then(|_| get_sequence_frame(source))
+            {
+                // every frame of a sequence maps to the one combined output
+                let mut file_name = OsString::from(name);
+                if let Some(ext) = source.extension() {
+                    file_name.push(".");
+                    file_name.push(ext);
+                }
+                dest_path.set_file_name(file_name);
+            }
+        }
+        if let Some(suffix) = &config.textures.output_suffix {
+            let mut file_name = dest_path.file_stem()?.to_os_string();
+            file_name.push(suffix);
+            if let Some(ext) = dest_path.extension() {
+                file_name.push(".");
+                file_name.push(ext);
+            }
+            dest_path.set_file_name(file_name);
+        }
+        if let Some(ext) = config.get_output_extension(ProcessingKind::Texture) {
+            dest_path.set_extension(ext);
+        }
+        // only reads the header, and only when the template asks for the size
+        let size = OnceCell::new();
+        let get_size = || *size.get_or_init(|| get_output_size(source, &config.textures));
+        config.apply_output_template(
+            ProcessingKind::Texture,
+            source,
+            dest_path,
+            |name| match name {
+                "w" => get_size().map(|(width, _)| width.to_string()),
+                "h" => get_size().map(|(_, height)| height.to_string()),
+                _ => None,
+            },
+        )
+    }
+
+    fn get_outputs(source: &PathBuf, config: &Config) -> Vec<PathBuf> {
+        let Some(dest) = Self::get_destination(source, config) else {
+            return vec![];
+        };
+        if config.textures.combine_sequences && get_sequence_frames(source).is_some() {
+            let info_path = get_sequence_info_path(&dest);
+            return vec![dest, info_path];
+        }
+        vec![dest]
+    }
+
+    fn is_enabled(config: &Config) -> bool {
+        config.textures.enabled
+    }
+
+    fn system(
+        query: Query<(Entity, &FileQueuedForProcessing), With<Self::Comp>>,
+        config: Res<Config>,
+        mut commands: Commands,
+        mut processed: EventWriter<FileProcessed>,
+        _: ResMut<StageTimings>,
+        tasks: Query<(&FileQueuedForProcessing, &ProcessingTask)>,
+    ) {
+        let mut free_workers =
+            get_free_workers(&tasks, ProcessingKind::Texture, config.textures.workers);
+        // outputs being rebuilt right now, so the other frames of a sequence don't rebuild it again
+        let mut building = tasks
+            .iter()
+            .filter(|(_, task)| task.kind() == ProcessingKind::Texture)
+            .map(|(entry, _)| entry.dest.clone())
+            .collect::<HashSet<_>>();
+        let mut entries = query.iter().collect::<Vec<_>>();
+        entries.sort_by_key(|(_, entry)| Reverse(entry.priority));
+        for (e, entry) in entries {
+            let file_config = entry.get_config(&config);
+            let sequence = if file_config.textures.combine_sequences {
+                get_sequence_frames(&entry.source)
+            } else {
+                None
+            };
+            if sequence.is_some() && building.contains(&entry.dest) {
+                info!(
+                    "{} => {} -- {}",
+                    entry.source.display(),
+                    entry.dest.display(),
+                    get_human_duration(entry.queue_time.elapsed())
+                );
+                processed.send(FileProcessed {
+                    source: entry.source.clone(),
+                    dest: entry.dest.clone(),
+                    kind: ProcessingKind::Texture,
+                    result: Ok(()),
+                    duration: entry.queue_time.elapsed(),
+                });
+                commands.entity(e).despawn_recursive();
+                continue;
+            }
+            if free_workers == 0 {
+                // picked up on a later frame once a worker frees up
+                continue;
+            }
+            free_workers -= 1;
+            building.insert(entry.dest.clone());
+            let file_config = file_config.clone();
+            let source = entry.source.clone();
+            let dest = entry.dest.clone();
+            let task = AsyncComputeTaskPool::get().spawn(async move {
+                // groups the stage spans of the file, which run on a pool thread outside any other span
+                let _span = info_span!("process_file", source = %source.display()).entered();
+                let mut timings = StageTimings::default();
+                let result =
+                    process_texture_file(&source, &dest, sequence, &file_config, &mut timings);
+                (result, timings)
+            });
+            commands
+                .entity(e)
+                .remove::<FileTexture>()
+                .insert(ProcessingTask::new(ProcessingKind::Texture, task));
+        }
+    }
+}
+
+/// Processes a texture or a whole sequence along with the verification and `.meta` file the config asks for
+fn process_texture_file(
+    source: &PathBuf,
+    dest: &PathBuf,
+    sequence: Option<Vec<PathBuf>>,
+    config: &Config,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    match sequence {
+        Some(frames) => process_sequence(&frames, dest, &config.textures, timings)?,
+        None => process_texture(source, dest, &config.textures, timings)?,
+    }
+    if config.verify_outputs {
+        verify_texture(dest)?;
+    }
+    if config.generate_meta {
+        write_texture_meta(source, dest, &config.textures)?;
+    }
+    Ok(())
+}
+
+/// Decodes the source image, applies the configured transformations, and writes it to the destination.
+/// The output format is inferred from the destination extension
+fn process_texture(
+    source: &PathBuf,
+    dest: &PathBuf,
+    config: &TextureConfigs,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    let image = timings.time(ProcessingStage::Import, || load_image(source, config))?;
+    let bytes = timings.time(ProcessingStage::Export, || {
+        encode_image(&transform_image(image, source, config), dest, config)
+    })?;
+    timings.time(ProcessingStage::Write, || {
+        write_output(dest, bytes).map_err(|err| format!("Failed to write image: {}", err))
+    })
+}
+
+/// Combines each frame of an image sequence into a single image. The default vertical strip can be reinterpreted as an
+/// array texture (`Image::reinterpret_stacked_2d_as_array`), while `SequenceLayout::Grid` produces a flipbook atlas.
+/// The frame layout is written alongside as `<output>.sequence.json`
+fn process_sequence(
+    frames: &Vec<PathBuf>,
+    dest: &PathBuf,
+    config: &TextureConfigs,
+    timings: &mut StageTimings,
+) -> Result<(), String> {
+    let images = timings.time(ProcessingStage::Import, || {
+        frames
+            .iter()
+            .map(|frame| load_image(frame, config))
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+    let (bytes, info) = timings.time(ProcessingStage::Export, || {
+        let images = images
+            .into_iter()
+            .zip(frames)
+            .map(|(image, frame)| transform_image(image, frame, config).to_rgba8())
+            .collect::<Vec<_>>();
+        let (frame_width, frame_height) = images[0].dimensions();
+        if let Some(index) = images
+            .iter()
+            .position(|image| image.dimensions() != (frame_width, frame_height))
+        {
+            return Err(format!(
+                "Sequence frame {} does not match the size of the first frame",
+                frames[index].display()
+            ));
+        }
+        let frame_count = images.len() as u32;
+        let columns = match config.sequence_layout {
+            SequenceLayout::Strip => 1,
+            SequenceLayout::Grid => (frame_count as f64).sqrt().ceil() as u32,
+        };
+        let rows = frame_count.div_ceil(columns);
+        let mut combined = RgbaImage::new(frame_width * columns, frame_height * rows);
+        for (index, image) in images.iter().enumerate() {
+            let index = index as u32;
+            combined
+                .copy_from(
+                    image,
+                    frame_width * (index % columns),
+                    frame_height * (index / columns),
+                )
+                .map_err(|err| format!("Failed to combine sequence frames: {}", err))?;
+        }
+        let info = SequenceInfo {
+            frames: frame_count,
+            frame_width,
+            frame_height,
+            columns,
+            rows,
+            fps: config.sequence_fps,
+        };
+        Ok((
+            encode_image(&DynamicImage::ImageRgba8(combined), dest, config)?,
+            info,
+        ))
+    })?;
+    timings.time(ProcessingStage::Write, || {
+        write_output(dest, bytes).map_err(|err| format!("Failed to write image: {}", err))?;
+        let info_text = serde_json::to_string_pretty(&info)
+            .map_err(|err| format!("Failed to serialize sequence info: {}", err))?;
+        write_output(&get_sequence_info_path(dest), info_text)
+            .map_err(|err| format!("Failed to write sequence info: {}", err))
+    })
+}
+
+/// `walk.png` => `walk.png.sequence.json`
+fn get_sequence_info_path(dest: &PathBuf) -> PathBuf {
+    let mut info_path = dest.clone().into_os_string();
+    info_path.push(".sequence.json");
+    PathBuf::from(info_path)
+}
+
+/// Layout of a combined image sequence, written next to the combined output
+#[derive(Serialize, Debug)]
+struct SequenceInfo {
+    frames: u32,
+    frame_width: u32,
+    frame_height: u32,
+    /// Frames are laid out left to right, then top to bottom
+    columns: u32,
+    rows: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fps: Option<f32>,
+}
+
+/// Splits a file stem like `walk_03` into `("walk", 3)`
+fn get_sequence_frame(path: &PathBuf) -> Option<(String, u32)> {
+    let stem = path.file_stem()?.to_str()?;
+    let (name, index) = stem.rsplit_once('_')?;
+    if name.is_empty() || index.is_empty() || !index.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((name.to_string(), index.parse().ok()?))
+}
+
+/// Whether both files are frames of the same sequence, which is combined into one output
+pub fn is_same_sequence(a: &PathBuf, b: &PathBuf) -> bool {
+    a.parent() == b.parent()
+        && a.extension() == b.extension()
+        && get_sequence_frame(a).is_some_and(|(name, _)| {
+            get_sequence_frame(b).is_some_and(|(other_name, _)| name == other_name)
+        })
+}
+
+/// Frames in a directory grouped by sequence name and extension, each ordered by index
+type SequenceGroups = HashMap<(String, OsString), Vec<(u32, PathBuf)>>;
+
+/// Each directory's sequence groups with the directory's modification time when it was listed. Destinations are
+/// worked out for every texture on every scan, so listing the directory each time would be files x siblings
+static SEQUENCE_GROUPS: OnceLock<Mutex<HashMap<PathBuf, (SystemTime, SequenceGroups)>>> =
+    OnceLock::new();
+
+/// Finds every frame sharing the source's sequence name and extension, ordered by index.
+/// A lone numbered file isn't treated as a sequence
+fn get_sequence_frames(source: &PathBuf) -> Option<Vec<PathBuf>> {
+    let (name, _) = get_sequence_frame(source)?;
+    let ext = source.extension()?.to_os_string();
+    let dir = source.parent()?;
+    // adding, removing or renaming a frame updates the directory's modification time
+    let modified = fs::metadata(dir).and_then(|meta| meta.modified()).ok()?;
+    let mut groups = SEQUENCE_GROUPS
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if groups
+        .get(dir)
+        .map_or(true, |(listed, _)| *listed != modified)
+    {
+        groups.insert(dir.to_path_buf(), (modified, group_sequence_frames(dir)?));
+    }
+    let frames = groups.get(dir)?.1.get(&(name, ext))?;
+    (frames.len() >= 2).then(|| frames.iter().map(|(_, path)| path.clone()).collect())
+}
+
+fn group_sequence_frames(dir: &Path) -> Option<SequenceGroups> {
+    let mut groups = SequenceGroups::new();
+    for path in fs::read_dir(dir).ok()?.flatten().map(|entry| entry.path()) {
+        let (Some((name, index)), Some(ext)) = (get_sequence_frame(&path), path.extension()) else {
+            continue;
+        };
+        groups
+            .entry((name, ext.to_os_string()))
+            .or_default()
+            .push((index, path));
+    }
+    for frames in groups.values_mut() {
+        frames.sort_by_key(|(index, _)| *index);
+    }
+    Some(groups)
+}
+
+fn load_image(source: &PathBuf, config: &TextureConfigs) -> Result<DynamicImage, String> {
+    check_media_file(source)?;
+    let data = fs::read(source).map_err(|err| format!("Failed to read image: {}", err))?;
+    let open_reader = || {
+        ImageReader::new(Cursor::new(&data))
+            .with_guessed_format()
+            .map_err(|err| format!("Failed to read image: {}", err))
+    };
+    if let Some(max_pixels) = config.max_source_pixels {
+        // only reads the header, so huge images are rejected before allocating their pixels
+        let (width, height) = open_reader()?
+            .into_dimensions()
+            .map_err(|err| format!("Failed to read image dimensions: {}", err))?;
+        if width as u64 * height as u64 > max_pixels {
+            return Err(format!(
+                "Image is {}x{} which exceeds max_source_pixels ({})",
+                width, height, max_pixels
+            ));
+        }
+    }
+    open_reader()?
+        .decode()
+        .map_err(|err| format!("Failed to decode image: {}", err))
+}
+
+/// Applies the configured per-image transformations
+fn transform_image(
+    mut image: DynamicImage,
+    source: &PathBuf,
+    config: &TextureConfigs,
+) -> DynamicImage {
+    if config.flip_vertical {
+        // mirrors the pixel rows. Does not touch channel data, so normal maps keep their green channel as-is
+        image = image.flipv();
+    }
+    if let Some(sdf) = config.get_sdf(source) {
+        // replaces the colors entirely, so there's nothing left to convert to linear
+        image = generate_sdf(&image, sdf);
+    } else if config.should_bake_linear(source) {
+        image = srgb_to_linear(image);
+    }
+    if let Some(swizzle) = config.get_swizzle(source) {
+        image = swizzle_channels(image, swizzle);
+    }
+    if let Some((width, height)) = get_max_size(image.width(), image.height(), config) {
+        image = resize_image(image, width, height, config);
+    }
+    if config.border > 0 {
+        image = extrude_border(&image, config.border);
+    }
+    match config.force_pow2 {
+        Some(PowerOfTwoMode::Pad) => {
+            let mut canvas = RgbaImage::from_pixel(
+                image.width().next_power_of_two(),
+                image.height().next_power_of_two(),
+                Rgba(config.pow2_pad_color),
+            );
+            imageops::replace(&mut canvas, &image.to_rgba8(), 0, 0);
+            image = DynamicImage::ImageRgba8(canvas);
+        }
+        Some(PowerOfTwoMode::Scale) => {
+            image = resize_image(
+                image.clone(),
+                nearest_power_of_two(image.width()),
+                nearest_power_of_two(image.height()),
+                config,
+            );
+        }
+        None => {}
+    }
+    if config.alpha_bleed && image.color().has_alpha() {
+        image = bleed_alpha(image);
+    }
+    image
+}
+
+/// The size to shrink an image to so it fits `max_size`, if it's larger
+fn get_max_size(width: u32, height: u32, config: &TextureConfigs) -> Option<(u32, u32)> {
+    let largest = width.max(height);
+    let max_size = config.max_size.filter(|max_size| largest > *max_size)?;
+    let scale = max_size as f64 / largest as f64;
+    Some((
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    ))
+}
+
+/// The size the source ends up after [`transform_image`], read from its header without decoding it. For sequences
+/// this is the size of one frame
+fn get_output_size(source: &PathBuf, config: &TextureConfigs) -> Option<(u32, u32)> {
+    let (width, height) = ImageReader::open(source)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+    let (width, height) = match config.get_sdf(source) {
+        Some(sdf) => get_sdf_size(width, height, sdf),
+        None => (width, height),
+    };
+    let (width, height) = get_max_size(width, height, config).unwrap_or((width, height));
+    let (width, height) = (width + config.border * 2, height + config.border * 2);
+    Some(match config.force_pow2 {
+        Some(PowerOfTwoMode::Pad) => (width.next_power_of_two(), height.next_power_of_two()),
+        Some(PowerOfTwoMode::Scale) => (nearest_power_of_two(width), nearest_power_of_two(height)),
+        None => (width, height),
+    })
+}
+
+fn resize_image(
+    image: DynamicImage,
+    width: u32,
+    height: u32,
+    config: &TextureConfigs,
+) -> DynamicImage {
+    if config.tiling {
+        DynamicImage::ImageRgba8(resize_wrapping(&image.to_rgba8(), width, height))
+    } else {
+        image.resize_exact(width, height, FilterType::Lanczos3)
+    }
+}
+
+/// Lanczos3 resize that samples past each edge from the opposite edge, the way a repeating sampler would, so a
+/// seamless tile is still seamless after resizing. `imageops` clamps at the edges instead, which leaves seams
+fn resize_wrapping(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let (source_width, source_height) = image.dimensions();
+    let pixels = image
+        .pixels()
+        .map(|pixel| pixel.0.map(|channel| channel as f32))
+        .collect::<Vec<_>>();
+    // resample rows first, then columns, each pass wrapping around its own axis
+    let horizontal = resample_axis(&pixels, source_width, source_height, width, true);
+    let resized = resample_axis(&horizontal, width, source_height, height, false);
+    RgbaImage::from_fn(width, height, |x, y| {
+        Rgba(
+            resized[(y * width + x) as usize]
+                .map(|channel| channel.round().clamp(0.0, 255.0) as u8),
+        )
+    })
+}
+
+fn resample_axis(
+    pixels: &[[f32; 4]],
+    width: u32,
+    height: u32,
+    target: u32,
+    horizontal: bool,
+) -> Vec<[f32; 4]> {
+    let (source_len, other_len) = if horizontal {
+        (width, height)
+    } else {
+        (height, width)
+    };
+    let ratio = source_len as f32 / target as f32;
+    // widen the kernel when shrinking so every source pixel contributes
+    let scale = ratio.max(1.0);
+    let support = 3.0 * scale;
+    let (out_width, out_height) = if horizontal {
+        (target, height)
+    } else {
+        (width, target)
+    };
+    let mut output = vec![[0.0; 4]; (out_width * out_height) as usize];
+    for i in 0..target {
+        let center = (i as f32 + 0.5) * ratio - 0.5;
+        let start = (center - support).floor() as i64;
+        let end = (center + support).ceil() as i64;
+        let weights = (start..=end)
+            .map(|s| (s, lanczos3((s as f32 - center) / scale)))
+            .filter(|(_, weight)| *weight != 0.0)
+            .collect::<Vec<_>>();
+        let total: f32 = weights.iter().map(|(_, weight)| weight).sum();
+        for j in 0..other_len {
+            let mut sum = [0.0; 4];
+            for (s, weight) in &weights {
+                let wrapped = s.rem_euclid(source_len as i64) as u32;
+                let (x, y) = if horizontal {
+                    (wrapped, j)
+                } else {
+                    (j, wrapped)
+                };
+                let pixel = pixels[(y * width + x) as usize];
+                for c in 0..4 {
+                    sum[c] += pixel[c] * weight / total;
+                }
+            }
+            let (x, y) = if horizontal { (i, j) } else { (j, i) };
+            output[(y * out_width + x) as usize] = sum;
+        }
+    }
+    output
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= 3.0 {
+        return 0.0;
+    }
+    let pi_x = std::f32::consts::PI * x;
+    3.0 * pi_x.sin() * (pi_x / 3.0).sin() / (pi_x * pi_x)
+}
+
+/// 300 => 256, 200 => 256, 100 => 128
+fn nearest_power_of_two(value: u32) -> u32 {
+    let up = value.next_power_of_two();
+    let down = up / 2;
+    if down > 0 && value - down < up - value {
+        down
+    } else {
+        up
+    }
+}
+
+/// Surrounds the image with `border` pixels on every side, each repeating the nearest edge pixel
+fn extrude_border(image: &DynamicImage, border: u32) -> DynamicImage {
+    let source = image.to_rgba8();
+    let (width, height) = source.dimensions();
+    let extruded = RgbaImage::from_fn(width + border * 2, height + border * 2, |x, y| {
+        let x = x.saturating_sub(border).min(width - 1);
+        let y = y.saturating_sub(border).min(height - 1);
+        *source.get_pixel(x, y)
+    });
+    DynamicImage::ImageRgba8(extruded)
+}
+
+/// Fills the color of fully transparent pixels from their nearest visible neighbours, so linear filtering at cutout
+/// edges blends towards the sprite's own colors instead of black. Alpha is left untouched
+fn bleed_alpha(image: DynamicImage) -> DynamicImage {
+    let mut rgba = image.into_rgba8();
+    let (width, height) = rgba.dimensions();
+    let index = |x: u32, y: u32| (y * width + x) as usize;
+    let neighbours = move |x: u32, y: u32| {
+        (-1i64..=1)
+            .flat_map(move |dy| (-1i64..=1).map(move |dx| (x as i64 + dx, y as i64 + dy)))
+            .filter(move |&(nx, ny)| {
+                (nx, ny) != (x as i64, y as i64)
+                    && nx >= 0
+                    && ny >= 0
+                    && nx < width as i64
+                    && ny < height as i64
+            })
+            .map(|(nx, ny)| (nx as u32, ny as u32))
+    };
+
+    let mut filled = rgba
+        .pixels()
+        .map(|pixel| pixel.0[3] > 0)
+        .collect::<Vec<_>>();
+    let mut queued = filled.clone();
+    let mut frontier = Vec::new();
+    for (x, y, _) in rgba.enumerate_pixels() {
+        if !filled[index(x, y)] && neighbours(x, y).any(|(nx, ny)| filled[index(nx, ny)]) {
+            queued[index(x, y)] = true;
+            frontier.push((x, y));
+        }
+    }
+    // grows outwards one ring at a time, so each pixel takes the average of the closest visible colors
+    while !frontier.is_empty() {
+        let colors = frontier
+            .iter()
+            .map(|&(x, y)| {
+                let (mut sum, mut count) = ([0u32; 3], 0);
+                for (nx, ny) in neighbours(x, y).filter(|&(nx, ny)| filled[index(nx, ny)]) {
+                    let pixel = rgba.get_pixel(nx, ny).0;
+                    for channel in 0..3 {
+                        sum[channel] += pixel[channel] as u32;
+                    }
+                    count += 1;
+                }
+                sum.map(|total| (total / count.max(1)) as u8)
+            })
+            .collect::<Vec<_>>();
+        let mut next = Vec::new();
+        for (&(x, y), color) in frontier.iter().zip(colors) {
+            let pixel = rgba.get_pixel_mut(x, y);
+            pixel.0[..3].copy_from_slice(&color);
+            filled[index(x, y)] = true;
+            for (nx, ny) in neighbours(x, y) {
+                if !queued[index(nx, ny)] {
+                    queued[index(nx, ny)] = true;
+                    next.push((nx, ny));
+                }
+            }
+        }
+        frontier = next;
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Maps each pixel's signed distance to the mask's edge into 0..1 with the edge at 0.5, then downscales by `scale`
+pub fn generate_sdf(image: &DynamicImage, config: &SdfConfigs) -> DynamicImage {
+    let use_alpha = match config.channel {
+        SdfChannel::Alpha => true,
+        SdfChannel::Luminance => false,
+        SdfChannel::Auto => {
+            image.color().has_alpha() && image.to_rgba8().pixels().any(|pixel| pixel[3] < 255)
+        }
+    };
+    let mask = if use_alpha {
+        image
+            .to_rgba8()
+            .pixels()
+            .map(|pixel| pixel[3] as f32 / 255.0 >= config.threshold)
+            .collect::<Vec<_>>()
+    } else {
+        image
+            .to_luma8()
+            .pixels()
+            .map(|pixel| pixel[0] as f32 / 255.0 >= config.threshold)
+            .collect::<Vec<_>>()
+    };
+    let (width, height) = (image.width(), image.height());
+    let to_inside = distance_transform(&mask, width, height, true);
+    let to_outside = distance_transform(&mask, width, height, false);
+    let field = RgbaImage::from_fn(width, height, |x, y| {
+        let index = (y * width + x) as usize;
+        // distances are between pixel centers, so the edge sits half a pixel from either side
+        let distance = if mask[index] {
+            0.5 - to_outside[index].sqrt()
+        } else {
+            to_inside[index].sqrt() - 0.5
+        };
+        let value = (0.5 - distance / (2.0 * config.spread)).clamp(0.0, 1.0);
+        let value = (value * 255.0).round() as u8;
+        Rgba([value, value, value, 255])
+    });
+    let (out_width, out_height) = get_sdf_size(width, height, config);
+    let field = DynamicImage::ImageRgba8(field);
+    if (out_width, out_height) == (width, height) {
+        field
+    } else {
+        field.resize_exact(out_width, out_height, FilterType::Triangle)
+    }
+}
+
+fn get_sdf_size(width: u32, height: u32, config: &SdfConfigs) -> (u32, u32) {
+    (
+        ((width as f32 * config.scale).round() as u32).max(1),
+        ((height as f32 * config.scale).round() as u32).max(1),
+    )
+}
+
+/// Squared distance from each pixel to the nearest pixel whose mask equals `target`, by transforming the columns
+/// and then the rows
+fn distance_transform(mask: &[bool], width: u32, height: u32, target: bool) -> Vec<f32> {
+    // large rather than infinite, so the parabola intersections stay finite
+    const FAR: f32 = 1e20;
+    let (width, height) = (width as usize, height as usize);
+    let mut distances = mask
+        .iter()
+        .map(|value| if *value == target { 0.0 } else { FAR })
+        .collect::<Vec<_>>();
+    for x in 0..width {
+        let column = (0..height)
+            .map(|y| distances[y * width + x])
+            .collect::<Vec<_>>();
+        for (y, distance) in distance_transform_1d(&column).into_iter().enumerate() {
+            distances[y * width + x] = distance;
+        }
+    }
+    for row in distances.chunks_mut(width) {
+        let transformed = distance_transform_1d(row);
+        row.copy_from_slice(&transformed);
+    }
+    distances
+}
+
+/// Felzenszwalb and Huttenlocher's 1D squared distance transform, the lower envelope of a parabola rooted at
+/// each sample
+fn distance_transform_1d(samples: &[f32]) -> Vec<f32> {
+    let len = samples.len();
+    let mut roots = vec![0usize; len];
+    let mut bounds = vec![0.0f32; len + 1];
+    bounds[0] = f32::NEG_INFINITY;
+    bounds[1] = f32::INFINITY;
+    let mut k = 0;
+    let intersect = |q: usize, p: usize| {
+        let (qf, pf) = (q as f32, p as f32);
+        ((samples[q] + qf * qf) - (samples[p] + pf * pf)) / (2.0 * (qf - pf))
+    };
+    for q in 1..len {
+        let mut s = intersect(q, roots[k]);
+        while s <= bounds[k] {
+            k -= 1;
+            s = intersect(q, roots[k]);
+        }
+        k += 1;
+        roots[k] = q;
+        bounds[k] = s;
+        bounds[k + 1] = f32::INFINITY;
+    }
+    k = 0;
+    (0..len)
+        .map(|q| {
+            while bounds[k + 1] < q as f32 {
+                k += 1;
+            }
+            let offset = q as f32 - roots[k] as f32;
+            offset * offset + samples[roots[k]]
+        })
+        .collect()
+}
+
+/// Converts the color channels from the sRGB transfer function to linear values. Alpha is already linear
+fn srgb_to_linear(image: DynamicImage) -> DynamicImage {
+    let table: [u8; 256] = std::array::from_fn(|value| {
+        let srgb = value as f32 / 255.0;
+        let linear = if srgb <= 0.04045 {
+            srgb / 12.92
+        } else {
+            ((srgb + 0.055) / 1.055).powf(2.4)
+        };
+        (linear * 255.0).round() as u8
+    });
+    let mut rgba = image.into_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = table[*channel as usize];
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Rebuilds each pixel from the channels named in `swizzle`. The swizzle is validated at config load
+fn swizzle_channels(image: DynamicImage, swizzle: &str) -> DynamicImage {
+    let channels = swizzle
+        .chars()
+        .map(|c| "rgba".find(c).unwrap_or_default())
+        .collect::<Vec<_>>();
+    let mut rgba = image.into_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let source = pixel.0;
+        for (target, channel) in channels.iter().enumerate() {
+            pixel.0[target] = source[*channel];
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Encodes the image in the format matching the destination extension
+fn encode_image(
+    image: &DynamicImage,
+    dest: &PathBuf,
+    config: &TextureConfigs,
+) -> Result<Vec<u8>, String> {
+    if dest.extension().is_some_and(|ext| ext == "ktx2") {
+        let encoding = config.ktx2.as_ref().ok_or("No ktx2 encoding configured")?;
+        return encode_ktx2(image, encoding, config.is_srgb_output(dest), config);
+    }
+    let format = ImageFormat::from_path(dest)
+        .map_err(|err| format!("Unsupported output format: {}", err))?;
+    // jpeg has no alpha channel, so transparent or 16 bit sources get flattened when outputs are normalized to jpg
+    let flattened;
+    let image = if format == ImageFormat::Jpeg
+        && !matches!(image.color(), ColorType::Rgb8 | ColorType::L8)
+    {
+        flattened = DynamicImage::ImageRgb8(image.to_rgb8());
+        &flattened
+    } else {
+        image
+    };
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), format)
+        .map_err(|err| format!("Failed to encode image: {}", err))?;
+    Ok(bytes)
+}
+
+/// Encodes the image as a single level Basis Universal `.ktx2`
+fn encode_ktx2(
+    image: &DynamicImage,
+    encoding: &Ktx2Encoding,
+    is_srgb: bool,
+    config: &TextureConfigs,
+) -> Result<Vec<u8>, String> {
+    let rgba = image.to_rgba8();
+    let mut texture = Texture::new(Ktx2CreateInfo {
+        vk_format: if is_srgb {
+            VK_FORMAT_R8G8B8A8_SRGB
+        } else {
+            VK_FORMAT_R8G8B8A8_UNORM
+        },
+        base_width: rgba.width(),
+        base_height: rgba.height(),
+        base_depth: 1,
+        num_dimensions: 2,
+        num_levels: 1,
+        num_layers: 1,
+        num_faces: 1,
+        is_array: false,
+        generate_mipmaps: false,
+        ..Default::default()
+    })
+    .map_err(|err| format!("Failed to create ktx2 texture: {:?}", err))?;
+    let offset = texture
+        .get_image_offset(0, 0, 0)
+        .map_err(|err| format!("Failed to create ktx2 texture: {:?}", err))?;
+    texture.data_mut()[offset..offset + rgba.len()].copy_from_slice(&rgba);
+
+    let mut params = sys::ktxBasisParams {
+        structSize: std::mem::size_of::<sys::ktxBasisParams>() as u32,
+        threadCount: 1,
+        ..Default::default()
+    };
+    match encoding {
+        Ktx2Encoding::Uastc => {
+            params.uastc = true;
+            params.uastcFlags = config.uastc_quality;
+        }
+        Ktx2Encoding::Etc1s => params.qualityLevel = config.etc1s_quality,
+    }
+    // SAFETY: the handle belongs to a ktx2 texture created above and outlives the call
+    let code = unsafe {
+        sys::ktxTexture2_CompressBasisEx(texture.handle() as *mut sys::ktxTexture2, &mut params)
+    };
+    if code != sys::ktx_error_code_e_KTX_SUCCESS {
+        return Err(format!(
+            "Failed to compress ktx2 texture: error code {}",
+            code
+        ));
+    }
+
+    let mut bytes = Vec::new();
+    texture
+        .write_to(&mut StreamSink::new(&mut Cursor::new(&mut bytes)))
+        .map_err(|err| format!("Failed to encode ktx2 texture: {:?}", err))?;
+    Ok(bytes)
+}
+
+/// Decodes the written output to make sure it isn't corrupted. Removes the output if it is so it gets reprocessed
+fn verify_texture(dest: &PathBuf) -> Result<(), String> {
+    if dest.extension().is_some_and(|ext| ext == "ktx2") {
+        // the image crate can't read ktx2, and libktx already validated it while encoding
+        return Ok(());
+    }
+    let Err(err) = image::open(dest) else {
+        return Ok(());
+    };
+    let _ = fs::remove_file(dest);
+    Err(format!("Output failed verification: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    fn get_pow2_config(mode: PowerOfTwoMode) -> TextureConfigs {
+        let mut config = Config::default().textures;
+        config.force_pow2 = Some(mode);
+        config.pow2_pad_color = BLUE.0;
+        config
+    }
+
+    #[test]
+    fn rounds_to_nearest_power_of_two() {
+        assert_eq!(nearest_power_of_two(300), 256);
+        assert_eq!(nearest_power_of_two(200), 256);
+        assert_eq!(nearest_power_of_two(100), 128);
+        assert_eq!(nearest_power_of_two(256), 256);
+        assert_eq!(nearest_power_of_two(1), 1);
+        // halfway between rounds up
+        assert_eq!(nearest_power_of_two(384), 512);
+    }
+
+    #[test]
+    fn pads_to_next_power_of_two() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(300, 200, RED));
+        let config = get_pow2_config(PowerOfTwoMode::Pad);
+        let padded = transform_image(image, &PathBuf::from("logo.png"), &config).to_rgba8();
+        assert_eq!(padded.dimensions(), (512, 256));
+        // the image stays in the top left corner, with the fill color everywhere else
+        assert_eq!(*padded.get_pixel(0, 0), RED);
+        assert_eq!(*padded.get_pixel(299, 199), RED);
+        assert_eq!(*padded.get_pixel(300, 0), BLUE);
+        assert_eq!(*padded.get_pixel(0, 200), BLUE);
+        assert_eq!(*padded.get_pixel(511, 255), BLUE);
+    }
+
+    #[test]
+    fn scales_to_nearest_power_of_two() {
+        for tiling in [false, true] {
+            let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(300, 200, RED));
+            let mut config = get_pow2_config(PowerOfTwoMode::Scale);
+            config.tiling = tiling;
+            let scaled = transform_image(image, &PathBuf::from("logo.png"), &config).to_rgba8();
+            assert_eq!(scaled.dimensions(), (256, 256));
+            // a flat color stays flat, with no fill color bleeding in
+            for pixel in [(0, 0), (128, 128), (255, 255)] {
+                let Rgba([r, g, b, a]) = *scaled.get_pixel(pixel.0, pixel.1);
+                assert!(r >= 254 && g <= 1 && b <= 1 && a >= 254, "{:?}", (r, g, b, a));
+            }
+        }
+    }
+
+    #[test]
+    fn swizzles_channels() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([10, 20, 30, 40])));
+        let swapped = swizzle_channels(image.clone(), "bgra").to_rgba8();
+        assert_eq!(swapped.get_pixel(0, 0).0, [30, 20, 10, 40]);
+        let packed = swizzle_channels(image, "rrrg").to_rgba8();
+        assert_eq!(packed.get_pixel(0, 0).0, [10, 10, 10, 20]);
+    }
+
+    #[test]
+    fn bleeds_nearest_colors_into_transparent_pixels() {
+        let mut image = RgbaImage::from_pixel(5, 1, CLEAR);
+        image.put_pixel(0, 0, RED);
+        image.put_pixel(2, 0, BLUE);
+        let bled = bleed_alpha(DynamicImage::ImageRgba8(image)).to_rgba8();
+        // between two visible pixels takes their average, further out takes whatever the previous ring got
+        assert_eq!(bled.get_pixel(1, 0).0, [127, 0, 127, 0]);
+        assert_eq!(bled.get_pixel(3, 0).0, [0, 0, 255, 0]);
+        assert_eq!(bled.get_pixel(4, 0).0, [0, 0, 255, 0]);
+        // visible pixels are untouched
+        assert_eq!(*bled.get_pixel(0, 0), RED);
+        assert_eq!(*bled.get_pixel(2, 0), BLUE);
+    }
+
+    #[test]
+    fn measures_squared_distances() {
+        let mask = [false, false, true, false, false];
+        assert_eq!(
+            distance_transform(&mask, 5, 1, true),
+            [4.0, 1.0, 0.0, 1.0, 4.0]
+        );
+        assert_eq!(
+            distance_transform(&mask, 5, 1, false),
+            [0.0, 0.0, 1.0, 0.0, 0.0]
+        );
+        let mut mask = [false; 9];
+        mask[0] = true;
+        let distances = distance_transform(&mask, 3, 3, true);
+        assert_eq!(distances[4], 2.0);
+        assert_eq!(distances[8], 8.0);
+    }
+
+    #[test]
+    fn generates_sdf_with_the_edge_at_half() {
+        // the left half is inside the shape
+        let image = RgbaImage::from_fn(8, 8, |x, _| if x < 4 { RED } else { CLEAR });
+        let mut config = SdfConfigs {
+            spread: 2.0,
+            scale: 1.0,
+            channel: SdfChannel::Alpha,
+            threshold: 0.5,
+        };
+        let field = generate_sdf(&DynamicImage::ImageRgba8(image.clone()), &config).to_rgba8();
+        assert_eq!(field.dimensions(), (8, 8));
+        let values = (0..8).map(|x| field.get_pixel(x, 4)[0]).collect::<Vec<_>>();
+        // half a pixel either side of the edge, then clamped once past `spread`
+        assert_eq!(values[3], 159);
+        assert_eq!(values[4], 96);
+        assert_eq!(values[0], 255);
+        assert_eq!(values[7], 0);
+        assert!(values.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(field.pixels().all(|pixel| pixel[3] == 255));
+
+        config.scale = 0.5;
+        let field = generate_sdf(&DynamicImage::ImageRgba8(image), &config);
+        assert_eq!(field.dimensions(), (4, 4));
+    }
+
+    #[test]
+    fn resamples_across_the_wrapped_edge() {
+        // a single bright pixel at the start of a repeating row
+        let mut row = vec![[0.0; 4]; 4];
+        row[0] = [255.0; 4];
+        let horizontal = resample_axis(&row, 4, 1, 8, true);
+        let vertical = resample_axis(&row, 1, 4, 8, false);
+        assert_eq!(horizontal.len(), 8);
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        for (a, b) in horizontal.iter().zip(&vertical) {
+            assert!(close(a[0], b[0]));
+        }
+        // the row repeats, so the bright pixel is mirrored around the seam instead of cut off by it
+        assert!(close(horizontal[0][0], horizontal[1][0]));
+        assert!(close(horizontal[7][0], horizontal[2][0]));
+        assert!(horizontal[7][0] > 1.0);
+
+        // a flat row stays flat, including at the edges
+        let flat = resample_axis(&[[100.0; 4]; 4], 4, 1, 3, true);
+        assert!(flat.iter().all(|pixel| close(pixel[0], 100.0)));
+    }
+
+    #[test]
+    fn extrudes_edge_pixels_into_the_border() {
+        let corners = [RED, BLUE, Rgba([0, 255, 0, 255]), Rgba([255, 255, 255, 255])];
+        let image = RgbaImage::from_fn(2, 2, |x, y| corners[(y * 2 + x) as usize]);
+        let extruded = extrude_border(&DynamicImage::ImageRgba8(image), 1).to_rgba8();
+        assert_eq!(extruded.dimensions(), (4, 4));
+        for (x, y, corner) in [
+            (0, 0, 0),
+            (1, 0, 0),
+            (0, 1, 0),
+            (1, 1, 0),
+            (3, 0, 1),
+            (2, 0, 1),
+            (0, 3, 2),
+            (0, 2, 2),
+            (3, 3, 3),
+            (2, 2, 3),
+        ] {
+            assert_eq!(*extruded.get_pixel(x, y), corners[corner], "({}, {})", x, y);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+/// Version info for bug reports, covering the dependencies that decide how assets get read and written along with
+/// what bpm was built for
+pub fn get_detailed_version() -> String {
+    [
+        format!("bpm {}", env!("CARGO_PKG_VERSION")),
+        format!("bevy {}", env!("BPM_BEVY_VERSION")),
+        format!("gltf_kun {}", env!("BPM_GLTF_KUN_VERSION")),
+        format!("image {}", env!("BPM_IMAGE_VERSION")),
+        format!("libktx-rs {}", env!("BPM_LIBKTX_VERSION")),
+        format!("target {}", env!("BPM_BUILD_TARGET")),
+        format!("profile {}", env!("BPM_BUILD_PROFILE")),
+    ]
+    .join("\n")
+}